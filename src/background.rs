@@ -1,151 +1,403 @@
-use std::{f32::consts::PI, fs::File, io::BufReader, sync::Arc};
+use std::{f32::consts::PI, fs::File, io::BufReader, path::Path, sync::Arc};
 
 use image::{codecs::hdr::HdrDecoder, Rgb};
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
 //use rayon::iter::{IntoParallelIterator, IndexedParallelIterator, ParallelIterator};
 //use rayon::slice::ParallelSliceMut;
 
 use crate::{
     object::Object,
-    ray::Ray,
+    ray::{Ray, RayType},
     sphere::Sphere,
     texture::Texture,
-    utilities::{math::Point2D, vector3::Vector3},
+    utilities::{math::Point2D, onb::ONB, vector3::Vector3},
 };
 
+//Backgrounds seen by each kind of ray, so a scene can be lit by one HDRI and show another
+//backplate or a solid color behind the subject
+pub struct Environment {
+    pub camera: Background,
+    pub reflection: Background,
+    pub refraction: Background,
+    pub lighting: Background,
+}
+
+impl Environment {
+    pub fn new(background: Background) -> Self {
+        Self {
+            camera: background.clone(),
+            reflection: background.clone(),
+            refraction: background.clone(),
+            lighting: background,
+        }
+    }
+    pub fn with_camera(mut self, background: Background) -> Self {
+        self.camera = background;
+        self
+    }
+    //Background seen in specular reflections
+    #[allow(dead_code)]
+    pub fn with_reflection(mut self, background: Background) -> Self {
+        self.reflection = background;
+        self
+    }
+    //Background seen through specular refractions
+    pub fn with_refraction(mut self, background: Background) -> Self {
+        self.refraction = background;
+        self
+    }
+    //Background that lights the scene, seen by diffuse bounces and light sampling
+    pub fn with_lighting(mut self, background: Background) -> Self {
+        self.lighting = background;
+        self
+    }
+    pub fn value(&self, r: &Ray, ray_type: RayType) -> Vector3<f32> {
+        match ray_type {
+            RayType::Camera => self.camera.value(r),
+            RayType::Reflection => self.reflection.value(r),
+            RayType::Refraction => self.refraction.value(r),
+            RayType::Diffuse => self.lighting.value(r),
+        }
+    }
+}
+
+impl From<Background> for Environment {
+    fn from(background: Background) -> Self {
+        Self::new(background)
+    }
+}
+
+#[derive(Clone)]
 pub enum Background {
-    Plain { color: Vector3<f32> },
-    HDRI { texture: Texture },
+    Plain {
+        color: Vector3<f32>,
+    },
+    HDRI {
+        env_map: EnviromentalMap,
+        sun: Option<Sun>,
+        dome: Option<Dome>,
+    },
 }
 
 impl Background {
     pub fn new_plain(color: Vector3<f32>) -> Self {
         Self::Plain { color }
     }
-    pub fn new_hdri(texture: Texture) -> Self {
-        Self::HDRI { texture }
+    pub fn new_hdri(env_map: EnviromentalMap, sun: Option<Sun>) -> Self {
+        Self::HDRI {
+            env_map,
+            sun,
+            dome: None,
+        }
+    }
+    //Projects the HDRI onto a ground disk and a hemisphere of the given radius, captured at
+    //height above center, so objects appear to sit on the photographed ground.
+    //Meant for camera backgrounds, lighting still treats the map as infinitely far away
+    pub fn with_dome(self, center: Vector3<f32>, height: f32, radius: f32) -> Self {
+        match self {
+            Self::HDRI { env_map, sun, .. } => Self::HDRI {
+                env_map,
+                sun,
+                dome: Some(Dome {
+                    center,
+                    height,
+                    radius,
+                }),
+            },
+            plain => plain,
+        }
     }
     pub fn value(&self, r: &Ray) -> Vector3<f32> {
         match self {
             Self::Plain { color } => *color,
-            Self::HDRI { texture } => {
-                let (u, v) = Sphere::get_sphere_uv(&r.direction.norm());
-                texture.value(u, v, r.direction)
+            Self::HDRI { env_map, sun, dome } => {
+                let sky = match dome {
+                    Some(dome) => env_map.value(dome.project(r)),
+                    None => env_map.value(r.direction),
+                };
+                match sun {
+                    Some(sun) => sky + sun.value(r.direction),
+                    None => sky,
+                }
             }
         }
     }
 }
 
-pub fn load_hdri(path: &str, angle: f32) -> (Object, Texture) {
-    #[inline(always)]
-    fn shift_index(index: usize, shift: usize, width: usize) -> usize {
-        let j = index / width;
-        let i = (index + shift) % width;
-        j * width + i
+#[derive(Clone)]
+pub struct Dome {
+    center: Vector3<f32>,
+    height: f32,
+    radius: f32,
+}
+
+impl Dome {
+    //Direction from the capture point to where the ray leaves the dome
+    fn project(&self, r: &Ray) -> Vector3<f32> {
+        let d = r.direction.norm();
+        let o = r.origin - self.center;
+        let capture = Vector3::new(0.0, self.height, 0.0);
+
+        if d.y < 0.0 {
+            let t = -o.y / d.y;
+            let p = o + d * t;
+            if t > 0.0 && p.x * p.x + p.z * p.z <= self.radius * self.radius {
+                return p - capture;
+            }
+        }
+
+        let half_b = Vector3::dot(o, d);
+        let c = o.magnitude2() - self.radius * self.radius;
+        let discriminant = half_b * half_b - c;
+        if discriminant < 0.0 {
+            return d;
+        }
+        let t = -half_b + discriminant.sqrt();
+        if t < 0.0 {
+            return d;
+        }
+        o + d * t - capture
+    }
+}
+
+//Loads a Radiance .hdr or OpenEXR .exr environment map. The rotation is given as Euler angles
+//in degrees (applied around y, then x, then z) and is applied to directions, not pixels.
+//If extract_sun is set, the sun is cut out of the map and returned as a separate light
+pub fn load_hdri(
+    path: &str,
+    rotation: Vector3<f32>,
+    extract_sun: bool,
+) -> (Vec<Object>, Background) {
+    let (mut image_v, im_width, im_height) = read_hdri(path);
+    let rotation = ONB::from_euler(rotation);
+
+    let sun = if extract_sun {
+        Sun::extract(&mut image_v, im_width as usize, im_height as usize).map(|sun| {
+            Sun::new(
+                rotation.local(sun.direction),
+                sun.cos_theta_max,
+                sun.radiance,
+            )
+        })
+    } else {
+        None
+    };
+
+    let env_map = EnviromentalMap::new(
+        Arc::new(image_v),
+        im_width as f32,
+        im_height as f32,
+        rotation,
+    );
+    let mut lights = vec![Object::EnviromentalMap(env_map.clone())];
+    if let Some(sun) = &sun {
+        lights.push(Object::Sun(sun.clone()));
+    }
+
+    (lights, Background::new_hdri(env_map, sun))
+}
+
+fn read_hdri(path: &str) -> (Vec<Rgb<f32>>, u32, u32) {
+    let is_exr = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+
+    if is_exr {
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to read image from {:?}: {}", path, e))
+            .unwrap()
+            .into_rgb32f();
+        let (width, height) = image.dimensions();
+        (image.pixels().copied().collect(), width, height)
+    } else {
+        let image = File::open(path).unwrap();
+
+        let bufreader = BufReader::new(image);
+        let hdrdecoder = HdrDecoder::new(bufreader).unwrap();
+        let width = hdrdecoder.metadata().width;
+        let height = hdrdecoder.metadata().height;
+
+        (hdrdecoder.read_image_hdr().unwrap(), width, height)
+    }
+}
+
+//Pixel (i,j) of an equirectangular map to the direction used by Sphere::get_sphere_uv
+fn pixel_direction(i: usize, j: usize, width: usize, height: usize) -> Vector3<f32> {
+    let theta = PI * (j as f32 + 0.5) / height as f32;
+    let alpha = 2.0 * PI * (i as f32 + 0.5) / width as f32 - PI;
+    Vector3::new(
+        theta.sin() * alpha.cos(),
+        theta.cos(),
+        -theta.sin() * alpha.sin(),
+    )
+}
+
+//Distant disk light, the sun cut out of an HDRI
+#[derive(Clone)]
+pub struct Sun {
+    pub direction: Vector3<f32>,
+    pub cos_theta_max: f32,
+    pub radiance: Vector3<f32>,
+}
+
+impl Sun {
+    //Pixels brighter than this fraction of the peak belong to the sun
+    const THRESHOLD: f32 = 0.05;
+    //The peak must stand this far above the average to count as a sun
+    const MIN_CONTRAST: f32 = 50.0;
+    //Regions bigger than this solid angle are bright sky, not a sun
+    const MAX_SOLID_ANGLE: f32 = 0.05;
+
+    pub fn new(direction: Vector3<f32>, cos_theta_max: f32, radiance: Vector3<f32>) -> Self {
+        Self {
+            direction: direction.norm(),
+            cos_theta_max,
+            radiance,
+        }
     }
 
-    fn offset_image(image: &Vec<Rgb<f32>>, shift: usize, width: usize) -> Vec<Rgb<f32>> {
-        let mut offset_image = image.clone();
-        offset_image
-            .iter_mut()
+    //Finds the small high energy region around the brightest pixel, in-paints it with the
+    //surrounding sky and returns a disk light with the same power, direction and solid angle
+    pub fn extract(image_v: &mut [Rgb<f32>], width: usize, height: usize) -> Option<Self> {
+        let d_phi_theta = 2.0 * PI * PI / (width * height) as f32;
+        let solid_angle = |j: usize| d_phi_theta * (PI * (j as f32 + 0.5) / height as f32).sin();
+
+        let (peak, peak_luminance) = image_v
+            .iter()
             .enumerate()
-            .for_each(|(index, pixel)| *pixel = image[shift_index(index, shift, width)]);
-        offset_image
-    }
-
-    /* fn image_copy(
-        dst: &mut Vec<Rgb<f32>>,
-        copy_x: usize,
-        copy_y: usize,
-        copy_w: usize,
-        copy_h: usize,
-        source: &Vec<Rgb<f32>>,
-        source_x: usize,
-        source_y: usize,
-        source_w: usize,
-        source_h: usize,
-        sstride: usize,
-    ) {
-        let dstride = copy_w;
-
-        // compute clamped dst rect.
-        let x0 = copy_x.clamp(0, copy_w) as usize;
-        let y0 = copy_y.clamp(0, copy_h) as usize;
-        let x1 = (copy_x + source_w).clamp(0, copy_w) as usize;
-        let y1 = (copy_y + source_h).clamp(0, copy_h) as usize;
-
-        let copy_w = x1 - x0;
-        let copy_h = y1 - y0;
-        if copy_w == 0 || copy_h == 0 {
-            return;
+            .map(|(index, pixel)| (index, luminance(*pixel)))
+            .fold((0, 0.0), |acc, x| if x.1 > acc.1 { x } else { acc });
+        let mean =
+            image_v.iter().map(|pixel| luminance(*pixel)).sum::<f32>() / image_v.len() as f32;
+        if peak_luminance < Self::MIN_CONTRAST * mean {
+            return None;
+        }
+
+        //Flood fill from the peak, wrapping around horizontally
+        let threshold = peak_luminance * Self::THRESHOLD;
+        let mut inside = vec![false; width * height];
+        let mut stack = vec![peak];
+        inside[peak] = true;
+        let mut region = vec![];
+        while let Some(index) = stack.pop() {
+            region.push(index);
+            for neighbour in neighbours(index, width, height) {
+                if !inside[neighbour] && luminance(image_v[neighbour]) > threshold {
+                    inside[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        let mut power = Vector3::new(0.0, 0.0, 0.0);
+        let mut direction = Vector3::new(0.0, 0.0, 0.0);
+        let mut omega = 0.0;
+        for &index in region.iter() {
+            let (i, j) = (index % width, index / width);
+            let d_omega = solid_angle(j);
+            let pixel = image_v[index];
+            power += Vector3::new(pixel[0], pixel[1], pixel[2]) * d_omega;
+            direction += pixel_direction(i, j, width, height) * luminance(pixel) * d_omega;
+            omega += d_omega;
         }
+        if omega > Self::MAX_SOLID_ANGLE {
+            return None;
+        }
+
+        inpaint(image_v, &mut inside, width, height);
+
+        Some(Self::new(
+            direction,
+            1.0 - omega / (2.0 * PI),
+            power / omega,
+        ))
+    }
 
-        for y in 0..copy_h {
-            let d0 = (y0 + y) * dstride + x0;
-            let d1 = (y0 + y) * dstride + x0 + copy_w;
-            let s0 = (source_y + y) * sstride + source_x;
-            let s1 = (source_y + y) * sstride + source_x + copy_w;
-            dst[d0..d1].copy_from_slice(&source[s0..s1]);
+    #[inline(always)]
+    pub fn value(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        if Vector3::dot(direction.norm(), self.direction) >= self.cos_theta_max {
+            self.radiance
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
         }
     }
 
-    fn offset_image(src: &Vec<Rgb<f32>>, shift: usize, w: usize, h: usize) -> Vec<Rgb<f32>> {
-        let shift = shift % w;
-        if shift == 0 {
-            return src.clone();
+    pub fn pdf_value(&self, _: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        if Vector3::dot(v.norm(), self.direction) >= self.cos_theta_max {
+            1.0 / (2.0 * PI * (1.0 - self.cos_theta_max))
+        } else {
+            0.0
         }
+    }
 
-        let mut dst: Vec<Rgb<f32>> = Vec::with_capacity(w * h);
-        unsafe { dst.set_len(w * h) };
-
-        image_copy(&mut dst, shift, 0, w, h, &src, 0, 0, shift, h, w);
-
-        image_copy(&mut dst, w-shift, 0, w, h, &src, shift, 0, w - shift, h, w);
-
-        dst
-    }*/
-
-    let image = File::open(path).unwrap();
-
-    let bufreader = BufReader::new(image);
-    let hdrdecoder = HdrDecoder::new(bufreader).unwrap();
-    let im_width = hdrdecoder.metadata().width;
-    let im_height = hdrdecoder.metadata().height;
-
-    let image_v = hdrdecoder.read_image_hdr().unwrap();
-    let angle = (angle + 360.0) % 360.0;
-    let shift = (im_width as f32 * angle / 360.0) as usize;
-    //let start = Instant::now();
-    let image_v = Arc::new(offset_image(
-        &image_v,
-        shift,
-        im_width as usize,
-        //im_height as usize
-    ));
-    //let duration = start.elapsed();
-    //println!("Time elapsed shifting: {:?}", duration);
-
-    let env_map = Object::build_env_map(image_v.clone(), im_width as f32, im_height as f32);
-    (
-        env_map,
-        Texture::Hdri {
-            image_v,
-            width: im_width as f32,
-            height: im_height as f32,
-        },
-    )
+    pub fn random(&self, _: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
+        let r1 = rng.gen::<f32>();
+        let r2 = rng.gen::<f32>();
+        let z = 1.0 + r2 * (self.cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+        ONB::build_from(self.direction).local(Vector3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            z,
+        ))
+    }
+}
+
+//8-neighbourhood of a pixel, wrapping in u and clamping in v
+fn neighbours(index: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (i, j) = ((index % width) as i64, (index / width) as i64);
+    (-1..=1)
+        .flat_map(move |dj| (-1..=1).map(move |di| (di, dj)))
+        .filter(move |&(di, dj)| (di, dj) != (0, 0) && j + dj >= 0 && j + dj < height as i64)
+        .map(move |(di, dj)| {
+            let i = (i + di).rem_euclid(width as i64) as usize;
+            i + (j + dj) as usize * width
+        })
+}
+
+//Fills the masked pixels from the outside in with the mean of their already known neighbours
+fn inpaint(image_v: &mut [Rgb<f32>], mask: &mut [bool], width: usize, height: usize) {
+    let mut remaining: Vec<usize> = (0..mask.len()).filter(|&index| mask[index]).collect();
+    while !remaining.is_empty() {
+        let filled: Vec<(usize, Rgb<f32>)> = remaining
+            .iter()
+            .filter_map(|&index| {
+                let (sum, count) = neighbours(index, width, height)
+                    .filter(|&neighbour| !mask[neighbour])
+                    .fold(([0.0; 3], 0), |(sum, count), neighbour| {
+                        let pixel = image_v[neighbour];
+                        (
+                            [sum[0] + pixel[0], sum[1] + pixel[1], sum[2] + pixel[2]],
+                            count + 1,
+                        )
+                    });
+                (count > 0).then(|| {
+                    let count = count as f32;
+                    (index, Rgb([sum[0] / count, sum[1] / count, sum[2] / count]))
+                })
+            })
+            .collect();
+        if filled.is_empty() {
+            break;
+        }
+        for (index, pixel) in filled {
+            image_v[index] = pixel;
+            mask[index] = false;
+        }
+        remaining.retain(|&index| mask[index]);
+    }
 }
 
 #[derive(Clone)]
 pub struct EnviromentalMap {
-    pub image_v: Arc<Vec<Rgb<f32>>>,
-    pub width: f32,
-    pub height: f32,
+    pub texture: Texture,
+    pub rotation: ONB,
     pub distribution: Distribution2D,
 }
 
 impl EnviromentalMap {
-    pub fn new(image_v: Arc<Vec<Rgb<f32>>>, width: f32, height: f32) -> Self {
+    pub fn new(image_v: Arc<Vec<Rgb<f32>>>, width: f32, height: f32, rotation: ONB) -> Self {
         let mut f = Vec::with_capacity((width * height) as usize);
         for v in 0..height as usize {
             let sin_theta = (PI * (v as f32 + 0.5) / height).sin();
@@ -156,13 +408,22 @@ impl EnviromentalMap {
         }
         let distribution = Distribution2D::new(&f, width as usize, height as usize);
         Self {
-            image_v,
-            width,
-            height,
+            texture: Texture::Hdri {
+                image_v,
+                width,
+                height,
+            },
+            rotation,
             distribution,
         }
     }
-    pub fn random(&self, _: Vector3<f32>, rng: &mut ThreadRng) -> Vector3<f32> {
+
+    pub fn value(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let (u, v) = Sphere::get_sphere_uv(&self.rotation.to_local(direction.norm()));
+        self.texture.value(u, v, direction)
+    }
+
+    pub fn random(&self, _: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
         let u = rng.gen::<f32>();
         let v = rng.gen::<f32>();
         let (uv, pdf) = self.distribution.sample_continous(u, v);
@@ -181,11 +442,15 @@ impl EnviromentalMap {
         let sin_phi = phi.sin();
         let cos_phi = phi.cos();
 
-        Vector3::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi)
+        self.rotation.local(Vector3::new(
+            sin_theta * cos_phi,
+            cos_theta,
+            sin_theta * sin_phi,
+        ))
     }
 
     pub fn pdf_value(&self, _: Vector3<f32>, v: Vector3<f32>) -> f32 {
-        let v = v.norm();
+        let v = self.rotation.to_local(v.norm());
         let theta = (v.y).acos();
         let phi = (-v.z).atan2(v.x) + PI;
 
@@ -250,14 +515,14 @@ impl Distribution2D {
     }
 }
 
-struct Distribution1D {
+pub struct Distribution1D {
     f: Vec<f32>,
     cdf: Vec<f32>,
     f_integral: f32,
 }
 
 impl Distribution1D {
-    fn new(f: &[f32]) -> Self {
+    pub fn new(f: &[f32]) -> Self {
         let n = f.len();
         let cdf = (1..n + 1).fold(vec![0.0], |mut acc, i| {
             acc.push(acc[i - 1] + f[i - 1] / (n as f32));
@@ -280,7 +545,7 @@ impl Distribution1D {
     }
 
     //uses the given random sample u to sample from its distribution. It returns the corresponding value x and the value of the PDF
-    fn sample_continous(&self, u: f32) -> (f32, f32, usize) {
+    pub fn sample_continous(&self, u: f32) -> (f32, f32, usize) {
         let mut first = 0;
         let mut len = self.cdf.len();
 
@@ -311,11 +576,49 @@ impl Distribution1D {
         (pdf, (offset as f32 + du) / self.count() as f32, offset)
     }
 
+    //Density of sample_continous at x in [0, 1)
+    pub fn pdf(&self, x: f32) -> f32 {
+        if self.f_integral == 0.0 {
+            return 0.0;
+        }
+        let offset = ((x * self.count() as f32) as usize).min(self.count() - 1);
+        self.f[offset] / self.f_integral
+    }
+
     pub fn count(&self) -> usize {
         self.f.len()
     }
+
+    //Mean of the function
+    pub fn integral(&self) -> f32 {
+        self.f_integral
+    }
 }
 
 pub fn luminance(rgb: Rgb<f32>) -> f32 {
     0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_types_see_their_background() {
+        let plain = |x: f32| Background::new_plain(Vector3::new(x, x, x));
+        let environment = Environment::new(plain(0.0))
+            .with_camera(plain(1.0))
+            .with_reflection(plain(2.0))
+            .with_refraction(plain(3.0))
+            .with_lighting(plain(4.0));
+        let r = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        for (ray_type, x) in [
+            (RayType::Camera, 1.0),
+            (RayType::Reflection, 2.0),
+            (RayType::Refraction, 3.0),
+            (RayType::Diffuse, 4.0),
+        ] {
+            assert_eq!(environment.value(&r, ray_type), Vector3::new(x, x, x));
+        }
+    }
+}
@@ -0,0 +1,732 @@
+use std::sync::atomic::AtomicU32;
+
+use rand::Rng;
+
+use crate::{
+    background::Environment,
+    camera::Camera,
+    material::ScatterRecord,
+    medium::MediumStack,
+    object::{Hittable, Object},
+    ray::{HitRecord, Ray, RayType},
+    simd_bvh::SceneBVH,
+    utilities::{
+        math::{atomic_add, atomic_load, fmax},
+        onb::ONB,
+        vector3::Vector3,
+    },
+};
+
+const PI: f32 = std::f32::consts::PI;
+
+//Bidirectional path tracer [2]. Light subpaths start on the DiffuseLight emitters of the light
+//list and every prefix of them is connected to every prefix of the camera subpath, the
+//strategies are weighted with the balance heuristic. Environment lights are only reached by
+//camera subpaths escaping the scene, and light linking is ignored
+pub struct Bdpt<'a> {
+    bvh: &'a SceneBVH,
+    camera: &'a Camera,
+    background: &'a Environment,
+    emitters: Vec<(&'a Object, f32)>,
+    depth: usize,
+    camera_media: MediumStack<'a>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+    Medium,
+}
+
+#[derive(Clone, Copy)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Vector3<f32>,
+    normal: Vector3<f32>,
+    hit: Option<HitRecord<'a>>,
+    //Ray that reached the vertex, in the direction it was traced
+    r_in: Ray,
+    //Media the ray travelled through
+    media: MediumStack<'a>,
+    attenuation: Vector3<f32>,
+    beta: Vector3<f32>,
+    delta: bool,
+    //Whether the vertex has a non specular BSDF to connect through
+    scatters: bool,
+    //Scattering events of subsurface walks do not count towards the depth
+    walk_step: bool,
+    //Area densities of generating the vertex from its predecessor and from its successor
+    pdf_fwd: f32,
+    pdf_rev: f32,
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(r: &Ray) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            p: r.origin,
+            normal: r.direction.norm(),
+            hit: None,
+            r_in: *r,
+            media: MediumStack::default(),
+            attenuation: Vector3::new(1.0, 1.0, 1.0),
+            beta: Vector3::new(1.0, 1.0, 1.0),
+            delta: false,
+            scatters: false,
+            walk_step: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn light(p: Vector3<f32>, normal: Vector3<f32>, beta: Vector3<f32>, pdf_fwd: f32) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            p,
+            normal,
+            hit: None,
+            r_in: Ray::new(p, normal),
+            media: MediumStack::default(),
+            attenuation: Vector3::new(1.0, 1.0, 1.0),
+            beta,
+            delta: false,
+            scatters: false,
+            walk_step: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn surface(hit: HitRecord<'a>, r_in: Ray, media: MediumStack<'a>, beta: Vector3<f32>) -> Self {
+        let kind = if hit.material.is_medium() {
+            VertexKind::Medium
+        } else {
+            VertexKind::Surface
+        };
+        Self {
+            kind,
+            p: hit.p,
+            normal: hit.normal,
+            hit: Some(hit),
+            r_in,
+            media,
+            attenuation: Vector3::new(1.0, 1.0, 1.0),
+            beta,
+            delta: false,
+            scatters: false,
+            walk_step: media.walk_step(&hit),
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        matches!(self.kind, VertexKind::Surface | VertexKind::Light)
+    }
+
+    //Media a ray leaving the vertex towards direction travels through
+    fn media_towards(&self, direction: Vector3<f32>) -> MediumStack<'a> {
+        match &self.hit {
+            Some(hit) => self.media.towards(hit, direction),
+            None => self.media,
+        }
+    }
+
+    //BSDF times the cosine towards direction, for light travelling along r_in
+    fn f(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        match self.hit {
+            Some(hit) if self.scatters => {
+                let f = hit.material.eval_brdf(
+                    &self.r_in,
+                    &hit,
+                    self.attenuation,
+                    &Ray::new(self.p, direction),
+                );
+                if finite(f) {
+                    f
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                }
+            }
+            _ => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    //Solid angle density of scattering towards direction_out for a ray arriving along direction_in
+    fn pdf_solid(
+        &self,
+        direction_in: Vector3<f32>,
+        direction_out: Vector3<f32>,
+        rng: &mut impl Rng,
+    ) -> f32 {
+        let hit = match self.hit {
+            Some(hit) if self.scatters => hit,
+            _ => return 0.0,
+        };
+        match hit
+            .material
+            .scatter(&Ray::new(self.p - direction_in, direction_in), &hit, rng)
+        {
+            Some(ScatterRecord::Scatter {
+                pdf,
+                attenuation: _,
+            })
+            | Some(ScatterRecord::SpecularDiffuse {
+                pdf,
+                attenuation: _,
+            }) => {
+                let pdf = pdf.value(direction_out);
+                if pdf > 0.0 && pdf.is_finite() {
+                    pdf
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    //Density of emitting towards next, for light vertices and emitters hit by camera subpaths
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let direction = (next.p - self.p).norm();
+        let pdf = fmax(Vector3::dot(self.normal, direction), 0.0) / PI;
+        convert_density(pdf, self, next)
+    }
+
+    //Area density at next of sampling it from this vertex, reached from prev
+    fn pdf(
+        &self,
+        camera: &Camera,
+        prev: Option<&Vertex>,
+        next: &Vertex,
+        rng: &mut impl Rng,
+    ) -> f32 {
+        let direction = next.p - self.p;
+        let pdf = match (self.kind, prev) {
+            (VertexKind::Camera, _) => camera.pdf_direction(direction),
+            (VertexKind::Light, _) => return self.pdf_light(next),
+            (_, Some(prev)) => self.pdf_solid(self.p - prev.p, direction, rng),
+            (_, None) => 0.0,
+        };
+        convert_density(pdf, self, next)
+    }
+
+    fn emitted(&self) -> Vector3<f32> {
+        match self.hit {
+            Some(hit) => hit.material.emit(hit.u, hit.v, hit.p, hit.front_face),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+fn finite(v: Vector3<f32>) -> bool {
+    v.to_array().iter().all(|c| c.is_finite())
+}
+
+//Vertices of a subpath that count towards the depth, steps of subsurface walks are left out
+fn length(path: &[Vertex]) -> usize {
+    path.iter().filter(|vertex| !vertex.walk_step).count()
+}
+
+//Turns a solid angle density at from into an area density at to
+fn convert_density(pdf: f32, from: &Vertex, to: &Vertex) -> f32 {
+    let direction = to.p - from.p;
+    let distance_2 = direction.magnitude2();
+    if distance_2 == 0.0 {
+        return 0.0;
+    }
+    if to.on_surface() {
+        pdf * Vector3::dot(to.normal, direction.norm()).abs() / distance_2
+    } else {
+        pdf / distance_2
+    }
+}
+
+impl<'a> Bdpt<'a> {
+    pub fn new(
+        bvh: &'a SceneBVH,
+        camera: &'a Camera,
+        background: &'a Environment,
+        lights: &'a [Object],
+        depth: i32,
+    ) -> Self {
+        let emitters = lights
+            .iter()
+            .filter_map(|light| light.emitter_area().map(|area| (light, area)))
+            .collect();
+        Self {
+            bvh,
+            camera,
+            background,
+            emitters,
+            depth: depth.max(1) as usize,
+            camera_media: MediumStack::at(bvh, camera.origin),
+        }
+    }
+
+    //Radiance along the camera ray r. Paths with a single camera vertex land on other pixels and
+    //are splatted on the film
+    pub fn sample(&self, r: Ray, film: &SplatFilm, rng: &mut impl Rng) -> Vector3<f32> {
+        let mut camera_path = Vec::with_capacity(self.depth + 1);
+        camera_path.push(Vertex::camera(&r));
+        let mut color = self.random_walk(
+            r,
+            self.camera_media,
+            Vector3::new(1.0, 1.0, 1.0),
+            self.camera.pdf_direction(r.direction),
+            RayType::Camera,
+            &mut camera_path,
+            true,
+            rng,
+        );
+
+        let mut light_path = Vec::with_capacity(self.depth);
+        self.light_subpath(&mut light_path, rng);
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if (s == 1 && t == 1)
+                    || s + t < 2
+                    || length(&light_path[..s]) + length(&camera_path[..t]) > self.depth + 2
+                {
+                    continue;
+                }
+                if t == 1 {
+                    self.connect_to_camera(&light_path, s, film, rng);
+                } else {
+                    let l = self.connect(&light_path, &camera_path, s, t, rng);
+                    if finite(l) {
+                        color += l;
+                    }
+                }
+            }
+        }
+        color
+    }
+
+    fn light_subpath(&self, path: &mut Vec<Vertex<'a>>, rng: &mut impl Rng) {
+        if self.emitters.is_empty() {
+            return;
+        }
+        let (emitter, area) = self.emitters[rng.gen_range(0..self.emitters.len())];
+        let (p, normal) = emitter.sample_surface(rng);
+        let pdf_position = 1.0 / (self.emitters.len() as f32 * area);
+
+        let emitted = emitter.emitted(p, normal);
+        if emitted.max_axis() <= 0.0 {
+            return;
+        }
+
+        let direction = ONB::build_from(normal).local(Vector3::random_cosine_direction(rng));
+        let cosine = Vector3::dot(direction.norm(), normal);
+        let pdf_direction = cosine / PI;
+        if pdf_direction <= 0.0 {
+            return;
+        }
+
+        path.push(Vertex::light(
+            p,
+            normal,
+            emitted / pdf_position,
+            pdf_position,
+        ));
+        self.random_walk(
+            Ray::new(p, direction),
+            MediumStack::at(self.bvh, p),
+            emitted * cosine / (pdf_position * pdf_direction),
+            pdf_direction,
+            RayType::Diffuse,
+            path,
+            false,
+            rng,
+        );
+    }
+
+    //Extends path from its last vertex. For camera subpaths it returns the radiance of the
+    //background if the subpath escapes, and the light of glowing media along the way, which no
+    //other strategy samples
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        mut ray: Ray,
+        mut media: MediumStack<'a>,
+        mut beta: Vector3<f32>,
+        mut pdf_fwd: f32,
+        mut ray_type: RayType,
+        path: &mut Vec<Vertex<'a>>,
+        from_camera: bool,
+        rng: &mut impl Rng,
+    ) -> Vector3<f32> {
+        let max_vertices = if from_camera {
+            self.depth + 1
+        } else {
+            self.depth
+        };
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+        loop {
+            let vertices = length(path);
+            if vertices >= max_vertices {
+                break;
+            }
+            let (hit, weight, emitted) =
+                media.trace(self.bvh, &ray, f32::INFINITY, ray_type, &[], rng);
+            if from_camera {
+                radiance += beta * emitted;
+            }
+            beta = beta * weight;
+            let mut hit = match hit {
+                Some(hit) => hit,
+                None => {
+                    if from_camera {
+                        return radiance + beta * self.background.value(&ray, ray_type);
+                    }
+                    break;
+                }
+            };
+            hit.material = hit.material.resolve(rng);
+
+            let mut vertex = Vertex::surface(hit, ray, media, beta);
+            vertex.pdf_fwd = convert_density(pdf_fwd, path.last().unwrap(), &vertex);
+
+            let pdf_rev;
+            match hit.material.scatter(&ray, &hit, rng) {
+                None => {
+                    path.push(vertex);
+                    break;
+                }
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => {
+                    vertex.delta = true;
+                    beta = beta * attenuation;
+                    pdf_fwd = 0.0;
+                    pdf_rev = 0.0;
+                    ray_type = ray_type.after_specular(specular_ray.direction, hit.normal);
+                    ray = specular_ray;
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation })
+                | Some(ScatterRecord::SpecularDiffuse { pdf, attenuation }) => {
+                    let direction = pdf.sample(rng);
+                    pdf_fwd = pdf.value(direction);
+                    vertex.scatters = true;
+                    vertex.attenuation = attenuation;
+
+                    let f = vertex.f(direction);
+                    if !(pdf_fwd > 0.0 && pdf_fwd.is_finite()) || f.max_axis() <= 0.0 {
+                        path.push(vertex);
+                        break;
+                    }
+                    beta = beta * f / pdf_fwd;
+                    pdf_rev = vertex.pdf_solid(direction * (-1.0), ray.direction * (-1.0), rng);
+                    ray_type = RayType::Diffuse;
+                    ray = Ray::new(hit.p, direction);
+                }
+            }
+            media.cross(&hit, ray.direction);
+            path.push(vertex);
+            let n = path.len();
+            path[n - 2].pdf_rev = convert_density(pdf_rev, &path[n - 1], &path[n - 2]);
+
+            //Russian roulette
+            if vertices > 6 && !path[n - 1].walk_step {
+                let q = fmax(0.03, 1.0 - beta.max_axis());
+                if rng.gen::<f32>() < q {
+                    break;
+                }
+                beta /= 1.0 - q;
+            }
+        }
+        radiance
+    }
+
+    //Area density of starting a light subpath at the emitter point v, seen from from
+    fn pdf_light_origin(&self, v: &Vertex, from: &Vertex) -> f32 {
+        let r = Ray::new(from.p, v.p - from.p);
+        self.emitters
+            .iter()
+            .find(|(emitter, _)| emitter.hit(&r, 0.999, 1.001).is_some())
+            .map_or(0.0, |(_, area)| 1.0 / (self.emitters.len() as f32 * area))
+    }
+
+    //Fraction of the light getting from one vertex to the other, absorbed inside dielectrics.
+    //Scattering in a medium on the way blocks the connection like a surface would
+    fn transmittance(
+        &self,
+        from: &Vertex<'a>,
+        to: Vector3<f32>,
+        ray_type: RayType,
+        rng: &mut impl Rng,
+    ) -> Vector3<f32> {
+        let direction = to - from.p;
+        let distance = direction.magnitude();
+        let direction = direction / distance;
+        match from.media_towards(direction).trace(
+            self.bvh,
+            &Ray::new(from.p, direction),
+            distance - 0.001,
+            ray_type,
+            &[],
+            rng,
+        ) {
+            (None, weight, _) => weight,
+            _ => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    //Strategies with at least two camera vertices, their radiance goes to the current pixel
+    fn connect(
+        &self,
+        light_path: &[Vertex<'a>],
+        camera_path: &[Vertex<'a>],
+        s: usize,
+        t: usize,
+        rng: &mut impl Rng,
+    ) -> Vector3<f32> {
+        let pt = &camera_path[t - 1];
+        let mut sampled = None;
+        let l = if s == 0 {
+            pt.beta * pt.emitted()
+        } else if s == 1 {
+            if !pt.scatters || self.emitters.is_empty() {
+                return Vector3::new(0.0, 0.0, 0.0);
+            }
+            let (emitter, area) = self.emitters[rng.gen_range(0..self.emitters.len())];
+            let (p, normal) = emitter.sample_surface(rng);
+            let pdf_position = 1.0 / (self.emitters.len() as f32 * area);
+
+            let direction = pt.p - p;
+            let cosine = Vector3::dot(normal, direction.norm());
+            if cosine <= 0.0 {
+                return Vector3::new(0.0, 0.0, 0.0);
+            }
+            let emitted = emitter.emitted(p, normal);
+            let light = Vertex::light(p, normal, emitted / pdf_position, pdf_position);
+            sampled = Some(light);
+
+            let l =
+                pt.beta * pt.f(direction * (-1.0)) * light.beta * cosine / direction.magnitude2();
+            if l.max_axis() <= 0.0 {
+                return l;
+            }
+            l * self.transmittance(pt, p, RayType::Diffuse, rng)
+        } else {
+            let qs = &light_path[s - 1];
+            if !pt.scatters || !qs.scatters {
+                return Vector3::new(0.0, 0.0, 0.0);
+            }
+            let direction = pt.p - qs.p;
+            let l = qs.beta * qs.f(direction) * pt.f(direction * (-1.0)) * pt.beta
+                / direction.magnitude2();
+            if l.max_axis() <= 0.0 {
+                return l;
+            }
+            l * self.transmittance(qs, pt.p, RayType::Diffuse, rng)
+        };
+        if l.max_axis() <= 0.0 {
+            return l;
+        }
+        l * self.mis_weight(light_path, camera_path, sampled, s, t, rng)
+    }
+
+    //Strategies with a single camera vertex, the light subpath vertex is projected on the film
+    fn connect_to_camera(
+        &self,
+        light_path: &[Vertex<'a>],
+        s: usize,
+        film: &SplatFilm,
+        rng: &mut impl Rng,
+    ) {
+        let qs = &light_path[s - 1];
+        if !qs.scatters {
+            return;
+        }
+        let (lens, film_s, film_t, importance) = match self.camera.sample_lens(qs.p, rng) {
+            Some(sample) => sample,
+            None => return,
+        };
+        if film.pixel(film_s, film_t).is_none() {
+            return;
+        }
+        let l = qs.beta * qs.f(lens - qs.p) * importance;
+        if !finite(l) || l.max_axis() <= 0.0 {
+            return;
+        }
+        let l = l * self.transmittance(qs, lens, RayType::Camera, rng);
+        if l.max_axis() <= 0.0 {
+            return;
+        }
+        let sampled = Vertex::camera(&Ray::new(lens, qs.p - lens));
+        let weight = self.mis_weight(light_path, &[], Some(sampled), s, 1, rng);
+        film.add(film_s, film_t, l * weight);
+    }
+
+    //Balance heuristic weight of the strategy with s light and t camera vertices, computed from
+    //the ratios of the densities of generating the same path with the other strategies.
+    //sampled replaces the light vertex when s == 1 and the camera vertex when t == 1
+    fn mis_weight(
+        &self,
+        light_path: &[Vertex<'a>],
+        camera_path: &[Vertex<'a>],
+        sampled: Option<Vertex<'a>>,
+        s: usize,
+        t: usize,
+        rng: &mut impl Rng,
+    ) -> f32 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        let pt = if t == 1 {
+            sampled.unwrap()
+        } else {
+            camera_path[t - 1]
+        };
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(light_path[s - 1]),
+        };
+        let pt_minus = (t > 1).then(|| camera_path[t - 2]);
+        let qs_minus = (s > 1).then(|| light_path[s - 2]);
+
+        //(pdf_fwd, pdf_rev, delta) of every vertex with the densities of the connection
+        let mut camera: Vec<(f32, f32, bool)> = (0..t)
+            .map(|i| {
+                let v = if i == t - 1 { &pt } else { &camera_path[i] };
+                (v.pdf_fwd, v.pdf_rev, v.delta)
+            })
+            .collect();
+        let mut light: Vec<(f32, f32, bool)> = (0..s)
+            .map(|i| {
+                let v = if i == s - 1 {
+                    qs.as_ref().unwrap()
+                } else {
+                    &light_path[i]
+                };
+                (v.pdf_fwd, v.pdf_rev, v.delta)
+            })
+            .collect();
+
+        camera[t - 1].1 = match &qs {
+            Some(qs) => qs.pdf(self.camera, qs_minus.as_ref(), &pt, rng),
+            None => self.pdf_light_origin(&pt, pt_minus.as_ref().unwrap()),
+        };
+        camera[t - 1].2 = false;
+        if let Some(pt_minus) = &pt_minus {
+            camera[t - 2].1 = match &qs {
+                Some(qs) => pt.pdf(self.camera, Some(qs), pt_minus, rng),
+                None => pt.pdf_light(pt_minus),
+            };
+        }
+        if let Some(qs) = &qs {
+            light[s - 1].1 = pt.pdf(self.camera, pt_minus.as_ref(), qs, rng);
+            light[s - 1].2 = false;
+        }
+        if let Some(qs_minus) = &qs_minus {
+            light[s - 2].1 = qs.unwrap().pdf(self.camera, Some(&pt), qs_minus, rng);
+        }
+
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum += ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let delta_previous = i > 0 && light[i - 1].2;
+            if !light[i].2 && !delta_previous {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+//Film for contributions that can land on any pixel, written from every thread
+pub struct SplatFilm {
+    width: usize,
+    height: usize,
+    pixels: Vec<AtomicU32>,
+}
+
+impl SplatFilm {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: (0..width * height * 3).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    //Pixel that draw samples the film coordinates (s, t) of get_ray for. Its u and v cover
+    //[0, width / (width - 1)) and (-1 / (height - 1), 1], one pixel more than the unit square
+    pub fn pixel(&self, s: f32, t: f32) -> Option<usize> {
+        let x = s * (self.width - 1) as f32;
+        let y = (1.0 - t) * (self.height - 1) as f32;
+        if !(x >= 0.0 && y >= 0.0) {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    pub fn add(&self, s: f32, t: f32, color: Vector3<f32>) {
+        let index = match self.pixel(s, t) {
+            Some(pixel) => pixel * 3,
+            None => return,
+        };
+        for (pixel, value) in self.pixels[index..index + 3].iter().zip(color.to_array()) {
+            atomic_add(pixel, value);
+        }
+    }
+
+    pub fn get(&self, i: usize) -> Vector3<f32> {
+        let value = |c: usize| atomic_load(&self.pixels[i * 3 + c]);
+        Vector3::new(value(0), value(1), value(2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn corner_connections_land_in_their_pixel() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let (width, height) = (8, 6);
+        let camera = Camera::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            width as f32 / height as f32,
+            0.0,
+            1.0,
+            1.0,
+        );
+        let film = SplatFilm::new(width, height);
+        for (x, y) in [
+            (0, 0),
+            (width - 1, 0),
+            (0, height - 1),
+            (width - 1, height - 1),
+        ] {
+            //Center of the pixel, sampled the way draw does
+            let u = (x as f32 + 0.5) / (width - 1) as f32;
+            let v = 1.0 - (y as f32 + 0.5) / (height - 1) as f32;
+            let r = camera.get_ray(u, v, &mut rng);
+            let (_, s, t, _) = camera.sample_lens(r.at(3.0), &mut rng).unwrap();
+            assert_eq!(film.pixel(s, t), Some(y * width + x));
+        }
+    }
+}
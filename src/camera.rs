@@ -1,4 +1,4 @@
-use rand::prelude::ThreadRng;
+use rand::Rng;
 
 use crate::{ray::Ray, utilities::vector3::Vector3};
 
@@ -55,7 +55,7 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32, rng: &mut ThreadRng) -> Ray {
+    pub fn get_ray(&self, s: f32, t: f32, rng: &mut impl Rng) -> Ray {
         let rd = Vector3::random_in_unit_disk(rng) * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
         Ray::new(
@@ -63,4 +63,53 @@ impl Camera {
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
         )
     }
+    fn focus_distance(&self) -> f32 {
+        let center = self.lower_left_corner + (self.horizontal + self.vertical) * 0.5;
+        Vector3::dot(self.origin - center, self.w)
+    }
+
+    //(s, t) film coordinates of a ray leaving the lens point, the inverse of get_ray. The film
+    //of draw reaches past 1 in s and below 0 in t, SplatFilm checks which pixel they land in
+    fn film_position(&self, lens: Vector3<f32>, direction: Vector3<f32>) -> (f32, f32) {
+        let k = self.focus_distance() / Vector3::dot(direction, self.w * (-1.0));
+        let q = lens + direction * k - self.lower_left_corner;
+        let s = Vector3::dot(q, self.horizontal) / self.horizontal.magnitude2();
+        let t = Vector3::dot(q, self.vertical) / self.vertical.magnitude2();
+        (s, t)
+    }
+
+    //Solid angle density of get_ray directions, uniform over the film
+    pub fn pdf_direction(&self, direction: Vector3<f32>) -> f32 {
+        let cosine = Vector3::dot(direction.norm(), self.w * (-1.0));
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        let focus = self.focus_distance();
+        let film_area = self.horizontal.magnitude() * self.vertical.magnitude();
+        focus * focus / (film_area * cosine.powi(3))
+    }
+
+    //Connects p to a point on the lens. Returns the lens point, the film coordinates and the
+    //importance divided by the density of the connection
+    pub fn sample_lens(
+        &self,
+        p: Vector3<f32>,
+        rng: &mut impl Rng,
+    ) -> Option<(Vector3<f32>, f32, f32, f32)> {
+        let rd = Vector3::random_in_unit_disk(rng) * self.lens_radius;
+        let lens = self.origin + self.u * rd.x + self.v * rd.y;
+        let direction = p - lens;
+        let cosine = Vector3::dot(direction.norm(), self.w * (-1.0));
+        if cosine <= 0.0 {
+            return None;
+        }
+        let (s, t) = self.film_position(lens, direction);
+
+        //We = pdf_direction / (lens_area * cos) and the lens point density seen from p is
+        //d² / (lens_area * cos), the lens area cancels out
+        let focus = self.focus_distance();
+        let film_area = self.horizontal.magnitude() * self.vertical.magnitude();
+        let pdf_direction = focus * focus / (film_area * cosine.powi(3));
+        Some((lens, s, t, pdf_direction / direction.magnitude2()))
+    }
 }
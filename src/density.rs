@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use crate::utilities::noise::Perlin;
+use crate::utilities::vector3::Vector3;
+
+//Density in [0, 1] over the bounding box of a medium boundary
+#[derive(Clone)]
+pub enum Density {
+    Noise {
+        perlin: Arc<Perlin>,
+        scale: f32,
+        octaves: u32,
+    },
+    Grid {
+        voxels: Arc<Vec<f32>>,
+        resolution: [usize; 3],
+        max: f32,
+    },
+}
+
+impl Density {
+    pub fn noise(seed: u64, scale: f32, octaves: u32) -> Self {
+        Self::Noise {
+            perlin: Arc::new(Perlin::new(seed)),
+            scale,
+            octaves,
+        }
+    }
+
+    //Mitsuba .vol grid of float32 or uint8 voxels, only the first channel is used. The grid is
+    //stretched over the boundary, the bounds stored in the file are ignored
+    pub fn load_vol(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Failed to read volume from {:?}: {}", path, e))?;
+        Self::parse_vol(&bytes).map_err(|e| format!("{:?} is not a valid .vol file: {}", path, e))
+    }
+
+    fn parse_vol(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" {
+            return Err("missing header".to_string());
+        }
+        let int = |i: usize| i32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let encoding = int(4);
+        let resolution = [int(8), int(12), int(16)];
+        let channels = int(20);
+        if resolution.iter().chain([&channels]).any(|&x| x <= 0) {
+            return Err(format!(
+                "resolution {:?} with {} channels",
+                resolution, channels
+            ));
+        }
+        let resolution = resolution.map(|x| x as usize);
+        let channels = channels as usize;
+        let size = match encoding {
+            1 => 4,
+            3 => 1,
+            _ => return Err(format!("unsupported encoding {}", encoding)),
+        };
+        let n = resolution.iter().product::<usize>();
+        let data = &bytes[48..];
+        if data.len() != n * channels * size {
+            return Err(format!(
+                "{} bytes of voxels for a {:?} grid with {} channels",
+                data.len(),
+                resolution,
+                channels
+            ));
+        }
+
+        let voxels: Vec<f32> = match encoding {
+            1 => (0..n)
+                .map(|i| {
+                    let offset = i * channels * 4;
+                    f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+                })
+                .collect(),
+            _ => (0..n).map(|i| data[i * channels] as f32 / 255.0).collect(),
+        };
+        let max = voxels.iter().cloned().fold(0.0, f32::max);
+        Ok(Self::Grid {
+            voxels: Arc::new(voxels),
+            resolution,
+            max,
+        })
+    }
+
+    //Upper bound of the density, the majorant of delta tracking
+    pub fn max(&self) -> f32 {
+        match self {
+            Self::Noise { .. } => 1.0,
+            Self::Grid { max, .. } => *max,
+        }
+    }
+
+    //p is the world position and local its position in the unit cube of the bounds
+    pub fn value(&self, p: Vector3<f32>, local: Vector3<f32>) -> f32 {
+        match self {
+            Self::Noise {
+                perlin,
+                scale,
+                octaves,
+            } => (0.5 + perlin.fbm(p * *scale, *octaves)).clamp(0.0, 1.0),
+            Self::Grid {
+                voxels,
+                resolution,
+                max: _,
+            } => {
+                //Trilinear interpolation between voxel centers
+                let coords = [local.x, local.y, local.z];
+                let mut cell = [0; 3];
+                let mut frac = [0.0; 3];
+                for axis in 0..3 {
+                    let x = (coords[axis] * resolution[axis] as f32 - 0.5)
+                        .clamp(0.0, (resolution[axis] - 1) as f32);
+                    cell[axis] = (x as usize).min(resolution[axis].saturating_sub(2));
+                    frac[axis] = x - cell[axis] as f32;
+                }
+                let voxel = |x: usize, y: usize, z: usize| {
+                    let x = (cell[0] + x).min(resolution[0] - 1);
+                    let y = (cell[1] + y).min(resolution[1] - 1);
+                    let z = (cell[2] + z).min(resolution[2] - 1);
+                    voxels[(z * resolution[1] + y) * resolution[0] + x]
+                };
+                let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+                let plane = |z: usize| {
+                    lerp(
+                        lerp(voxel(0, 0, z), voxel(1, 0, z), frac[0]),
+                        lerp(voxel(0, 1, z), voxel(1, 1, z), frac[0]),
+                        frac[1],
+                    )
+                };
+                lerp(plane(0), plane(1), frac[2])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vol(resolution: [i32; 3], voxels: &[f32]) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for x in [1, resolution[0], resolution[1], resolution[2], 1] {
+            bytes.extend(x.to_le_bytes());
+        }
+        bytes.extend([0; 24]);
+        for v in voxels {
+            bytes.extend(v.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_grid() {
+        let density = Density::parse_vol(&vol([2, 1, 1], &[0.25, 0.75])).unwrap();
+        assert_eq!(density.max(), 0.75);
+        let center = Vector3::new(0.5, 0.5, 0.5);
+        assert!((density.value(center, center) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        //Truncated payload, payload too long, negative size and a bad magic number
+        assert!(Density::parse_vol(&vol([2, 2, 2], &[0.5; 7])).is_err());
+        assert!(Density::parse_vol(&vol([2, 2, 2], &[0.5; 9])).is_err());
+        assert!(Density::parse_vol(&vol([-2, 2, 2], &[0.5; 8])).is_err());
+        let mut bytes = vol([1, 1, 1], &[0.5]);
+        bytes[0] = b'X';
+        assert!(Density::parse_vol(&bytes).is_err());
+        assert!(Density::parse_vol(&bytes[..20]).is_err());
+    }
+}
@@ -0,0 +1,453 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    aabb::AABB,
+    integrator::{ray_color, World},
+    medium::MediumStack,
+    pdf::PDFMixture,
+    utilities::{math::seeded_rng, vector3::Vector3},
+};
+
+const PI: f32 = std::f32::consts::PI;
+//Spatial leaves split after c * sqrt(2^pass) samples
+const SPATIAL_THRESHOLD: f32 = 12000.0;
+//Directional quads split when they hold more than this fraction of the energy
+const RHO: f32 = 0.01;
+const MAX_DIRECTIONAL_DEPTH: usize = 20;
+//Rows traced before their records are inserted, bounding memory use
+const BAND_ROWS: usize = 16;
+
+//SD-tree path guide, a binary tree over space with quadtrees over directions in its leaves
+pub struct PathGuide {
+    nodes: Vec<SpatialNode>,
+    bounds: AABB,
+    fraction: f32,
+    trained: bool,
+}
+
+#[derive(Clone)]
+struct SpatialNode {
+    axis: usize,
+    //Zero for leaves, the root is never a child
+    children: [usize; 2],
+    samples: usize,
+    sampling: DirectionalTree,
+    building: DirectionalTree,
+}
+
+//Quadtree over cylindrical coordinates (cos(theta), phi), an equal area mapping of the sphere
+#[derive(Clone)]
+struct DirectionalTree {
+    nodes: Vec<QuadNode>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct QuadNode {
+    sum: [f32; 4],
+    child: [usize; 4],
+}
+
+#[derive(Clone, Copy)]
+pub struct Record {
+    p: Vector3<f32>,
+    direction: Vector3<f32>,
+    radiance: f32,
+}
+
+#[derive(Clone, Copy)]
+struct GuideVertex {
+    p: Vector3<f32>,
+    direction: Vector3<f32>,
+    pdf: f32,
+    throughput: Vector3<f32>,
+}
+
+//Guiding state of a single path, recording its vertices while training
+pub struct GuidedPath<'a> {
+    guide: &'a PathGuide,
+    vertices: Vec<GuideVertex>,
+    records: Option<&'a mut Vec<Record>>,
+}
+
+impl<'a> GuidedPath<'a> {
+    pub fn sampling(guide: &'a PathGuide) -> Self {
+        Self {
+            guide,
+            vertices: vec![],
+            records: None,
+        }
+    }
+
+    pub fn recording(guide: &'a PathGuide, records: &'a mut Vec<Record>) -> Self {
+        Self {
+            guide,
+            vertices: vec![],
+            records: Some(records),
+        }
+    }
+
+    //Samples the learned distribution with the guide fraction and the mixture otherwise,
+    //returns the direction and its combined pdf
+    pub fn sample(
+        &self,
+        p: Vector3<f32>,
+        mixture: &PDFMixture,
+        chance: f32,
+        rng: &mut impl Rng,
+    ) -> (Vector3<f32>, f32) {
+        let tree = self.guide.distribution(p);
+        let fraction = if tree.is_some() {
+            self.guide.fraction
+        } else {
+            0.0
+        };
+
+        let direction = match tree {
+            Some(tree) if rng.gen::<f32>() < fraction => tree.sample(rng),
+            _ => mixture.sample(chance, rng),
+        };
+        let guided = tree.map_or(0.0, |tree| tree.pdf(direction));
+        let pdf = fraction * guided + (1.0 - fraction) * mixture.value(chance, direction);
+        (direction, pdf)
+    }
+
+    //throughput includes the scattering at p
+    pub fn add_vertex(
+        &mut self,
+        p: Vector3<f32>,
+        direction: Vector3<f32>,
+        pdf: f32,
+        throughput: Vector3<f32>,
+    ) {
+        if self.records.is_some() {
+            self.vertices.push(GuideVertex {
+                p,
+                direction,
+                pdf,
+                throughput,
+            });
+        }
+    }
+
+    //Radiance arriving at each vertex is the path contribution over the throughput up to it
+    pub fn finish(&mut self, color: Vector3<f32>) {
+        if let Some(records) = self.records.as_mut() {
+            for vertex in self.vertices.drain(..) {
+                let ratio = |c: f32, t: f32| if t > 0.0 { c / t } else { 0.0 };
+                let incident = Vector3::new(
+                    ratio(color.x, vertex.throughput.x),
+                    ratio(color.y, vertex.throughput.y),
+                    ratio(color.z, vertex.throughput.z),
+                );
+                let radiance = incident.luminance() / vertex.pdf;
+                if radiance.is_finite() && vertex.pdf > 0.0 {
+                    records.push(Record {
+                        p: vertex.p,
+                        direction: vertex.direction,
+                        radiance,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl PathGuide {
+    //Trains the guide with passes of 1, 2, 4... samples per pixel
+    pub fn train(world: &World, passes: usize, fraction: f32) -> Self {
+        let mut guide = Self {
+            nodes: vec![SpatialNode::new(0, DirectionalTree::new())],
+            bounds: AABB::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+            fraction,
+            trained: false,
+        };
+        let height = world.height as usize;
+        let camera_media = MediumStack::at(&world.bvh, world.camera.origin);
+
+        let pb = ProgressBar::new(passes as u64);
+        pb.set_style(ProgressStyle::default_bar().template("{bar:40.green/white}  {percent} %"));
+        for pass in 0..passes {
+            let spp = 1 << pass;
+            //The first pass is kept whole to bound the scene
+            let band = if pass == 0 { height } else { BAND_ROWS };
+            for start in (0..height).step_by(band) {
+                let records: Vec<Vec<Record>> = (start..(start + band).min(height))
+                    .into_par_iter()
+                    .map(|y| guide.trace_row(world, camera_media, y, spp, pass))
+                    .collect();
+                if pass == 0 {
+                    guide.bounds = bound(records.iter().flatten());
+                }
+                for record in records.iter().flatten() {
+                    guide.record(record);
+                }
+            }
+            guide.refine(SPATIAL_THRESHOLD * (spp as f32).sqrt());
+            pb.inc(1);
+        }
+        pb.finish_and_clear();
+        guide
+    }
+
+    fn trace_row(
+        &self,
+        world: &World,
+        camera_media: MediumStack,
+        y: usize,
+        spp: usize,
+        pass: usize,
+    ) -> Vec<Record> {
+        let width = world.width as usize;
+        let n = width * world.height as usize;
+        let mut records = vec![];
+        for x in 0..width {
+            let mut rng = seeded_rng(world.seed, ((pass + 1) * n + y * width + x) as u64);
+            for _ in 0..spp {
+                let u = (x as f32 + rng.gen::<f32>()) / (world.width - 1.0);
+                let v = 1.0 - (y as f32 + rng.gen::<f32>()) / (world.height - 1.0);
+                let r = world.camera.get_ray(u, v, &mut rng);
+                let mut path = GuidedPath::recording(self, &mut records);
+                ray_color(
+                    &world.bvh,
+                    r,
+                    camera_media,
+                    world.depth,
+                    &world.background,
+                    &world.light,
+                    Some(&mut path),
+                    world.spectral,
+                    &mut rng,
+                );
+            }
+        }
+        records
+    }
+
+    //Learned distribution around p, None before it has seen any radiance
+    fn distribution(&self, p: Vector3<f32>) -> Option<&DirectionalTree> {
+        if !self.trained {
+            return None;
+        }
+        let tree = &self.nodes[self.leaf(p)].sampling;
+        if tree.total() > 0.0 {
+            Some(tree)
+        } else {
+            None
+        }
+    }
+
+    fn leaf(&self, p: Vector3<f32>) -> usize {
+        let size = self.bounds.maximum - self.bounds.minimum;
+        let mut p = ((p - self.bounds.minimum) / size.max_axis())
+            .to_array()
+            .map(|c| c.clamp(0.0, 1.0));
+        let mut i = 0;
+        while self.nodes[i].children[0] != 0 {
+            let axis = self.nodes[i].axis;
+            let side = p[axis] >= 0.5;
+            p[axis] = 2.0 * p[axis] - side as usize as f32;
+            i = self.nodes[i].children[side as usize];
+        }
+        i
+    }
+
+    fn record(&mut self, record: &Record) {
+        let i = self.leaf(record.p);
+        let node = &mut self.nodes[i];
+        node.samples += 1;
+        node.building.record(record.direction, record.radiance);
+    }
+
+    //Splits crowded spatial leaves, then every leaf samples from what it learned and starts a
+    //finer tree for the next pass
+    fn refine(&mut self, threshold: f32) {
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            if node.children[0] == 0 && node.samples as f32 > threshold {
+                let axis = (node.axis + 1) % 3;
+                let mut child = SpatialNode::new(axis, node.building.clone());
+                child.samples = node.samples / 2;
+                let first = self.nodes.len();
+                self.nodes.push(child.clone());
+                self.nodes.push(child);
+                let parent = &mut self.nodes[i];
+                parent.children = [first, first + 1];
+                parent.sampling = DirectionalTree::new();
+                parent.building = DirectionalTree::new();
+            }
+            i += 1;
+        }
+
+        for node in self.nodes.iter_mut().filter(|node| node.children[0] == 0) {
+            let refined = node.building.refined();
+            node.sampling = std::mem::replace(&mut node.building, refined);
+            node.samples = 0;
+        }
+        self.trained = true;
+    }
+}
+
+impl SpatialNode {
+    fn new(axis: usize, building: DirectionalTree) -> Self {
+        Self {
+            axis,
+            children: [0, 0],
+            samples: 0,
+            sampling: DirectionalTree::new(),
+            building,
+        }
+    }
+}
+
+impl DirectionalTree {
+    fn new() -> Self {
+        Self {
+            nodes: vec![QuadNode::default()],
+        }
+    }
+
+    fn total(&self) -> f32 {
+        self.nodes[0].sum.iter().sum()
+    }
+
+    fn record(&mut self, direction: Vector3<f32>, radiance: f32) {
+        let mut p = to_canonical(direction);
+        let mut i = 0;
+        loop {
+            let q = quadrant(&mut p);
+            self.nodes[i].sum[q] += radiance;
+            match self.nodes[i].child[q] {
+                0 => break,
+                child => i = child,
+            }
+        }
+    }
+
+    //Solid angle density
+    fn pdf(&self, direction: Vector3<f32>) -> f32 {
+        let mut p = to_canonical(direction);
+        let mut density = 1.0 / (4.0 * PI);
+        let mut i = 0;
+        loop {
+            let node = &self.nodes[i];
+            let total: f32 = node.sum.iter().sum();
+            if total <= 0.0 {
+                return 0.0;
+            }
+            let q = quadrant(&mut p);
+            density *= 4.0 * node.sum[q] / total;
+            match node.child[q] {
+                0 => return density,
+                child => i = child,
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> Vector3<f32> {
+        let mut origin = [0.0, 0.0];
+        let mut size = 1.0;
+        let mut i = 0;
+        loop {
+            let node = &self.nodes[i];
+            let mut u = rng.gen::<f32>() * node.sum.iter().sum::<f32>();
+            let mut q = 0;
+            while q < 3 && u >= node.sum[q] {
+                u -= node.sum[q];
+                q += 1;
+            }
+            size *= 0.5;
+            origin[0] += (q & 1) as f32 * size;
+            origin[1] += (q >> 1) as f32 * size;
+            match node.child[q] {
+                0 => break,
+                child => i = child,
+            }
+        }
+        from_canonical([
+            origin[0] + rng.gen::<f32>() * size,
+            origin[1] + rng.gen::<f32>() * size,
+        ])
+    }
+
+    //Empty tree for the next pass, subdivided where this one gathered energy
+    fn refined(&self) -> Self {
+        let mut tree = Self::new();
+        let total = self.total();
+        if total > 0.0 {
+            self.refine_into(Some(0), 0.0, total, 1, 0, &mut tree);
+        }
+        tree
+    }
+
+    //Leaves of this tree split their energy evenly when the new tree goes deeper
+    fn refine_into(
+        &self,
+        old: Option<usize>,
+        energy: f32,
+        total: f32,
+        depth: usize,
+        new: usize,
+        tree: &mut Self,
+    ) {
+        for q in 0..4 {
+            let (child, energy) = match old {
+                Some(i) => (
+                    Some(self.nodes[i].child[q]).filter(|c| *c != 0),
+                    self.nodes[i].sum[q],
+                ),
+                None => (None, energy / 4.0),
+            };
+            if depth < MAX_DIRECTIONAL_DEPTH && energy > RHO * total {
+                tree.nodes.push(QuadNode::default());
+                let index = tree.nodes.len() - 1;
+                tree.nodes[new].child[q] = index;
+                self.refine_into(child, energy, total, depth + 1, index, tree);
+            }
+        }
+    }
+}
+
+//Quadrant of p, which is rescaled to the quadrant
+fn quadrant(p: &mut [f32; 2]) -> usize {
+    let right = p[0] >= 0.5;
+    let top = p[1] >= 0.5;
+    p[0] = 2.0 * p[0] - right as usize as f32;
+    p[1] = 2.0 * p[1] - top as usize as f32;
+    right as usize | (top as usize) << 1
+}
+
+fn to_canonical(direction: Vector3<f32>) -> [f32; 2] {
+    let d = direction.norm();
+    let phi = d.y.atan2(d.x);
+    let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+    [
+        ((d.z.clamp(-1.0, 1.0) + 1.0) * 0.5).min(0.999_999),
+        (phi / (2.0 * PI)).min(0.999_999),
+    ]
+}
+
+fn from_canonical(p: [f32; 2]) -> Vector3<f32> {
+    let cos_theta = 2.0 * p[0] - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * p[1];
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+//Cube around the recorded vertices, so the spatial splits stay even
+fn bound<'a>(records: impl Iterator<Item = &'a Record>) -> AABB {
+    let (minimum, maximum) = records.fold(
+        (
+            Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        ),
+        |(minimum, maximum), record| (minimum.min(record.p), maximum.max(record.p)),
+    );
+    if minimum.x > maximum.x {
+        return AABB::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+    }
+    let size = (maximum - minimum).max_axis() * 1.01 + 0.001;
+    AABB::new(minimum, minimum + Vector3::new(size, size, size))
+}
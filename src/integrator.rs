@@ -1,46 +1,127 @@
-use crate::background::Background;
+use crate::background::Environment;
+use crate::bdpt::{Bdpt, SplatFilm};
 use crate::camera::Camera;
+use crate::guiding::{GuidedPath, PathGuide};
 use crate::material::ScatterRecord;
+use crate::medium::MediumStack;
+use crate::mlt::Mlt;
 use crate::object::Object;
 use crate::pdf::{PDFMixture, PDFType, PDF};
+use crate::photon_mapping::PhotonMapping;
 use crate::scenes::Scenes;
-use crate::utilities::math::fmax;
+use crate::utilities::color::Wavelengths;
+use crate::utilities::math::{fmax, seeded_rng};
 use crate::utilities::vector3::Vector3;
+use crate::visibility::ALL_LIGHT_GROUPS;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 
-use crate::ray::Ray;
+use crate::ray::{Ray, RayType};
 use crate::simd_bvh::SceneBVH;
 
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
+
+#[derive(Clone, Copy)]
+pub enum Integrator {
+    PathTracing,
+    Bidirectional,
+    PhotonMapping,
+    Metropolis,
+}
 
 pub struct World {
     pub camera: Camera,
-    pub background: Background,
+    pub background: Environment,
     pub bvh: SceneBVH,
+    //Emitters sampled for direct light. The path tracer also picks up emission it hits without
+    //sampling it, while photons and light subpaths only start on these
     pub light: Vec<Object>,
     pub aa: i32,
     pub depth: i32,
-    width: f32,
-    height: f32,
+    pub integrator: Integrator,
+    pub width: f32,
+    pub height: f32,
+    //Training passes and fraction of guided directions, for path tracing
+    pub guiding: Option<(usize, f32)>,
+    pub seed: Option<u64>,
+    //Path tracing and Metropolis carry wavelengths instead of RGB
+    pub spectral: bool,
 }
 impl World {
-    pub fn new(scene: Scenes, width: f32, height: f32, aa: i32, depth: i32) -> Self {
+    pub fn new(
+        scene: Scenes,
+        width: f32,
+        height: f32,
+        aa: i32,
+        depth: i32,
+        integrator: Integrator,
+    ) -> Self {
         let scene_config = scene.get(width, height);
 
         Self {
             camera: scene_config.camera,
             background: scene_config.background,
             light: scene_config.light,
-            bvh: SceneBVH::from(scene_config.objects),
+            bvh: SceneBVH::from(scene_config.objects).with_atmosphere(scene_config.atmosphere),
             aa,
             depth,
+            integrator,
             width,
             height,
+            guiding: None,
+            seed: None,
+            spectral: false,
         }
     }
 
+    pub fn with_guiding(mut self, passes: usize, fraction: f32) -> Self {
+        self.guiding = Some((passes, fraction)).filter(|(passes, _)| *passes > 0);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_spectral(mut self, spectral: bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
+
     pub fn draw(&self, frame: &mut [f32]) {
+        //Photon mapping works in iterations over the whole image, one per sample
+        if let Integrator::PhotonMapping = self.integrator {
+            let photon_mapping = PhotonMapping::new(
+                &self.bvh,
+                &self.camera,
+                &self.background,
+                &self.light,
+                self.depth,
+                self.width as usize,
+                self.height as usize,
+                self.seed,
+            );
+            photon_mapping.render(self.aa as usize, frame);
+            return;
+        }
+        //Metropolis chains wander the whole image, with aa mutations per pixel on average
+        if let Integrator::Metropolis = self.integrator {
+            let mlt = Mlt::new(
+                &self.bvh,
+                &self.camera,
+                &self.background,
+                &self.light,
+                self.depth,
+                self.width as usize,
+                self.height as usize,
+                self.spectral,
+                self.seed,
+            );
+            mlt.render(self.aa as usize, frame);
+            return;
+        }
+
         let n = (self.width * self.height) as u64;
         let pb = ProgressBar::new(n);
         pb.set_style(ProgressStyle::default_bar().template("{bar:40.green/white}  {percent} %"));
@@ -48,9 +129,24 @@ impl World {
 
         let x_strata = (self.aa as f32).sqrt().floor() as usize;
         let y_strata = (self.aa as f32 / x_strata as f32).floor() as usize;
+        let bdpt = Bdpt::new(
+            &self.bvh,
+            &self.camera,
+            &self.background,
+            &self.light,
+            self.depth,
+        );
+        let splats = SplatFilm::new(self.width as usize, self.height as usize);
+        let camera_media = MediumStack::at(&self.bvh, self.camera.origin);
+        let guide = match (self.integrator, self.guiding) {
+            (Integrator::PathTracing, Some((passes, fraction))) => {
+                Some(PathGuide::train(self, passes, fraction))
+            }
+            _ => None,
+        };
 
         frame.par_chunks_mut(3).enumerate().for_each(|(i, pixel)| {
-            let mut rng = rand::thread_rng();
+            let mut rng = seeded_rng(self.seed, i as u64);
 
             let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
             let x = (i % self.width as usize) as f32;
@@ -65,14 +161,21 @@ impl World {
                             / (self.height - 1.0);
 
                     let r = self.camera.get_ray(u, v, &mut rng);
-                    pixel_color += ray_color(
-                        &self.bvh,
-                        r,
-                        self.depth,
-                        &self.background,
-                        &self.light,
-                        &mut rng,
-                    );
+                    pixel_color += match self.integrator {
+                        Integrator::PathTracing => ray_color(
+                            &self.bvh,
+                            r,
+                            camera_media,
+                            self.depth,
+                            &self.background,
+                            &self.light,
+                            guide.as_ref().map(GuidedPath::sampling).as_mut(),
+                            self.spectral,
+                            &mut rng,
+                        ),
+                        Integrator::Bidirectional => bdpt.sample(r, &splats, &mut rng),
+                        Integrator::PhotonMapping | Integrator::Metropolis => unreachable!(),
+                    };
                 }
             }
             pixel.copy_from_slice(&get_color(
@@ -83,69 +186,166 @@ impl World {
             pb.inc(1);
         });
         pb.finish_and_clear();
+
+        //Light subpaths connected to the camera land on any pixel, one per camera sample. The
+        //film spans (width - 1) * (height - 1) pixels of get_ray
+        if let Integrator::Bidirectional = self.integrator {
+            let scale = (self.width - 1.0) * (self.height - 1.0)
+                / (self.width * self.height * (x_strata * y_strata) as f32);
+            frame.par_chunks_mut(3).enumerate().for_each(|(i, pixel)| {
+                let splat = splats.get(i) * scale;
+                pixel[0] += splat.x;
+                pixel[1] += splat.y;
+                pixel[2] += splat.z;
+            });
+        }
     }
 }
 
-// Monte Carlo Integrator
-fn ray_color(
-    bvh: &SceneBVH,
+// Monte Carlo Integrator, media holds the media r starts in. Spectral paths sample their
+// wavelengths and turn them into RGB at the end
+#[allow(clippy::too_many_arguments)]
+pub fn ray_color<'a>(
+    bvh: &'a SceneBVH,
     r: Ray,
+    media: MediumStack<'a>,
     depth_t: i32,
-    background: &Background,
+    background: &Environment,
     light: &[Object],
-    rng: &mut ThreadRng,
+    mut guide: Option<&mut GuidedPath>,
+    spectral: bool,
+    rng: &mut impl Rng,
 ) -> Vector3<f32> {
+    let wavelengths = spectral.then(|| Wavelengths::sample(rng.gen()));
+    let color = trace_path(
+        bvh,
+        r,
+        media,
+        depth_t,
+        background,
+        light,
+        guide.as_deref_mut(),
+        wavelengths,
+        rng,
+    );
+    //The guide divides by throughputs, which are per wavelength too
+    if let Some(guide) = guide {
+        guide.finish(color);
+    }
+    wavelengths.map_or(color, |wavelengths| wavelengths.to_rgb(color))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn trace_path<'a>(
+    bvh: &'a SceneBVH,
+    r: Ray,
+    mut media: MediumStack<'a>,
+    depth_t: i32,
+    background: &Environment,
+    light: &[Object],
+    mut guide: Option<&mut GuidedPath>,
+    wavelengths: Option<Wavelengths>,
+    rng: &mut impl Rng,
+) -> Vector3<f32> {
+    //RGB reflectances and radiances are upsampled to the wavelengths of spectral paths
+    let spectrum = |rgb: Vector3<f32>| match wavelengths {
+        Some(wavelengths) => wavelengths.upsample(rgb),
+        None => rgb,
+    };
+    //Whether only the hero wavelength is left after a dispersive event
+    let mut terminated = false;
     let mut color = Vector3::new(1.0, 1.0, 1.0);
-    let chance = if light.is_empty() { 0.0 } else { 0.5 };
+    //Light given off by media along the way
+    let mut radiance = Vector3::new(0.0, 0.0, 0.0);
 
     let mut scatter_ray = r;
-    for bounces in 0..depth_t {
-        if let Some(hit) = bvh.hit(&scatter_ray, 0.001, f32::INFINITY) {
+    let mut ray_type = RayType::Camera;
+    //Light groups allowed to light the last surface, the background lights everything
+    let mut lit_by = ALL_LIGHT_GROUPS;
+    let mut bounces = 0;
+    while bounces < depth_t {
+        scatter_ray.wavelength = wavelengths.map(|wavelengths| wavelengths.hero());
+        let (hit, weight, emitted) =
+            media.trace(bvh, &scatter_ray, f32::INFINITY, ray_type, light, rng);
+        radiance += color * spectrum(emitted);
+        color = color * spectrum(weight);
+        if let Some(hit) = hit {
             if let Some(scatter) = hit.material.scatter(&scatter_ray, &hit, rng) {
+                lit_by = hit.visibility.lit_by;
+                let pdf = PDF::new(hit.p, light, lit_by);
+                let chance = if pdf.linked().next().is_some() {
+                    0.5
+                } else {
+                    0.0
+                };
+                let pdf_lights = PDFType::PDFObj { pdf };
+                if let Some(wavelengths) = wavelengths {
+                    if hit.material.is_dispersive() && !terminated {
+                        color = wavelengths.terminate_secondary(color);
+                        terminated = true;
+                    }
+                }
                 match scatter {
                     ScatterRecord::Specular {
                         specular_ray,
                         attenuation,
                     } => {
-                        color = color * attenuation;
+                        color = color * spectrum(attenuation);
+                        ray_type = ray_type.after_specular(specular_ray.direction, hit.normal);
                         scatter_ray = specular_ray;
                     }
                     ScatterRecord::Scatter { pdf, attenuation } => {
-                        let pdf_lights = PDFType::PDFObj {
-                            pdf: PDF::new(hit.p, light),
-                        };
                         let mixture = PDFMixture::new(&pdf_lights, &pdf);
-                        let scattered = Ray::new(hit.p, mixture.sample(chance, rng));
-                        let pdf_val = mixture.value(chance, scattered.direction);
+                        let (direction, pdf_val) =
+                            sample_direction(guide.as_deref(), hit.p, &mixture, chance, rng);
+                        let scattered = Ray::new(hit.p, direction);
 
                         let pdf_multiplicator = pdf.value(scattered.direction) / pdf_val;
 
                         if pdf_multiplicator == pdf_multiplicator {
-                            color = color * attenuation * pdf_multiplicator;
+                            color = color * spectrum(attenuation) * pdf_multiplicator;
+                        }
+                        if let Some(guide) = guide.as_deref_mut() {
+                            guide.add_vertex(hit.p, direction, pdf_val, color);
                         }
 
+                        ray_type = RayType::Diffuse;
                         scatter_ray = scattered;
                     }
                     ScatterRecord::SpecularDiffuse { pdf, attenuation } => {
-                        let pdf_lights = PDFType::PDFObj {
-                            pdf: PDF::new(hit.p, light),
-                        };
                         let mixture = PDFMixture::new(&pdf_lights, &pdf);
 
-                        let scattered = Ray::new(hit.p, mixture.sample(chance, rng));
-                        let pdf_val = mixture.value(chance, scattered.direction);
+                        let (direction, pdf_val) =
+                            sample_direction(guide.as_deref(), hit.p, &mixture, chance, rng);
+                        let scattered = Ray::new(hit.p, direction);
 
                         let eval =
                             hit.material
-                                .eval_brdf(&scatter_ray, &hit, attenuation, &scattered, );
+                                .eval_brdf(&scatter_ray, &hit, attenuation, &scattered);
                         if eval == eval && pdf_val == pdf_val && pdf_val > 0.0 {
-                            color = color * eval / pdf_val;
+                            color = color * spectrum(eval) / pdf_val;
+                        } else if direction.near_zero() {
+                            //Samples lost by microfacet BSDFs, like reflections below the
+                            //surface, come out as the zero direction
+                            break;
+                        }
+                        if let Some(guide) = guide.as_deref_mut() {
+                            guide.add_vertex(hit.p, direction, pdf_val, color);
                         }
 
-
+                        ray_type = RayType::Diffuse;
                         scatter_ray = scattered;
                     }
                 }
+                debug_assert!(scatter_ray
+                    .direction
+                    .to_array()
+                    .iter()
+                    .all(|c| c.is_finite()));
+                media.cross(&hit, scatter_ray.direction);
+                if media.walk_step(&hit) {
+                    continue;
+                }
                 //Russian roulette
                 if bounces > 5 {
                     let q = fmax(0.03, 1.0 - color.max_axis());
@@ -155,20 +355,50 @@ fn ray_color(
                         color /= 1.0 - q;
                     }
                 }
+                bounces += 1;
 
                 continue;
             } else {
-                let emitted = hit.material.emit(hit.u, hit.v, hit.p, hit.front_face);
-                return color * emitted;
+                if !hit.visibility.lights(lit_by) {
+                    return radiance;
+                }
+                let emitted = match wavelengths {
+                    Some(wavelengths) => hit.material.emit_spectrum(
+                        hit.u,
+                        hit.v,
+                        hit.p,
+                        hit.front_face,
+                        &wavelengths,
+                    ),
+                    None => hit.material.emit(hit.u, hit.v, hit.p, hit.front_face),
+                };
+                return radiance + color * emitted;
             }
         }
 
         /*  let unit_direction = scatter_ray.direction.norm();
         let t = 0.5 * (unit_direction.y + 1.0);
         return color * (Vector3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vector3::new(0.5, 0.7, 1.0) * t);*/
-        return color * background.value(&scatter_ray);
+        return radiance + color * spectrum(background.value(&scatter_ray, ray_type));
+    }
+    radiance
+}
+
+//Direction from the guide when there is one, from the mixture otherwise, and its pdf
+fn sample_direction(
+    guide: Option<&GuidedPath>,
+    p: Vector3<f32>,
+    mixture: &PDFMixture,
+    chance: f32,
+    rng: &mut impl Rng,
+) -> (Vector3<f32>, f32) {
+    match guide {
+        Some(guide) => guide.sample(p, mixture, chance, rng),
+        None => {
+            let direction = mixture.sample(chance, rng);
+            (direction, mixture.value(chance, direction))
+        }
     }
-    Vector3::new(0.0, 0.0, 0.0)
 }
 
 #[inline(always)]
@@ -204,3 +434,70 @@ fn get_color(color: Vector3<f32>, samples_per_pixel: f32, _exposure: f32) -> [f3
     );
     color.to_rgbau8()
 }*/
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+    use crate::visibility::RayVisibility;
+    use crate::{background::Background, material::Material, texture::Texture};
+
+    //Floor lit by light group lit_by under a light in group 2, seen from above
+    fn floor_radiance(lit_by: u32) -> f32 {
+        let floor = Object::build_xz_rect(
+            -5.0,
+            5.0,
+            -5.0,
+            5.0,
+            0.0,
+            Material::Lambertian {
+                albedo: Vector3::new(0.8, 0.8, 0.8),
+            },
+            false,
+        )
+        .with_visibility(RayVisibility::default().with_lit_by(lit_by));
+        let light = Object::build_xz_rect(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            3.0,
+            Material::DiffuseLight {
+                texture: Texture::SolidColor {
+                    albedo: Vector3::new(4.0, 4.0, 4.0),
+                },
+            },
+            true,
+        )
+        .with_visibility(RayVisibility::hidden_from_camera().with_light_group(2));
+        let bvh = SceneBVH::from(vec![floor, light.clone()]);
+        let background = Environment::from(Background::new_plain(Vector3::new(0.0, 0.0, 0.0)));
+        let lights = [light];
+
+        let mut rng = SmallRng::seed_from_u64(3);
+        let r = Ray::new(Vector3::new(0.5, 2.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        (0..1000)
+            .map(|_| {
+                ray_color(
+                    &bvh,
+                    r,
+                    MediumStack::at(&bvh, r.origin),
+                    5,
+                    &background,
+                    &lights,
+                    None,
+                    false,
+                    &mut rng,
+                )
+                .luminance()
+            })
+            .sum::<f32>()
+    }
+
+    #[test]
+    fn unlinked_light_adds_nothing() {
+        assert!(floor_radiance(ALL_LIGHT_GROUPS) > 0.0);
+        assert_eq!(floor_radiance(1), 0.0);
+    }
+}
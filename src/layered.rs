@@ -0,0 +1,79 @@
+use crate::{
+    microfacet::{fresnel_dielectric, GGX},
+    ray::HitRecord,
+    texture::Texture,
+    utilities::vector3::Vector3,
+};
+
+//Dielectric coat over a base material, like lacquer, varnish or the clear layer of car paint
+//[16]. The coat reflects by Fresnel off smooth or GGX rough facets, and the base shows through it
+//attenuated by the light the coat reflects and by the tint it absorbs on the way in and out
+#[derive(Clone)]
+pub struct Clearcoat {
+    ior: f32,
+    roughness: Texture,
+    tint: Vector3<f32>,
+}
+
+#[allow(dead_code)]
+impl Clearcoat {
+    pub fn new(ior: f32) -> Self {
+        Self {
+            ior,
+            roughness: 0.0.into(),
+            tint: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn with_roughness(mut self, roughness: impl Into<Texture>) -> Self {
+        self.roughness = roughness.into();
+        self
+    }
+
+    //Color of the base seen through the coat at normal incidence, it deepens at grazing angles
+    pub fn with_tint(mut self, tint: Vector3<f32>) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    fn roughness(&self, hit: &HitRecord) -> f32 {
+        self.roughness.value(hit.u, hit.v, hit.p).x.clamp(0.0, 1.0)
+    }
+
+    pub fn distribution(&self, hit: &HitRecord) -> GGX {
+        GGX::from_roughness(self.roughness(hit), 0.0)
+    }
+
+    //Coats too smooth for GGX reflect like a mirror, like smooth conductors
+    pub fn is_smooth(&self, hit: &HitRecord) -> bool {
+        self.roughness(hit).powi(2) < 1e-3
+    }
+
+    pub fn fresnel(&self, cosine: f32) -> f32 {
+        fresnel_dielectric(cosine, self.ior)
+    }
+
+    //Light through the coat in one pass, from or toward a direction at the given cosine to the
+    //normal. The tint applies halfway at normal incidence and more along longer refracted paths
+    pub fn transmittance(&self, cosine: f32) -> Vector3<f32> {
+        let cosine = cosine.clamp(0.0, 1.0);
+        let cos_t = (1.0 - (1.0 - cosine * cosine) / (self.ior * self.ior)).sqrt();
+        let exponent = 0.5 / cos_t.max(1e-3);
+        Vector3::new(
+            self.tint.x.powf(exponent),
+            self.tint.y.powf(exponent),
+            self.tint.z.powf(exponent),
+        ) * (1.0 - self.fresnel(cosine))
+    }
+
+    //Chance of sampling the coat rather than the base, by their share of the reflected light
+    pub fn coat_chance(&self, cosine: f32, base_albedo: Vector3<f32>) -> f32 {
+        let coat = self.fresnel(cosine);
+        let base = (1.0 - coat) * (base_albedo * self.tint).luminance().max(0.0);
+        if coat + base > 0.0 {
+            coat / (coat + base)
+        } else {
+            1.0
+        }
+    }
+}
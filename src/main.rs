@@ -2,13 +2,25 @@
 
 mod aabb;
 mod background;
+mod bdpt;
 mod camera;
-mod constant_medium;
+mod density;
+mod guiding;
 mod imaging;
 mod integrator;
+mod layered;
 mod material;
+mod measured;
+mod medium;
+mod microfacet;
+mod mlt;
+mod normal_map;
 mod object;
+mod orientation;
 mod pdf;
+mod photon_mapping;
+mod portal;
+mod principled;
 mod ray;
 mod rectangle;
 mod scenes;
@@ -16,9 +28,11 @@ mod simd;
 mod simd_bvh;
 mod sphere;
 mod texture;
+mod thin_film;
 mod transformations;
 mod triangle_mesh;
 mod utilities;
+mod visibility;
 
 use show_image::{event, ImageInfo, ImageView, WindowOptions};
 use utilities::vector3::Vector3;
@@ -32,7 +46,7 @@ const DEPTH: i32 = 50;
 
 use crate::{
     imaging::{bloom, tone_map},
-    integrator::World,
+    integrator::{Integrator, World},
     scenes::Scenes,
 };
 
@@ -50,11 +64,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "rect_light",
                     "cornell_box",
                     "volumes",
+                    "smoke",
+                    "fire",
+                    "mesh_volume",
+                    "liquid",
                     "balls",
                     "3Dmodel",
                     "david",
                     "sponza",
+                    "sponza_sky",
                     "teapots",
+                    "diamond",
+                    "principled",
+                    "frosted",
+                    "iridescence",
+                    "clearcoat",
+                    "subsurface",
+                    "bump",
+                    "cutout",
+                    "measured",
+                    "light_linking",
+                    "helmet",
                 ])
                 //.default_value("cornell_box"),
                 .default_value("3Dmodel"),
@@ -67,6 +97,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .possible_values(["480", "720", "1080"])
                 .default_value("480")
                 .validator(|a| a.parse::<u32>()),
+            arg!(-i --integrator <NAME>)
+                .help("Path tracing, bidirectional path tracing, progressive photon mapping or Metropolis")
+                .possible_values(["path", "bdpt", "ppm", "mlt"])
+                .default_value("path"),
+            arg!(-g --guiding <PASSES>)
+                .help("Path guiding training passes, 0 disables it")
+                .default_value("0")
+                .validator(|a| a.parse::<usize>()),
+            arg!(--"guide-fraction" <FRACTION>)
+                .help("Fraction of directions sampled from the path guide")
+                .default_value("0.5")
+                .validator(|a| a.parse::<f32>()),
+            arg!(--seed <SEED>)
+                .help("Seed for reproducible renders")
+                .required(false)
+                .validator(|a| a.parse::<u64>()),
+            arg!(--spectral)
+                .help("Trace wavelengths instead of RGB, for dispersion")
+                .required(false)
+                .takes_value(false),
             arg!(-d --denoising <oidn>)
                 .help("Intel OpenI mage Denoising")
                 .required(false)
@@ -82,11 +132,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some("rect_light") => Scenes::RectangleLight,
         Some("cornell_box") => Scenes::CornellBox,
         Some("volumes") => Scenes::Volumes,
+        Some("smoke") => Scenes::Smoke,
+        Some("fire") => Scenes::Fire,
+        Some("mesh_volume") => Scenes::MeshVolume,
+        Some("liquid") => Scenes::Liquid,
         Some("balls") => Scenes::Balls,
         Some("3Dmodel") => Scenes::Model3D,
         Some("david") => Scenes::David,
         Some("sponza") => Scenes::Sponza,
+        Some("sponza_sky") => Scenes::SponzaSky,
         Some("teapots") => Scenes::Teapots,
+        Some("diamond") => Scenes::Diamond,
+        Some("principled") => Scenes::Principled,
+        Some("frosted") => Scenes::Frosted,
+        Some("iridescence") => Scenes::Iridescence,
+        Some("clearcoat") => Scenes::Clearcoat,
+        Some("subsurface") => Scenes::Subsurface,
+        Some("bump") => Scenes::Bump,
+        Some("cutout") => Scenes::Cutout,
+        Some("measured") => Scenes::Measured,
+        Some("light_linking") => Scenes::LightLinking,
+        Some("helmet") => Scenes::Helmet,
+        _ => {
+            unreachable!()
+        }
+    };
+
+    let integrator = match commands.value_of("integrator") {
+        Some("path") => Integrator::PathTracing,
+        Some("bdpt") => Integrator::Bidirectional,
+        Some("ppm") => Integrator::PhotonMapping,
+        Some("mlt") => Integrator::Metropolis,
         _ => {
             unreachable!()
         }
@@ -105,6 +181,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .value_of_t("AA")
         .expect("'AA' is required and drawing will fail if its missing");
 
+    let guiding: usize = commands
+        .value_of_t("guiding")
+        .expect("'guiding' has a default value");
+    let guide_fraction: f32 = commands
+        .value_of_t("guide-fraction")
+        .expect("'guide-fraction' has a default value");
+
+    let spectral = commands.is_present("spectral");
+    if spectral
+        && matches!(
+            integrator,
+            Integrator::Bidirectional | Integrator::PhotonMapping
+        )
+    {
+        println!("Spectral rendering needs the path or mlt integrator, rendering in RGB");
+    }
+
     let do_denoising = commands.is_present("denoising");
     let mut pixel_data = vec![0.0; (width * height) as usize * 3];
     //let mut denoise_data = pixel_data.clone();
@@ -112,7 +205,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut output_data_no_blur = output_data.clone();
 
     let start = Instant::now();
-    let world = World::new(scene, width as f32, height as f32, aa, DEPTH);
+    let mut world = World::new(scene, width as f32, height as f32, aa, DEPTH, integrator)
+        .with_guiding(guiding, guide_fraction)
+        .with_spectral(spectral);
+    if let Ok(seed) = commands.value_of_t("seed") {
+        world = world.with_seed(seed);
+    }
     let duration = start.elapsed();
     println!("Time elapsed in building: {:?}", duration);
 
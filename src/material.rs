@@ -1,15 +1,31 @@
 #![allow(dead_code)]
 
+use std::sync::Arc;
+
 use crate::{
-    pdf::{PDFAshikhminShirley, PDFBlinnPhongSpec, PDFCosine, PDFSphere},
-    utilities::{math::fmax, onb::ONB},
+    microfacet::{dielectric_scattering, fresnel_conductor, fresnel_dielectric, GGX},
+    pdf::{
+        PDFAshikhminShirley, PDFBlinnPhongSpec, PDFConductor, PDFCosine, PDFHenyeyGreenstein,
+        PDFLayered, PDFRoughDielectric, PDFSphere,
+    },
+    utilities::{
+        color::Wavelengths,
+        math::{fmax, hash_float},
+        onb::ONB,
+    },
 };
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
 
 use crate::{
+    layered::Clearcoat,
+    measured::Measured,
+    normal_map::NormalMap,
+    orientation::Orientation,
     pdf::PDFType,
+    principled::Principled,
     ray::{HitRecord, Ray},
     texture::Texture,
+    thin_film::ThinFilm,
     utilities::{math::fmin, vector3::Vector3},
 };
 const PI: f32 = std::f32::consts::PI;
@@ -34,6 +50,10 @@ pub enum Material {
         absorption: f32,
         color: Vector3<f32>,
     },
+    //Dielectric whose index of refraction depends on the wavelength of spectral paths
+    DispersiveDielectric {
+        dispersion: Dispersion,
+    },
     DiffuseLight {
         texture: Texture,
     },
@@ -43,6 +63,11 @@ pub enum Material {
     Isotropic {
         color: Vector3<f32>,
     },
+    //Phase function of anisotropic media, g > 0 scatters forward and g < 0 backward
+    HenyeyGreenstein {
+        color: Vector3<f32>,
+        g: f32,
+    },
     BlinnPhong {
         color: Vector3<f32>,
         k_specular: f32,
@@ -67,6 +92,58 @@ pub enum Material {
         nu: f32,
         nv: f32,
     },
+    Principled {
+        bsdf: Arc<Principled>,
+    },
+    //Table of a measured BRDF, loaded with Measured::load
+    Measured {
+        brdf: Arc<Measured>,
+    },
+    //Microfacet dielectric that reflects and refracts through rough facets, like frosted glass
+    RoughDielectric {
+        index_of_refraction: f32,
+        roughness: Texture,
+    },
+    //Single surface sheet of glass, like a window pane or a bubble. Light goes through without
+    //offset and the reflections inside the sheet are summed up
+    ThinDielectric {
+        index_of_refraction: f32,
+    },
+    //Thin film coating over a dielectric, thin dielectric or conductor, other materials ignore it
+    Coated {
+        film: ThinFilm,
+        base: Box<Material>,
+    },
+    //Dielectric clearcoat over any base material. The coat is smooth over mirror-like bases and
+    //below a roughness of about 0.03
+    Layered {
+        coat: Clearcoat,
+        base: Box<Material>,
+    },
+    //Any material shaded with the normal bent by a normal or bump map
+    NormalMapped {
+        map: NormalMap,
+        base: Box<Material>,
+    },
+    //Any material with its anisotropy turned to an orientation
+    Oriented {
+        orientation: Orientation,
+        base: Box<Material>,
+    },
+    //Any material with an opacity mask, like leaves or lace. Rays go through it where it is
+    //cut out
+    Masked {
+        opacity: Opacity,
+        base: Box<Material>,
+    },
+    //GGX microfacet conductor, alpha_x and alpha_y below 1e-3 make a perfect mirror
+    Conductor {
+        ior: ComplexIor,
+        alpha_x: f32,
+        alpha_y: f32,
+    },
+    //Invisible boundary of a participating medium, rays pass straight through it
+    Interface,
 }
 
 impl Material {
@@ -74,7 +151,7 @@ impl Material {
         &self,
         r_in: &Ray,
         hit: &HitRecord,
-        rng: &mut ThreadRng,
+        rng: &mut impl Rng,
     ) -> Option<ScatterRecord> {
         match self {
             Material::Lambertian { albedo } => {
@@ -115,28 +192,36 @@ impl Material {
             Material::Dielectric {
                 index_of_refraction,
             } => {
-                let refraction_ratio = if hit.front_face {
+                //Tracked interiors know the indices on both sides
+                let refraction_ratio = hit.eta.unwrap_or(if hit.front_face {
                     1.0 / index_of_refraction
                 } else {
                     *index_of_refraction
-                };
-
-                let unit_direction = r_in.direction.norm();
-                let cos_theta = fmin(Vector3::dot(unit_direction * (-1.0), hit.normal), 1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                });
 
-                let cannot_refract = refraction_ratio * sin_theta > 1.0;
-
-                let direction = if cannot_refract
-                    || reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>()
-                {
-                    Vector3::reflect(unit_direction, hit.normal)
+                Some(ScatterRecord::Specular {
+                    specular_ray: Ray::new(
+                        hit.p,
+                        dielectric_direction(r_in, hit, refraction_ratio, rng),
+                    ),
+                    attenuation: Vector3::new(1.0, 1.0, 1.0),
+                })
+            }
+            Material::DispersiveDielectric { dispersion } => {
+                //Paths without a wavelength refract by the d line like a plain dielectric
+                let index_of_refraction =
+                    dispersion.index_of_refraction(r_in.wavelength.unwrap_or(587.6));
+                let refraction_ratio = hit.eta.unwrap_or(if hit.front_face {
+                    1.0 / index_of_refraction
                 } else {
-                    Vector3::refract(unit_direction, hit.normal, refraction_ratio)
-                };
+                    index_of_refraction
+                });
 
                 Some(ScatterRecord::Specular {
-                    specular_ray: Ray::new(hit.p, direction),
+                    specular_ray: Ray::new(
+                        hit.p,
+                        dielectric_direction(r_in, hit, refraction_ratio, rng),
+                    ),
                     attenuation: Vector3::new(1.0, 1.0, 1.0),
                 })
             }
@@ -145,35 +230,26 @@ impl Material {
                 absorption,
                 color,
             } => {
-                let refraction_ratio = if hit.front_face {
+                let refraction_ratio = hit.eta.unwrap_or(if hit.front_face {
                     1.0 / index_of_refraction
                 } else {
                     *index_of_refraction
-                };
+                });
 
-                let attenuation = if hit.front_face {
+                //Assumes the ray went straight through the object, tracked interiors absorb
+                //along the actual path instead
+                let attenuation = if hit.front_face || hit.eta.is_some() {
                     Vector3::new(1.0, 1.0, 1.0)
                 } else {
                     let dist = hit.t * r_in.direction.magnitude();
                     (*color * *absorption * dist * (-1.0)).exp()
                 };
 
-                let unit_direction = r_in.direction.norm();
-                let cos_theta = Vector3::dot(unit_direction * (-1.0), hit.normal);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-                let cannot_refract = refraction_ratio * sin_theta > 1.0;
-
-                let direction = if cannot_refract
-                    || reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>()
-                {
-                    Vector3::reflect(unit_direction, hit.normal)
-                } else {
-                    Vector3::refract(unit_direction, hit.normal, refraction_ratio)
-                };
-
                 Some(ScatterRecord::Specular {
-                    specular_ray: Ray::new(hit.p, direction),
+                    specular_ray: Ray::new(
+                        hit.p,
+                        dielectric_direction(r_in, hit, refraction_ratio, rng),
+                    ),
                     attenuation,
                 })
             }
@@ -186,6 +262,15 @@ impl Material {
                     attenuation: *color,
                 })
             }
+            Material::HenyeyGreenstein { color, g } => {
+                let pdf = PDFType::PDFHenyeyGreenstein {
+                    pdf: PDFHenyeyGreenstein::new(r_in.direction, *g),
+                };
+                Some(ScatterRecord::Scatter {
+                    pdf,
+                    attenuation: *color,
+                })
+            }
             Material::BlinnPhong {
                 color,
                 k_specular,
@@ -220,7 +305,7 @@ impl Material {
                 let pdf = PDFType::PDFAshikhminShirley {
                     pdf: PDFAshikhminShirley::new(
                         r_in.direction,
-                        hit.normal,
+                        hit.shading_frame(),
                         *nu,
                         *nv,
                         *k_specular,
@@ -241,7 +326,7 @@ impl Material {
                 let pdf = PDFType::PDFAshikhminShirley {
                     pdf: PDFAshikhminShirley::new(
                         r_in.direction,
-                        hit.normal,
+                        hit.shading_frame(),
                         *nu,
                         *nv,
                         *k_specular,
@@ -252,10 +337,279 @@ impl Material {
                     attenuation: texture.value(hit.u, hit.v, hit.p),
                 })
             }
+            Material::Conductor {
+                ior,
+                alpha_x,
+                alpha_y,
+            } => Some(conductor_scatter(r_in, hit, *alpha_x, *alpha_y, |cosine| {
+                fresnel_conductor(cosine, ior.eta, ior.k)
+            })),
+            Material::ThinDielectric {
+                index_of_refraction,
+            } => {
+                let cosine = Vector3::dot(r_in.direction.norm(), hit.normal).abs();
+                let reflectance = fresnel_dielectric(cosine, *index_of_refraction);
+                Some(thin_dielectric_scatter(
+                    r_in,
+                    hit,
+                    Vector3::new(1.0, 1.0, 1.0) * (2.0 * reflectance / (1.0 + reflectance)),
+                    rng,
+                ))
+            }
+            Material::Layered { coat, base } => {
+                let unit_direction = r_in.direction.norm();
+                let cos_o = Vector3::dot(unit_direction * (-1.0), hit.normal);
+                match base.scatter(r_in, hit, rng)? {
+                    ScatterRecord::Specular {
+                        specular_ray,
+                        attenuation,
+                    } => {
+                        let chance = coat.coat_chance(cos_o, attenuation);
+                        if rng.gen::<f32>() < chance {
+                            return Some(ScatterRecord::Specular {
+                                specular_ray: Ray::new(
+                                    hit.p,
+                                    Vector3::reflect(unit_direction, hit.normal),
+                                ),
+                                attenuation: Vector3::new(1.0, 1.0, 1.0)
+                                    * (coat.fresnel(cos_o) / chance),
+                            });
+                        }
+                        let cos_i = Vector3::dot(specular_ray.direction.norm(), hit.normal);
+                        Some(ScatterRecord::Specular {
+                            specular_ray,
+                            attenuation: attenuation * layered_transmittance(coat, cos_o, cos_i)
+                                / (1.0 - chance),
+                        })
+                    }
+                    ScatterRecord::Scatter { pdf, attenuation }
+                    | ScatterRecord::SpecularDiffuse { pdf, attenuation } => {
+                        //A smooth coat is picked like a mirror, the base then leaves out the
+                        //light the coat would have reflected
+                        if coat.is_smooth(hit) {
+                            let chance = coat.coat_chance(cos_o, attenuation);
+                            if rng.gen::<f32>() < chance {
+                                return Some(ScatterRecord::Specular {
+                                    specular_ray: Ray::new(
+                                        hit.p,
+                                        Vector3::reflect(unit_direction, hit.normal),
+                                    ),
+                                    attenuation: Vector3::new(1.0, 1.0, 1.0)
+                                        * (coat.fresnel(cos_o) / chance),
+                                });
+                            }
+                            return Some(ScatterRecord::SpecularDiffuse { pdf, attenuation });
+                        }
+                        let coat_pdf = PDFType::PDFConductor {
+                            pdf: PDFConductor::new(
+                                r_in.direction,
+                                hit.shading_frame(),
+                                coat.distribution(hit),
+                            ),
+                        };
+                        Some(ScatterRecord::SpecularDiffuse {
+                            pdf: PDFType::PDFLayered {
+                                pdf: PDFLayered::new(
+                                    coat_pdf,
+                                    pdf,
+                                    coat.coat_chance(cos_o, attenuation),
+                                ),
+                            },
+                            attenuation,
+                        })
+                    }
+                }
+            }
+            Material::Coated { film, base } => {
+                let thickness = film.thickness(hit);
+                let unit_direction = r_in.direction.norm();
+                let cosine = Vector3::dot(unit_direction * (-1.0), hit.normal).min(1.0);
+                match base.as_ref() {
+                    Material::Dielectric {
+                        index_of_refraction,
+                    } => {
+                        //The film is on the outside of the dielectric, light reaches it first
+                        //from the index on the incident side
+                        let eta = relative_ior(hit, *index_of_refraction);
+                        let incident = if hit.front_face {
+                            index_of_refraction / eta
+                        } else {
+                            *index_of_refraction
+                        };
+                        let reflectance = film.dielectric_reflectance(
+                            thickness,
+                            cosine,
+                            incident,
+                            incident * eta,
+                            r_in.wavelength,
+                        );
+                        let cannot_refract = (1.0 - cosine * cosine) > eta * eta;
+                        Some(fresnel_scatter(
+                            hit,
+                            Vector3::reflect(unit_direction, hit.normal),
+                            (!cannot_refract)
+                                .then(|| Vector3::refract(unit_direction, hit.normal, 1.0 / eta)),
+                            reflectance,
+                            rng,
+                        ))
+                    }
+                    Material::ThinDielectric {
+                        index_of_refraction,
+                    } => {
+                        //The coated face reflects like the film over the dielectric, the other
+                        //face like the bare dielectric. Both sides of the sheet are in air
+                        let front = film.dielectric_reflectance(
+                            thickness,
+                            cosine,
+                            1.0,
+                            *index_of_refraction,
+                            r_in.wavelength,
+                        );
+                        let back = fresnel_dielectric(cosine, *index_of_refraction);
+                        let sheet = |front: f32| {
+                            if front * back >= 1.0 {
+                                return 1.0;
+                            }
+                            front + (1.0 - front) * (1.0 - front) * back / (1.0 - front * back)
+                        };
+                        let reflectance =
+                            Vector3::new(sheet(front.x), sheet(front.y), sheet(front.z));
+                        Some(thin_dielectric_scatter(r_in, hit, reflectance, rng))
+                    }
+                    Material::Conductor {
+                        ior,
+                        alpha_x,
+                        alpha_y,
+                    } => Some(conductor_scatter(r_in, hit, *alpha_x, *alpha_y, |cosine| {
+                        film.conductor_reflectance(thickness, cosine, ior, r_in.wavelength)
+                    })),
+                    base => base.scatter(r_in, hit, rng),
+                }
+            }
+            Material::RoughDielectric {
+                index_of_refraction,
+                roughness,
+            } => {
+                let pdf = PDFType::PDFRoughDielectric {
+                    pdf: PDFRoughDielectric::new(
+                        r_in.direction,
+                        hit.normal,
+                        rough_distribution(roughness, hit),
+                        relative_ior(hit, *index_of_refraction),
+                    ),
+                };
+                Some(ScatterRecord::SpecularDiffuse {
+                    pdf,
+                    attenuation: Vector3::new(1.0, 1.0, 1.0),
+                })
+            }
+            Material::Principled { bsdf } => {
+                let attenuation = bsdf.base_color(hit);
+                let pdf = PDFType::PDFPrincipled {
+                    pdf: bsdf.at(hit, attenuation, r_in.direction),
+                };
+                Some(ScatterRecord::SpecularDiffuse { pdf, attenuation })
+            }
+            Material::Measured { brdf } => Some(ScatterRecord::SpecularDiffuse {
+                pdf: PDFType::PDFMeasured {
+                    pdf: brdf.at(hit, r_in.direction),
+                },
+                attenuation: Vector3::new(1.0, 1.0, 1.0),
+            }),
+            Material::NormalMapped { map, base } => base.scatter(r_in, &map.apply(r_in, hit), rng),
+            Material::Masked { opacity: _, base } => base.scatter(r_in, hit, rng),
+            Material::Oriented { orientation, base } => {
+                base.scatter(r_in, &orientation.apply(hit), rng)
+            }
             _ => None,
         }
     }
 
+    //Coats a dielectric, thin dielectric or conductor with a thin film
+    pub fn with_film(self, film: ThinFilm) -> Self {
+        Material::Coated {
+            film,
+            base: Box::new(self),
+        }
+    }
+
+    pub fn with_normal_map(self, map: NormalMap) -> Self {
+        Material::NormalMapped {
+            map,
+            base: Box::new(self),
+        }
+    }
+
+    pub fn with_orientation(self, orientation: Orientation) -> Self {
+        Material::Oriented {
+            orientation,
+            base: Box::new(self),
+        }
+    }
+
+    //Fractional opacities let rays through at random, in proportion to their transparency
+    pub fn with_opacity(self, opacity: impl Into<Texture>) -> Self {
+        Material::Masked {
+            opacity: Opacity {
+                texture: opacity.into(),
+                cutoff: None,
+            },
+            base: Box::new(self),
+        }
+    }
+
+    //Cuts the material out where the opacity is below the cutoff
+    pub fn with_cutout(self, opacity: impl Into<Texture>, cutoff: f32) -> Self {
+        Material::Masked {
+            opacity: Opacity {
+                texture: opacity.into(),
+                cutoff: Some(cutoff),
+            },
+            base: Box::new(self),
+        }
+    }
+
+    //Whether a ray stops at the hit or goes on through a hole in the opacity mask
+    #[inline(always)]
+    pub fn opaque(&self, r: &Ray, hit: &HitRecord) -> bool {
+        match self {
+            Material::Masked { opacity, base } => opacity.opaque(r, hit) && base.opaque(r, hit),
+            Material::NormalMapped { map: _, base }
+            | Material::Oriented {
+                orientation: _,
+                base,
+            } => base.opaque(r, hit),
+            _ => true,
+        }
+    }
+
+    //Covers the material with a dielectric clearcoat
+    pub fn with_clearcoat(self, coat: Clearcoat) -> Self {
+        Material::Layered {
+            coat,
+            base: Box::new(self),
+        }
+    }
+
+    //Blend materials pick one of their components per scattering event. Integrators that
+    //evaluate the BSDF of a vertex again resolve the choice first so sampling and evaluation agree
+    pub fn resolve(&self, rng: &mut impl Rng) -> &Material {
+        match self {
+            Material::Blend {
+                material1,
+                material2,
+                ratio,
+            } => {
+                if rng.gen::<f32>() < *ratio {
+                    material1.resolve(rng)
+                } else {
+                    material2.resolve(rng)
+                }
+            }
+            _ => self,
+        }
+    }
+
     pub fn eval_brdf(
         &self,
         r_in: &Ray,
@@ -271,6 +625,11 @@ impl Material {
 
             Material::Isotropic { color: _ } => attenuation / (4.0 * PI),
 
+            Material::HenyeyGreenstein { color: _, g } => {
+                let cosine = Vector3::dot(r_in.direction.norm(), scattered.direction.norm());
+                attenuation * henyey_greenstein(cosine, *g)
+            }
+
             Material::BlinnPhong {
                 color,
                 k_specular,
@@ -310,7 +669,7 @@ impl Material {
 
                 let r_s_corr = *r_s * *k_specular;
                 let r_d_corr = attenuation;
-                let onb_normal = ONB::build_from(hit.normal);
+                let onb_normal = hit.shading_frame();
 
                 let hn = Vector3::dot(h, hit.normal);
                 let vn = Vector3::dot(hit.normal, v);
@@ -338,6 +697,67 @@ impl Material {
                     * (1.0 - (1.0 - Vector3::dot(hit.normal, l) / 2.0).powi(5));
                 (diffuse_brdf + specular_brdf) * Vector3::dot(hit.normal, l)
             }
+            Material::Conductor {
+                ior,
+                alpha_x,
+                alpha_y,
+            } => conductor_eval(r_in, hit, scattered, *alpha_x, *alpha_y, |cosine| {
+                fresnel_conductor(cosine, ior.eta, ior.k)
+            }),
+            Material::Layered { coat, base } => {
+                let cos_o = Vector3::dot(r_in.direction.norm() * (-1.0), hit.normal);
+                let cos_i = Vector3::dot(scattered.direction.norm(), hit.normal);
+                let under = base.eval_brdf(r_in, hit, attenuation, scattered)
+                    * layered_transmittance(coat, cos_o, cos_i);
+                //The base is only sampled when a smooth coat is not, see scatter
+                if coat.is_smooth(hit) {
+                    return under / (1.0 - coat.coat_chance(cos_o, attenuation));
+                }
+                let distribution = coat.distribution(hit);
+                let (alpha_x, alpha_y) = (distribution.alpha_x, distribution.alpha_y);
+                under
+                    + conductor_eval(r_in, hit, scattered, alpha_x, alpha_y, |cosine| {
+                        Vector3::new(1.0, 1.0, 1.0) * coat.fresnel(cosine)
+                    })
+            }
+            Material::Coated { film, base } => match base.as_ref() {
+                Material::Conductor {
+                    ior,
+                    alpha_x,
+                    alpha_y,
+                } => {
+                    let thickness = film.thickness(hit);
+                    conductor_eval(r_in, hit, scattered, *alpha_x, *alpha_y, |cosine| {
+                        film.conductor_reflectance(thickness, cosine, ior, r_in.wavelength)
+                    })
+                }
+                base => base.eval_brdf(r_in, hit, attenuation, scattered),
+            },
+            Material::RoughDielectric {
+                index_of_refraction,
+                roughness,
+            } => {
+                let onb = ONB::build_from(hit.normal);
+                let wo = onb.to_local(r_in.direction.norm() * (-1.0));
+                let wi = onb.to_local(scattered.direction.norm());
+                let eta = relative_ior(hit, *index_of_refraction);
+                let distribution = rough_distribution(roughness, hit);
+                let f = dielectric_scattering(&distribution, wo, wi, eta).map_or(0.0, |s| s.value);
+                Vector3::new(1.0, 1.0, 1.0) * f
+            }
+            Material::Principled { bsdf } => bsdf
+                .at(hit, attenuation, r_in.direction)
+                .eval(scattered.direction),
+            Material::Measured { brdf } => brdf.at(hit, r_in.direction).eval(scattered.direction),
+            Material::NormalMapped { map, base } => {
+                base.eval_brdf(r_in, &map.apply(r_in, hit), attenuation, scattered)
+            }
+            Material::Masked { opacity: _, base } => {
+                base.eval_brdf(r_in, hit, attenuation, scattered)
+            }
+            Material::Oriented { orientation, base } => {
+                base.eval_brdf(r_in, &orientation.apply(hit), attenuation, scattered)
+            }
             _ => Vector3::new(1.0, 1.0, 1.0),
         }
     }
@@ -353,9 +773,74 @@ impl Material {
                 }
             }
             Material::Hdri { texture } => texture.value(u, v, p),
+            Material::NormalMapped { map: _, base }
+            | Material::Masked { opacity: _, base }
+            | Material::Oriented {
+                orientation: _,
+                base,
+            } => base.emit(u, v, p, front_face),
             _ => Vector3::new(0.0, 0.0, 0.0),
         }
     }
+    //Emission at the wavelengths of a spectral path, blackbodies follow Planck's law exactly
+    pub fn emit_spectrum(
+        &self,
+        u: f32,
+        v: f32,
+        p: Vector3<f32>,
+        front_face: bool,
+        wavelengths: &Wavelengths,
+    ) -> Vector3<f32> {
+        match self {
+            Material::DiffuseLight {
+                texture:
+                    Texture::Blackbody {
+                        kelvin,
+                        color: _,
+                        scale,
+                    },
+            } => {
+                if front_face {
+                    wavelengths.planck(*kelvin) * *scale
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                }
+            }
+            _ => wavelengths.upsample(self.emit(u, v, p, front_face)),
+        }
+    }
+    //Phase functions of participating media, which scatter in every direction
+    pub fn is_medium(&self) -> bool {
+        matches!(
+            self,
+            Material::Isotropic { color: _ } | Material::HenyeyGreenstein { color: _, g: _ }
+        )
+    }
+    //Materials that send each wavelength of a spectral path in a different direction, or whose
+    //films are only evaluated at the hero wavelength
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Coated { film: _, base } => matches!(
+                base.as_ref(),
+                Material::Dielectric {
+                    index_of_refraction: _
+                } | Material::ThinDielectric {
+                    index_of_refraction: _
+                } | Material::Conductor {
+                    ior: _,
+                    alpha_x: _,
+                    alpha_y: _
+                }
+            ),
+            Material::NormalMapped { map: _, base }
+            | Material::Masked { opacity: _, base }
+            | Material::Oriented {
+                orientation: _,
+                base,
+            } => base.is_dispersive(),
+            _ => matches!(self, Material::DispersiveDielectric { dispersion: _ }),
+        }
+    }
     #[inline(always)]
     pub fn textured(&self) -> bool {
         matches!(
@@ -363,6 +848,22 @@ impl Material {
             Material::TexturedLambertian { texture: _ }
                 | Material::DiffuseLight { texture: _ }
                 | Material::Hdri { texture: _ }
+                | Material::Principled { bsdf: _ }
+                | Material::Coated { film: _, base: _ }
+                | Material::Layered { coat: _, base: _ }
+                | Material::NormalMapped { map: _, base: _ }
+                | Material::Masked {
+                    opacity: _,
+                    base: _
+                }
+                | Material::Oriented {
+                    orientation: _,
+                    base: _
+                }
+                | Material::RoughDielectric {
+                    index_of_refraction: _,
+                    roughness: _
+                }
                 | Material::TexturedAshikhminShirley {
                     texture: _,
                     r_s: _,
@@ -382,6 +883,240 @@ impl Default for Material {
     }
 }
 
+//Complex index of refraction eta + i k of a conductor, per RGB channel
+#[derive(Clone, Copy)]
+pub struct ComplexIor {
+    pub eta: Vector3<f32>,
+    pub k: Vector3<f32>,
+}
+
+impl ComplexIor {
+    pub fn gold() -> Self {
+        Self {
+            eta: Vector3::new(0.143, 0.374, 1.442),
+            k: Vector3::new(3.983, 2.385, 1.603),
+        }
+    }
+
+    pub fn copper() -> Self {
+        Self {
+            eta: Vector3::new(0.200, 0.924, 1.102),
+            k: Vector3::new(3.912, 2.452, 2.142),
+        }
+    }
+
+    pub fn silver() -> Self {
+        Self {
+            eta: Vector3::new(0.155, 0.117, 0.138),
+            k: Vector3::new(4.828, 3.122, 2.147),
+        }
+    }
+
+    pub fn aluminum() -> Self {
+        Self {
+            eta: Vector3::new(1.657, 0.880, 0.521),
+            k: Vector3::new(9.224, 6.270, 4.837),
+        }
+    }
+}
+
+//Opacity read from the first channel of a texture. With a cutoff, surfaces are solid at or above
+//it and cut out below it. Without one, a ray goes through with a chance of the transparency,
+//picked by hashing the ray and the distance so that no random numbers are needed
+#[derive(Clone)]
+pub struct Opacity {
+    texture: Texture,
+    cutoff: Option<f32>,
+}
+
+impl Opacity {
+    #[inline(always)]
+    pub fn opaque(&self, r: &Ray, hit: &HitRecord) -> bool {
+        let opacity = self.texture.value(hit.u, hit.v, hit.p).x;
+        match self.cutoff {
+            Some(cutoff) => opacity >= cutoff,
+            None if opacity >= 1.0 => true,
+            None if opacity <= 0.0 => false,
+            None => {
+                let (o, d) = (r.origin, r.direction);
+                hash_float(&[o.x, o.y, o.z, d.x, d.y, d.z, hit.t]) < opacity
+            }
+        }
+    }
+}
+
+//Dispersion formulas of the index of refraction, with wavelengths in um
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    //n = a + b / lambda^2
+    Cauchy { a: f32, b: f32 },
+    //n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn diamond() -> Self {
+        Self::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030625, 0.011236, 0.0],
+        }
+    }
+
+    pub fn bk7() -> Self {
+        Self::Sellmeier {
+            b: [1.039_612, 0.231_792_3, 1.010_469],
+            c: [0.006_000_7, 0.020_017_9, 103.560_6],
+        }
+    }
+
+    pub fn index_of_refraction(&self, lambda_nm: f32) -> f32 {
+        let lambda2 = (lambda_nm * 1e-3).powi(2);
+        match self {
+            Self::Cauchy { a, b } => a + b / lambda2,
+            Self::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c)
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum::<f32>())
+            .sqrt(),
+        }
+    }
+}
+
+//Index of refraction on the other side of the surface over the one on the side of the ray.
+//Tracked interiors know the indices on both sides
+pub fn relative_ior(hit: &HitRecord, index_of_refraction: f32) -> f32 {
+    match hit.eta {
+        Some(eta) => 1.0 / eta,
+        None if hit.front_face => index_of_refraction,
+        None => 1.0 / index_of_refraction,
+    }
+}
+
+fn rough_distribution(roughness: &Texture, hit: &HitRecord) -> GGX {
+    GGX::from_roughness(roughness.value(hit.u, hit.v, hit.p).x.clamp(0.0, 1.0), 0.0)
+}
+
+//Mirror reflection of a smooth conductor, or GGX reflection sampled from the visible normals.
+//fresnel gives the reflectance by the cosine to the normal
+fn conductor_scatter<'a>(
+    r_in: &Ray,
+    hit: &HitRecord,
+    alpha_x: f32,
+    alpha_y: f32,
+    fresnel: impl Fn(f32) -> Vector3<f32>,
+) -> ScatterRecord<'a> {
+    if alpha_x.max(alpha_y) < 1e-3 {
+        let unit_direction = r_in.direction.norm();
+        let cos_theta = Vector3::dot(unit_direction * (-1.0), hit.normal);
+        return ScatterRecord::Specular {
+            specular_ray: Ray::new(hit.p, Vector3::reflect(unit_direction, hit.normal)),
+            attenuation: fresnel(cos_theta),
+        };
+    }
+    let pdf = PDFType::PDFConductor {
+        pdf: PDFConductor::new(
+            r_in.direction,
+            hit.shading_frame(),
+            GGX::new(alpha_x, alpha_y),
+        ),
+    };
+    ScatterRecord::SpecularDiffuse {
+        pdf,
+        attenuation: Vector3::new(1.0, 1.0, 1.0),
+    }
+}
+
+fn conductor_eval(
+    r_in: &Ray,
+    hit: &HitRecord,
+    scattered: &Ray,
+    alpha_x: f32,
+    alpha_y: f32,
+    fresnel: impl Fn(f32) -> Vector3<f32>,
+) -> Vector3<f32> {
+    let onb = hit.shading_frame();
+    let wo = onb.to_local(r_in.direction.norm() * (-1.0));
+    let wi = onb.to_local(scattered.direction.norm());
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+    let h = (wo + wi).norm();
+    let distribution = GGX::new(alpha_x, alpha_y);
+    fresnel(Vector3::dot(wo, h)) * (distribution.d(h) * distribution.g(wo, wi) / (4.0 * wo.z))
+}
+
+//Light through a coat on the way to the base and, unless the base transmits it, back out
+fn layered_transmittance(coat: &Clearcoat, cos_o: f32, cos_i: f32) -> Vector3<f32> {
+    let inward = coat.transmittance(cos_o);
+    if cos_i > 0.0 {
+        inward * coat.transmittance(cos_i)
+    } else {
+        inward
+    }
+}
+
+//Reflection or transmission picked by the average of a colored reflectance, weighted so that each
+//channel gets its own share. No transmitted direction means total internal reflection
+fn fresnel_scatter<'a>(
+    hit: &HitRecord,
+    reflected: Vector3<f32>,
+    transmitted: Option<Vector3<f32>>,
+    reflectance: Vector3<f32>,
+    rng: &mut impl Rng,
+) -> ScatterRecord<'a> {
+    let chance = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+    let (direction, attenuation) = match transmitted {
+        Some(transmitted) if rng.gen::<f32>() >= chance => (
+            transmitted,
+            (Vector3::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - chance),
+        ),
+        Some(_) => (reflected, reflectance / chance),
+        None => (reflected, Vector3::new(1.0, 1.0, 1.0)),
+    };
+    ScatterRecord::Specular {
+        specular_ray: Ray::new(hit.p, direction),
+        attenuation,
+    }
+}
+
+//Thin sheets reflect off their surface or let light through along the same direction
+fn thin_dielectric_scatter<'a>(
+    r_in: &Ray,
+    hit: &HitRecord,
+    reflectance: Vector3<f32>,
+    rng: &mut impl Rng,
+) -> ScatterRecord<'a> {
+    let unit_direction = r_in.direction.norm();
+    fresnel_scatter(
+        hit,
+        Vector3::reflect(unit_direction, hit.normal),
+        Some(unit_direction),
+        reflectance,
+        rng,
+    )
+}
+
+//Reflected or refracted direction of a smooth dielectric, picked by Schlick's Fresnel term
+fn dielectric_direction(
+    r_in: &Ray,
+    hit: &HitRecord,
+    refraction_ratio: f32,
+    rng: &mut impl Rng,
+) -> Vector3<f32> {
+    let unit_direction = r_in.direction.norm();
+    let cos_theta = fmin(Vector3::dot(unit_direction * (-1.0), hit.normal), 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+    let cannot_refract = refraction_ratio * sin_theta > 1.0;
+
+    if cannot_refract || reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>() {
+        Vector3::reflect(unit_direction, hit.normal)
+    } else {
+        Vector3::refract(unit_direction, hit.normal, refraction_ratio)
+    }
+}
+
 fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 * r0 + (1.0 - r0 * r0) * (1.0 - cosine).powi(5)
@@ -406,3 +1141,303 @@ pub enum ScatterRecord<'a> {
         attenuation: Vector3<f32>,
     },
 }
+
+//Density of scattering by the angle whose cosine is given, against the propagation direction
+pub fn henyey_greenstein(cosine: f32, g: f32) -> f32 {
+    let denominator = 1.0 + g * g - 2.0 * g * cosine;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    const SAMPLES: usize = 200_000;
+
+    //Runs f on a hit at the origin of a surface facing +z, lit on its front face from the angle
+    //whose cosine is given
+    fn at_hit<T>(material: &Material, cosine: f32, f: impl FnOnce(&Ray, &HitRecord) -> T) -> T {
+        let sine = (1.0 - cosine * cosine).sqrt();
+        let r_in = Ray::new(
+            Vector3::new(sine, 0.0, cosine),
+            Vector3::new(-sine, 0.0, -cosine),
+        );
+        let hit = HitRecord::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            1.0,
+            0.5,
+            0.5,
+            &r_in,
+            material,
+        );
+        f(&r_in, &hit)
+    }
+
+    fn wide(v: Vector3<f32>) -> Vector3<f64> {
+        Vector3::new(v.x as f64, v.y as f64, v.z as f64)
+    }
+
+    //Checks the BSDF of a material seen from the angle whose cosine is given. Its density
+    //integrates to one, counting the samples lost as the zero direction, it gives back no more
+    //light than it gets under a white furnace, and sampling it agrees with integrating it over
+    //uniform directions
+    pub fn check_bsdf(material: &Material, cosine: f32) {
+        let mut rng = SmallRng::seed_from_u64(1);
+        at_hit(material, cosine, |r_in, hit| {
+            let (pdf, attenuation, scatter) = match material.scatter(r_in, hit, &mut rng) {
+                Some(ScatterRecord::SpecularDiffuse { pdf, attenuation }) => {
+                    (pdf, attenuation, false)
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation }) => (pdf, attenuation, true),
+                _ => panic!("the material has no density to check"),
+            };
+            //BSDF times the cosine, which Scatter records fold into the density
+            let f = |direction: Vector3<f32>| {
+                if scatter {
+                    attenuation * pdf.value(direction)
+                } else {
+                    material.eval_brdf(r_in, hit, attenuation, &Ray::new(hit.p, direction))
+                }
+            };
+
+            //Sums are kept in f64, rounding in f32 sums this long biases them by percents
+            let mut integral = 0.0;
+            let mut uniform = Vector3::new(0.0, 0.0, 0.0);
+            for _ in 0..SAMPLES {
+                let direction = Vector3::random_unit_vector(&mut rng);
+                integral += pdf.value(direction) as f64;
+                uniform += wide(f(direction));
+            }
+            let integral = integral * 4.0 * PI as f64 / SAMPLES as f64;
+            let uniform = uniform * (4.0 * PI as f64 / SAMPLES as f64);
+
+            let mut lost = 0;
+            let mut sampled = Vector3::new(0.0, 0.0, 0.0);
+            for _ in 0..SAMPLES {
+                let direction = pdf.sample(&mut rng);
+                let pdf_value = pdf.value(direction);
+                if direction.near_zero() || pdf_value <= 0.0 {
+                    lost += 1;
+                } else {
+                    sampled += wide(f(direction) / pdf_value);
+                }
+            }
+            let lost = lost as f64 / SAMPLES as f64;
+            let sampled = sampled / SAMPLES as f64;
+
+            assert!(
+                (integral + lost - 1.0).abs() < 0.03,
+                "density integrates to {} with {} of the samples lost",
+                integral,
+                lost
+            );
+            for (sampled, uniform) in sampled.to_array().into_iter().zip(uniform.to_array()) {
+                assert!(sampled <= 1.01, "albedo {} above one", sampled);
+                assert!(
+                    (sampled - uniform).abs() < 0.03,
+                    "sampled albedo {} against {} integrated uniformly",
+                    sampled,
+                    uniform
+                );
+            }
+        })
+    }
+
+    #[test]
+    fn conductor() {
+        for (alpha_x, alpha_y) in [(0.3, 0.3), (0.5, 0.1)] {
+            for cosine in [0.9, 0.4] {
+                let material = Material::Conductor {
+                    ior: ComplexIor::gold(),
+                    alpha_x,
+                    alpha_y,
+                };
+                check_bsdf(&material, cosine);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric() {
+        for roughness in [0.6, 0.8] {
+            for cosine in [0.9, 0.4] {
+                let material = Material::RoughDielectric {
+                    index_of_refraction: 1.5,
+                    roughness: roughness.into(),
+                };
+                check_bsdf(&material, cosine);
+            }
+        }
+    }
+
+    #[test]
+    fn principled() {
+        let materials = [
+            Principled::new(Vector3::new(0.8, 0.4, 0.2))
+                .with_roughness(0.6)
+                .with_sheen(0.5),
+            Principled::new(Vector3::new(0.9, 0.9, 0.9))
+                .with_metallic(1.0)
+                .with_roughness(0.6)
+                .with_anisotropic(0.5),
+            Principled::new(Vector3::new(0.9, 0.9, 0.9))
+                .with_roughness(0.7)
+                .with_transmission(1.0)
+                .with_ior(1.5),
+        ];
+        for bsdf in materials {
+            let material = Material::Principled {
+                bsdf: Arc::new(bsdf),
+            };
+            for cosine in [0.9, 0.4] {
+                check_bsdf(&material, cosine);
+            }
+        }
+    }
+
+    #[test]
+    fn coated() {
+        for thickness in [0.0, 0.3, 0.6] {
+            let material = Material::Conductor {
+                ior: ComplexIor::copper(),
+                alpha_x: 0.4,
+                alpha_y: 0.4,
+            }
+            .with_film(ThinFilm::new(thickness, 1.33));
+            for cosine in [0.9, 0.4] {
+                check_bsdf(&material, cosine);
+            }
+        }
+    }
+
+    #[test]
+    fn coated_dielectric_between_equal_indices() {
+        //A film matching the index on both sides of it does not reflect
+        let material = Material::Dielectric {
+            index_of_refraction: 1.5,
+        }
+        .with_film(ThinFilm::new(0.3, 1.5));
+        let mut rng = SmallRng::seed_from_u64(1);
+        at_hit(&material, 0.6, |r_in, hit| {
+            let hit = HitRecord {
+                eta: Some(1.0),
+                ..*hit
+            };
+            for _ in 0..1000 {
+                match material.scatter(r_in, &hit, &mut rng) {
+                    Some(ScatterRecord::Specular {
+                        specular_ray,
+                        attenuation,
+                    }) => {
+                        let bent = specular_ray.direction.norm() - r_in.direction.norm();
+                        assert!(bent.magnitude() < 1e-4, "the ray is bent or reflected");
+                        assert!((attenuation - Vector3::new(1.0, 1.0, 1.0)).magnitude() < 1e-4);
+                    }
+                    _ => panic!("the coated dielectric is not specular"),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn coated_at_the_hero_wavelength() {
+        let material = Material::Conductor {
+            ior: ComplexIor::copper(),
+            alpha_x: 0.4,
+            alpha_y: 0.4,
+        }
+        .with_film(ThinFilm::new(0.3, 1.33));
+        at_hit(&material, 0.8, |r_in, hit| {
+            let r_in = Ray {
+                wavelength: Some(550.0),
+                ..*r_in
+            };
+            let scattered = Ray::new(hit.p, Vector3::new(0.3, 0.2, 0.9).norm());
+            let f = material.eval_brdf(&r_in, hit, Vector3::new(1.0, 1.0, 1.0), &scattered);
+            assert!(f.x > 0.0 && f.x == f.y && f.y == f.z);
+        })
+    }
+
+    #[test]
+    fn layered() {
+        let bases = [
+            Material::Lambertian {
+                albedo: Vector3::new(0.9, 0.5, 0.2),
+            },
+            Material::Conductor {
+                ior: ComplexIor::gold(),
+                alpha_x: 0.5,
+                alpha_y: 0.5,
+            },
+        ];
+        for base in bases {
+            let material = base.with_clearcoat(
+                Clearcoat::new(1.5)
+                    .with_roughness(0.6)
+                    .with_tint(Vector3::new(0.8, 0.9, 1.0)),
+            );
+            for cosine in [0.9, 0.4] {
+                check_bsdf(&material, cosine);
+            }
+        }
+    }
+
+    //Light a material sends back under a white furnace, estimated by sampling it
+    fn sampled_albedo(material: &Material, cosine: f32) -> (Vector3<f32>, usize) {
+        let mut rng = SmallRng::seed_from_u64(1);
+        at_hit(material, cosine, |r_in, hit| {
+            let mut albedo = Vector3::new(0.0, 0.0, 0.0);
+            let mut specular = 0;
+            for _ in 0..SAMPLES {
+                match material.scatter(r_in, hit, &mut rng) {
+                    Some(ScatterRecord::Specular { attenuation, .. }) => {
+                        albedo += attenuation;
+                        specular += 1;
+                    }
+                    Some(ScatterRecord::SpecularDiffuse { pdf, attenuation }) => {
+                        let direction = pdf.sample(&mut rng);
+                        let pdf_value = pdf.value(direction);
+                        if !direction.near_zero() && pdf_value > 0.0 {
+                            let scattered = Ray::new(hit.p, direction);
+                            albedo +=
+                                material.eval_brdf(r_in, hit, attenuation, &scattered) / pdf_value;
+                        }
+                    }
+                    _ => panic!("unexpected scattering record"),
+                }
+            }
+            (albedo / SAMPLES as f32, specular)
+        })
+    }
+
+    #[test]
+    fn smooth_coat_is_specular() {
+        let base = Material::Lambertian {
+            albedo: Vector3::new(0.9, 0.5, 0.2),
+        };
+        for cosine in [0.9, 0.4] {
+            let (smooth, specular) =
+                sampled_albedo(&base.clone().with_clearcoat(Clearcoat::new(1.5)), cosine);
+            assert!(specular > 0, "the smooth coat never reflects like a mirror");
+            //A coat barely rough enough for GGX reflects about the same light
+            let (rough, _) = sampled_albedo(
+                &base
+                    .clone()
+                    .with_clearcoat(Clearcoat::new(1.5).with_roughness(0.04)),
+                cosine,
+            );
+            for (smooth, rough) in smooth.to_array().into_iter().zip(rough.to_array()) {
+                assert!(smooth <= 1.01, "albedo {} above one", smooth);
+                assert!(
+                    (smooth - rough).abs() < 0.02,
+                    "smooth coat albedo {} against {} for a nearly smooth one",
+                    smooth,
+                    rough
+                );
+            }
+        }
+    }
+}
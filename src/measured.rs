@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{
+    background::Distribution1D,
+    microfacet::reflect,
+    ray::HitRecord,
+    utilities::{onb::ONB, vector3::Vector3},
+};
+
+const PI: f32 = std::f32::consts::PI;
+//Resolution of the MERL tables, phi_d only covers half a turn thanks to reciprocity
+const THETA_H: usize = 90;
+const THETA_D: usize = 90;
+const PHI_D: usize = 180;
+const SCALE: [f32; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+//Difference angles near the specular configuration, where the table follows the facet
+//distribution most closely
+const FIT_THETA_D: usize = 10;
+
+//Isotropic BRDF measured by Matusik et al. [21], a table over the half and difference angles of
+//Rusinkiewicz [22] read from MERL .binary files. Half vectors are importance sampled by a
+//distribution over theta_h fitted to the table when it is loaded, mixed with cosine sampling for
+//the diffuse part
+pub struct Measured {
+    table: Vec<f32>,
+    distribution: Distribution1D,
+    lobe_chance: f32,
+}
+
+impl Measured {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Failed to read BRDF from {:?}: {}", path, e))?;
+        Self::parse(&bytes).map_err(|e| format!("{:?} is not a MERL .binary file: {}", path, e))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 12 {
+            return Err("missing header".to_string());
+        }
+        let dimensions: Vec<i32> = (0..3)
+            .map(|i| i32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()))
+            .collect();
+        if dimensions != [THETA_H as i32, THETA_D as i32, PHI_D as i32] {
+            return Err(format!("dimensions {:?}", dimensions));
+        }
+        let n = THETA_H * THETA_D * PHI_D;
+        if bytes.len() != 12 + 3 * n * 8 {
+            return Err(format!("{} bytes for {} values", bytes.len() - 12, 3 * n));
+        }
+        let value = |i: usize| {
+            let offset = 12 + i * 8;
+            f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as f32
+        };
+        //Channels are stored one after another, they are interleaved here. Missing measurements
+        //are negative
+        let table: Vec<f32> = (0..n)
+            .flat_map(|i| (0..3).map(move |c| (i, c)))
+            .map(|(i, c)| (value(i + c * n) * SCALE[c]).max(0.0))
+            .collect();
+
+        //Reflectance near the mirror direction for every theta_h. The part above its minimum is
+        //taken as the lobe, the minimum as the diffuse part that cosine sampling covers
+        let reflectance: Vec<f32> = (0..THETA_H)
+            .map(|h| {
+                let sum: f32 = (0..FIT_THETA_D * PHI_D)
+                    .map(|i| texel(&table, i + h * THETA_D * PHI_D).luminance())
+                    .sum();
+                sum / (FIT_THETA_D * PHI_D) as f32
+            })
+            .collect();
+        let floor = reflectance.iter().cloned().fold(f32::INFINITY, f32::min);
+        let peak = reflectance.iter().cloned().fold(0.0, f32::max);
+
+        //Facets are seen with density D cos theta_h, D follows the reflectance times cos^2 for
+        //half vectors near the mirror direction. The rest converts from solid angle to the
+        //square root spacing of theta_h in the table
+        let weights: Vec<f32> = reflectance
+            .iter()
+            .enumerate()
+            .map(|(h, r)| {
+                let x = (h as f32 + 0.5) / THETA_H as f32;
+                let theta_h = x * x * PI / 2.0;
+                let lobe = r - floor + 1e-3 * peak + 1e-8;
+                lobe * theta_h.cos().powi(3) * theta_h.sin() * x
+            })
+            .collect();
+        let lobe_chance = if peak > 0.0 {
+            (1.0 - floor / peak).clamp(0.1, 0.9)
+        } else {
+            0.5
+        };
+
+        Ok(Self {
+            table,
+            distribution: Distribution1D::new(&weights),
+            lobe_chance,
+        })
+    }
+
+    //BRDF between two directions in the frame of the surface, with the normal along z
+    fn lookup(&self, wi: Vector3<f32>, wo: Vector3<f32>) -> Vector3<f32> {
+        let h = (wi + wo).norm();
+        let theta_h = h.z.clamp(-1.0, 1.0).acos();
+        let phi_h = h.y.atan2(h.x);
+        let d = rotate(wi, Vector3::new(0.0, 0.0, 1.0), -phi_h);
+        let d = rotate(d, Vector3::new(0.0, 1.0, 0.0), -theta_h);
+        let theta_d = d.z.clamp(-1.0, 1.0).acos();
+        let phi_d = d.y.atan2(d.x);
+        let phi_d = if phi_d < 0.0 { phi_d + PI } else { phi_d };
+
+        let index_h = ((theta_h / (PI / 2.0)).max(0.0).sqrt() * THETA_H as f32) as usize;
+        let index_d = (theta_d / (PI / 2.0) * THETA_D as f32) as usize;
+        let index_phi = (phi_d / PI * PHI_D as f32) as usize;
+        texel(
+            &self.table,
+            index_phi.min(PHI_D - 1)
+                + index_d.min(THETA_D - 1) * PHI_D
+                + index_h.min(THETA_H - 1) * PHI_D * THETA_D,
+        )
+    }
+
+    pub fn at(self: &Arc<Self>, hit: &HitRecord, r_in_direction: Vector3<f32>) -> MeasuredBSDF {
+        let onb = hit.shading_frame();
+        MeasuredBSDF {
+            brdf: self.clone(),
+            onb,
+            wo: onb.to_local(r_in_direction.norm() * (-1.0)),
+        }
+    }
+}
+
+fn texel(table: &[f32], i: usize) -> Vector3<f32> {
+    Vector3::new(table[i * 3], table[i * 3 + 1], table[i * 3 + 2])
+}
+
+//Rotation of v around a unit axis by angle
+fn rotate(v: Vector3<f32>, axis: Vector3<f32>, angle: f32) -> Vector3<f32> {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis * (Vector3::dot(axis, v) * (1.0 - cos)) + Vector3::cross(axis, v) * sin
+}
+
+//Measured BRDF at a hit, seen from wo in its shading frame
+pub struct MeasuredBSDF {
+    brdf: Arc<Measured>,
+    onb: ONB,
+    wo: Vector3<f32>,
+}
+
+impl MeasuredBSDF {
+    //BRDF times the cosine of wi
+    pub fn eval(&self, wi: Vector3<f32>) -> Vector3<f32> {
+        if self.wo.z <= 0.0 || wi.near_zero() {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let wi = self.onb.to_local(wi.norm());
+        if wi.z <= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        self.brdf.lookup(wi, self.wo) * wi.z
+    }
+
+    pub fn pdf(&self, wi: Vector3<f32>) -> f32 {
+        if self.wo.z <= 0.0 || wi.near_zero() {
+            return 0.0;
+        }
+        let wi = self.onb.to_local(wi.norm());
+        if wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (self.wo + wi).norm();
+        let theta_h = h.z.clamp(-1.0, 1.0).acos();
+        let x = (theta_h / (PI / 2.0)).sqrt();
+        //theta_h = x^2 pi / 2, so d theta_h = pi x dx
+        let pdf_h =
+            self.brdf.distribution.pdf(x) / (PI * x.max(1e-4) * 2.0 * PI * theta_h.sin().max(1e-6));
+        let lobe = pdf_h / (4.0 * Vector3::dot(self.wo, h));
+        self.brdf.lobe_chance * lobe + (1.0 - self.brdf.lobe_chance) * wi.z / PI
+    }
+
+    //Reflections below the surface are lost as the zero direction
+    pub fn sample(&self, rng: &mut impl Rng) -> Vector3<f32> {
+        let lost = Vector3::new(0.0, 0.0, 0.0);
+        if self.wo.z <= 0.0 {
+            return lost;
+        }
+        let wi = if rng.gen::<f32>() < self.brdf.lobe_chance {
+            let (_, x, _) = self.brdf.distribution.sample_continous(rng.gen());
+            let theta_h = x * x * PI / 2.0;
+            let phi_h = 2.0 * PI * rng.gen::<f32>();
+            let h = Vector3::new(
+                theta_h.sin() * phi_h.cos(),
+                theta_h.sin() * phi_h.sin(),
+                theta_h.cos(),
+            );
+            reflect(self.wo, h)
+        } else {
+            Vector3::random_cosine_direction(rng)
+        };
+        if wi.z <= 0.0 {
+            return lost;
+        }
+        self.onb.local(wi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{tests::check_bsdf, Material};
+
+    //MERL file holding value(theta_h, theta_d, phi_d, channel) at every entry, before scaling
+    fn merl(value: impl Fn(usize, usize, usize, usize) -> f64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + 3 * THETA_H * THETA_D * PHI_D * 8);
+        for x in [THETA_H, THETA_D, PHI_D] {
+            bytes.extend((x as i32).to_le_bytes());
+        }
+        for c in 0..3 {
+            for h in 0..THETA_H {
+                for d in 0..THETA_D {
+                    for p in 0..PHI_D {
+                        bytes.extend(value(h, d, p, c).to_le_bytes());
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    //BRDF given by theta_h and theta_d at the centers of the bins, the same in every channel
+    fn brdf(f: impl Fn(f32, f32) -> f32) -> Measured {
+        let bytes = merl(|h, d, _, c| {
+            let x = (h as f32 + 0.5) / THETA_H as f32;
+            let theta_d = (d as f32 + 0.5) / THETA_D as f32 * PI / 2.0;
+            (f(x * x * PI / 2.0, theta_d) / SCALE[c]) as f64
+        });
+        Measured::parse(&bytes).unwrap()
+    }
+
+    #[test]
+    fn looks_up_angles() {
+        let measured = Measured::parse(&merl(|h, d, p, c| {
+            ((h * THETA_D + d) * PHI_D + p + c) as f64
+        }))
+        .unwrap();
+        //Directions at the centers of the bins of theta_h, theta_d and phi_d
+        let (h, d, p) = (30, 40, 60);
+        let x = (h as f32 + 0.5) / THETA_H as f32;
+        let theta_h = x * x * PI / 2.0;
+        let theta_d = (d as f32 + 0.5) / THETA_D as f32 * PI / 2.0;
+        let phi_d = (p as f32 + 0.5) / PHI_D as f32 * PI;
+        let difference = Vector3::new(
+            theta_d.sin() * phi_d.cos(),
+            theta_d.sin() * phi_d.sin(),
+            theta_d.cos(),
+        );
+        let half = Vector3::new(theta_h.sin(), 0.0, theta_h.cos());
+        let wi = rotate(difference, Vector3::new(0.0, 1.0, 0.0), theta_h);
+        let wo = half * (2.0 * Vector3::dot(wi, half)) - wi;
+
+        let i = ((h * THETA_D + d) * PHI_D + p) as f32;
+        let value = measured.lookup(wi, wo);
+        let expected = [i * SCALE[0], (i + 1.0) * SCALE[1], (i + 2.0) * SCALE[2]];
+        for (value, expected) in [value.x, value.y, value.z].into_iter().zip(expected) {
+            assert!(
+                (value / expected - 1.0).abs() < 1e-5,
+                "{} {}",
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        //Truncated payload, payload too long, wrong dimensions and a missing header
+        let bytes = merl(|_, _, _, _| 0.1);
+        assert!(Measured::parse(&bytes[..bytes.len() - 8]).is_err());
+        let mut long = bytes.clone();
+        long.extend(0.1f64.to_le_bytes());
+        assert!(Measured::parse(&long).is_err());
+        let mut dimensions = bytes.clone();
+        dimensions[0..4].copy_from_slice(&(-90i32).to_le_bytes());
+        assert!(Measured::parse(&dimensions).is_err());
+        assert!(Measured::parse(&bytes[..8]).is_err());
+    }
+
+    #[test]
+    fn diffuse() {
+        let material = Material::Measured {
+            brdf: Arc::new(brdf(|_, _| 0.7 / PI)),
+        };
+        for cosine in [1.0, 0.5, 0.1] {
+            check_bsdf(&material, cosine);
+        }
+    }
+
+    #[test]
+    fn glossy() {
+        //Broad lobe around the mirror direction over a diffuse base
+        let material = Material::Measured {
+            brdf: Arc::new(brdf(|theta_h, _| 0.2 / PI + 0.1 * theta_h.cos().powi(8))),
+        };
+        for cosine in [1.0, 0.5, 0.1] {
+            check_bsdf(&material, cosine);
+        }
+    }
+}
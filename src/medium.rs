@@ -0,0 +1,1057 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{
+    aabb::AABB,
+    density::Density,
+    material::Material,
+    object::{Hittable, Object},
+    ray::{HitRecord, Ray, RayType},
+    simd_bvh::SceneBVH,
+    texture::Texture,
+    utilities::{color::blackbody, vector3::Vector3},
+};
+
+//Deepest nesting of media a path keeps track of
+const MAX_NESTED_MEDIA: usize = 4;
+//Temperatures the colors of blackbody emission are tabulated at
+const BLACKBODY_STEPS: usize = 64;
+//Scattering events of a subsurface walk before it is absorbed. They have a budget of their own
+//and do not count as bounces of the path
+const MAX_WALK_STEPS: u32 = 256;
+
+//Participating medium in a closed boundary, or everywhere as the atmosphere of a scene
+#[derive(Clone)]
+pub enum Medium {
+    Homogeneous {
+        sigma_t: f32,
+        phase_function: Material,
+        emission: Option<Emission>,
+    },
+    //Extinction varies with the density field, free paths are sampled with delta tracking
+    //against the majorant
+    Heterogeneous {
+        density: Density,
+        bounds: AABB,
+        sigma_t: f32,
+        phase_function: Material,
+        emission: Option<Emission>,
+    },
+    //Full density below height, thinning out exponentially over scale above it
+    Exponential {
+        sigma_t: f32,
+        height: f32,
+        scale: f32,
+        phase_function: Material,
+        emission: Option<Emission>,
+    },
+    //Dense body under a dielectric surface, like skin, marble or wax, that light walks through
+    //from where it went in to somewhere else on the surface. The albedo is the color the body
+    //takes on after many scattering events, and both it and the mean free path are per channel
+    //and may be textures, read where the path went in. The mean free path is scaled by scale
+    Subsurface {
+        albedo: Texture,
+        mean_free_path: Texture,
+        scale: f32,
+        phase_function: Material,
+    },
+}
+
+#[allow(dead_code)]
+impl Medium {
+    pub fn homogeneous(d: f32, color: Vector3<f32>) -> Self {
+        Self::Homogeneous {
+            sigma_t: d,
+            phase_function: Material::Isotropic { color },
+            emission: None,
+        }
+    }
+    //sigma_a and sigma_s are the absorption and scattering coefficients where the density is 1,
+    //the density field is stretched over bounds
+    pub fn heterogeneous(
+        density: Density,
+        bounds: AABB,
+        sigma_a: f32,
+        sigma_s: f32,
+        color: Vector3<f32>,
+        g: f32,
+    ) -> Self {
+        let (sigma_t, phase_function) = phase_function(sigma_a, sigma_s, color, g);
+        Self::Heterogeneous {
+            density,
+            bounds,
+            sigma_t,
+            phase_function,
+            emission: None,
+        }
+    }
+    //Haze of the same density everywhere
+    pub fn fog(sigma_a: f32, sigma_s: f32, color: Vector3<f32>, g: f32) -> Self {
+        let (sigma_t, phase_function) = phase_function(sigma_a, sigma_s, color, g);
+        Self::Homogeneous {
+            sigma_t,
+            phase_function,
+            emission: None,
+        }
+    }
+    //Ground fog, sigma_a and sigma_s are the coefficients below height
+    pub fn height_fog(
+        sigma_a: f32,
+        sigma_s: f32,
+        color: Vector3<f32>,
+        g: f32,
+        height: f32,
+        scale: f32,
+    ) -> Self {
+        let (sigma_t, phase_function) = phase_function(sigma_a, sigma_s, color, g);
+        Self::Exponential {
+            sigma_t,
+            height,
+            scale,
+            phase_function,
+            emission: None,
+        }
+    }
+    pub fn subsurface(
+        albedo: impl Into<Texture>,
+        mean_free_path: impl Into<Texture>,
+        scale: f32,
+        g: f32,
+    ) -> Self {
+        Self::Subsurface {
+            albedo: albedo.into(),
+            mean_free_path: mean_free_path.into(),
+            scale,
+            phase_function: Material::HenyeyGreenstein {
+                color: Vector3::new(1.0, 1.0, 1.0),
+                g,
+            },
+        }
+    }
+    //Measured skin and marble, millimeter is its length in scene units
+    pub fn skin(millimeter: f32) -> Self {
+        Self::subsurface(
+            Vector3::new(0.779, 0.622, 0.529),
+            Vector3::new(0.907, 0.602, 0.517),
+            millimeter,
+            0.0,
+        )
+    }
+    pub fn marble(millimeter: f32) -> Self {
+        Self::subsurface(
+            Vector3::new(0.931, 0.913, 0.894),
+            Vector3::new(0.456, 0.381, 0.333),
+            millimeter,
+            0.0,
+        )
+    }
+    //Subsurface media do not glow
+    pub fn with_emission(mut self, emission: Emission) -> Self {
+        match &mut self {
+            Self::Homogeneous { emission: e, .. }
+            | Self::Heterogeneous { emission: e, .. }
+            | Self::Exponential { emission: e, .. } => *e = Some(emission),
+            Self::Subsurface { .. } => {}
+        }
+        self
+    }
+
+    //Random walk starting where a path goes into a subsurface medium at a point of its surface.
+    //The albedo seen is inverted to the single scattering albedo with the fit of Chiang et al.
+    fn walk(&self, u: f32, v: f32, p: Vector3<f32>) -> Option<Walk> {
+        match self {
+            Self::Subsurface {
+                albedo,
+                mean_free_path,
+                scale,
+                ..
+            } => {
+                let single = |a: f32| {
+                    let a = a.clamp(0.0, 1.0);
+                    let b =
+                        4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+                    (1.0 - b * b).clamp(0.0, 1.0)
+                };
+                let sigma_t = |d: f32| 1.0 / (d * scale).max(1e-6);
+                let albedo = albedo.value(u, v, p);
+                let mean_free_path = mean_free_path.value(u, v, p);
+                Some(Walk {
+                    sigma_t: Vector3::new(
+                        sigma_t(mean_free_path.x),
+                        sigma_t(mean_free_path.y),
+                        sigma_t(mean_free_path.z),
+                    ),
+                    albedo: Vector3::new(single(albedo.x), single(albedo.y), single(albedo.z)),
+                    throughput: Vector3::new(1.0, 1.0, 1.0),
+                    steps: 0,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    //Scattering event along r between t_min and t_max, if the ray gets that far
+    pub fn sample(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rng: &mut impl Rng,
+    ) -> Option<HitRecord<'_>> {
+        let ray_length = r.direction.magnitude();
+        match self {
+            Self::Homogeneous {
+                sigma_t,
+                phase_function,
+                ..
+            } => {
+                let t = t_min - (1.0 - rng.gen::<f32>()).ln() / (sigma_t * ray_length);
+                (t < t_max).then(|| scattering(r, t, phase_function))
+            }
+            Self::Heterogeneous {
+                density,
+                bounds,
+                sigma_t,
+                phase_function,
+                ..
+            } => {
+                let majorant = sigma_t * density.max() * ray_length;
+                if majorant <= 0.0 {
+                    return None;
+                }
+                //Nothing is left to collide with once the ray leaves the density field
+                let t_max = t_max.min(exit_distance(bounds, r));
+
+                //Collisions with the majorant are real with probability density / max
+                let mut t = t_min;
+                loop {
+                    t -= (1.0 - rng.gen::<f32>()).ln() / majorant;
+                    if t >= t_max {
+                        return None;
+                    }
+                    let p = r.at(t);
+                    if rng.gen::<f32>() * density.max() < density.value(p, local(bounds, p)) {
+                        return Some(scattering(r, t, phase_function));
+                    }
+                }
+            }
+            Self::Exponential {
+                sigma_t,
+                height,
+                scale,
+                phase_function,
+                ..
+            } => {
+                let tau = -(1.0 - rng.gen::<f32>()).ln();
+                let dy = r.direction.y;
+                let y0 = r.origin.y + dy * t_min;
+                let t = if dy.abs() < 1e-6 {
+                    t_min + tau / (sigma_t * ray_length * height_density(y0, *height, *scale))
+                } else {
+                    //Inverts the optical depth through the antiderivative of the density in y,
+                    //rays going up may escape with optical depth to spare
+                    let g =
+                        height_integral(y0, *height, *scale) + tau * dy / (sigma_t * ray_length);
+                    let y = if g < 0.0 {
+                        height + g
+                    } else if g < *scale {
+                        height - scale * (1.0 - g / scale).ln()
+                    } else {
+                        return None;
+                    };
+                    (y - r.origin.y) / dy
+                };
+                (t < t_max).then(|| scattering(r, t, phase_function))
+            }
+            Self::Subsurface { .. } => unreachable!("subsurface media are walked by MediumStack"),
+        }
+    }
+
+    //Optical depth along r between t0 and t1, for media with a closed form
+    fn optical_depth(&self, r: &Ray, t0: f32, t1: f32) -> Option<f32> {
+        let ray_length = r.direction.magnitude();
+        match self {
+            Self::Homogeneous { sigma_t, .. } => Some(sigma_t * ray_length * (t1 - t0)),
+            Self::Exponential {
+                sigma_t,
+                height,
+                scale,
+                ..
+            } => {
+                let dy = r.direction.y;
+                let y0 = r.origin.y + dy * t0;
+                if dy.abs() < 1e-6 {
+                    Some(sigma_t * ray_length * (t1 - t0) * height_density(y0, *height, *scale))
+                } else {
+                    let y1 = r.origin.y + dy * t1;
+                    Some(
+                        sigma_t * ray_length / dy
+                            * (height_integral(y1, *height, *scale)
+                                - height_integral(y0, *height, *scale)),
+                    )
+                }
+            }
+            Self::Heterogeneous { .. } | Self::Subsurface { .. } => None,
+        }
+    }
+
+    //Extinction per unit of the ray parameter at t
+    fn extinction(&self, r: &Ray, t: f32) -> f32 {
+        let ray_length = r.direction.magnitude();
+        match self {
+            Self::Homogeneous { sigma_t, .. } => sigma_t * ray_length,
+            Self::Exponential {
+                sigma_t,
+                height,
+                scale,
+                ..
+            } => sigma_t * ray_length * height_density(r.at(t).y, *height, *scale),
+            Self::Heterogeneous {
+                density,
+                bounds,
+                sigma_t,
+                ..
+            } => {
+                let p = r.at(t);
+                sigma_t * ray_length * density.value(p, local(bounds, p))
+            }
+            Self::Subsurface { .. } => unreachable!("subsurface media are walked by MediumStack"),
+        }
+    }
+
+    //Like sample, but half of the distances are drawn from the equiangular distribution around
+    //the light point, which finds the light scattered close to small lights. The two are
+    //combined with one sample MIS, whose weight comes back with the scattering event
+    pub fn sample_toward(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        light: Vector3<f32>,
+        rng: &mut impl Rng,
+    ) -> (Option<HitRecord<'_>>, f32) {
+        let ray_length = r.direction.magnitude();
+        let direction = r.direction / ray_length;
+        //Foot of the light on the ray and its distance to it
+        let delta = Vector3::dot(light - r.origin, direction);
+        let distance = (light - (r.origin + direction * delta)).magnitude();
+        let theta_a = ((t_min * ray_length - delta) / distance).atan();
+        let theta_b = ((t_max * ray_length - delta) / distance).atan();
+        if self.optical_depth(r, t_min, t_min).is_none()
+            || t_max.is_nan()
+            || t_max < t_min
+            || distance < 1e-4
+            || theta_b - theta_a < 1e-6
+        {
+            return (self.sample(r, t_min, t_max, rng), 1.0);
+        }
+
+        let t = if rng.gen::<f32>() < 0.5 {
+            let theta = theta_a + rng.gen::<f32>() * (theta_b - theta_a);
+            ((distance * theta.tan() + delta) / ray_length)
+                .max(t_min)
+                .min(t_max)
+        } else {
+            match self.sample(r, t_min, t_max, rng) {
+                Some(scattering) => scattering.t,
+                //The surface is only reached by free flights, with the transmittance as chance
+                None => return (None, 2.0),
+            }
+        };
+        let x = t * ray_length - delta;
+        let pdf_equiangular =
+            ray_length * distance / ((theta_b - theta_a) * (distance * distance + x * x));
+        let transmittance = (-self.optical_depth(r, t_min, t).unwrap()).exp();
+        let pdf_free_flight = self.extinction(r, t) * transmittance;
+        let weight = pdf_free_flight / (0.5 * pdf_free_flight + 0.5 * pdf_equiangular);
+
+        let phase_function = match self {
+            Self::Homogeneous { phase_function, .. }
+            | Self::Heterogeneous { phase_function, .. }
+            | Self::Exponential { phase_function, .. }
+            | Self::Subsurface { phase_function, .. } => phase_function,
+        };
+        (Some(scattering(r, t, phase_function)), weight)
+    }
+
+    //Radiance gathered at a collision at p. Emission and extinction both scale with the density,
+    //so collisions sampled from the extinction estimate the emission along the ray with their
+    //ratio. A medium without extinction is never collided with and does not glow
+    pub fn emitted(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let (sigma_t, emission) = match self {
+            Self::Homogeneous {
+                sigma_t, emission, ..
+            }
+            | Self::Heterogeneous {
+                sigma_t, emission, ..
+            }
+            | Self::Exponential {
+                sigma_t, emission, ..
+            } => (*sigma_t, emission),
+            Self::Subsurface { .. } => return Vector3::new(0.0, 0.0, 0.0),
+        };
+        match emission {
+            Some(emission) if sigma_t > 0.0 => emission.radiance(p) / sigma_t,
+            _ => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+//Light given off by a medium per unit length where its density is 1
+#[allow(dead_code)]
+#[derive(Clone)]
+pub enum Emission {
+    Color(Vector3<f32>),
+    //Temperature field stretched over bounds. It glows like a blackbody, with the colors it goes
+    //through from cold to hot tabulated once
+    Blackbody {
+        temperature: Density,
+        bounds: AABB,
+        colors: Arc<Vec<Vector3<f32>>>,
+    },
+}
+
+impl Emission {
+    //Temperatures map from cold to hot kelvin, brighter with the fourth power of the temperature
+    //up to scale at hot
+    pub fn blackbody(temperature: Density, bounds: AABB, cold: f32, hot: f32, scale: f32) -> Self {
+        let colors = (0..BLACKBODY_STEPS)
+            .map(|i| {
+                let kelvin = cold + (hot - cold) * i as f32 / (BLACKBODY_STEPS - 1) as f32;
+                blackbody(kelvin) * (scale * (kelvin / hot).powi(4))
+            })
+            .collect();
+        Self::Blackbody {
+            temperature,
+            bounds,
+            colors: Arc::new(colors),
+        }
+    }
+
+    fn radiance(&self, p: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            Self::Color(color) => *color,
+            Self::Blackbody {
+                temperature,
+                bounds,
+                colors,
+            } => {
+                let max = temperature.max();
+                if max <= 0.0 {
+                    return Vector3::new(0.0, 0.0, 0.0);
+                }
+                let heat = (temperature.value(p, local(bounds, p)) / max).clamp(0.0, 1.0);
+                let x = heat * (BLACKBODY_STEPS - 1) as f32;
+                let i = (x as usize).min(BLACKBODY_STEPS - 2);
+                colors[i] + (colors[i + 1] - colors[i]) * (x - i as f32)
+            }
+        }
+    }
+}
+
+//Path through a subsurface medium, sampling distances in one channel weighted by all of them
+#[derive(Clone, Copy)]
+struct Walk {
+    sigma_t: Vector3<f32>,
+    albedo: Vector3<f32>,
+    throughput: Vector3<f32>,
+    steps: u32,
+}
+
+impl Walk {
+    //Scattering event along r between t_min and t_max and its weight per channel
+    fn step<'a>(
+        &mut self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        phase_function: &'a Material,
+        rng: &mut impl Rng,
+    ) -> (Option<HitRecord<'a>>, Vector3<f32>) {
+        if self.steps >= MAX_WALK_STEPS {
+            return (None, Vector3::new(0.0, 0.0, 0.0));
+        }
+        self.steps += 1;
+        let share = self.throughput * self.albedo;
+        let total = share.x + share.y + share.z;
+        let chances = if total > 0.0 && total.is_finite() {
+            share / total
+        } else {
+            Vector3::new(1.0, 1.0, 1.0) / 3.0
+        };
+        let sigma_t = self.sigma_t * r.direction.magnitude();
+        let u = rng.gen::<f32>();
+        let channel = if u < chances.x {
+            sigma_t.x
+        } else if u < chances.x + chances.y {
+            sigma_t.y
+        } else {
+            sigma_t.z
+        };
+
+        let t = t_min - (1.0 - rng.gen::<f32>()).ln() / channel;
+        let transmittance = (sigma_t * (t_min - t.min(t_max))).exp();
+        let (sampled, f) = if t < t_max {
+            (
+                Some(scattering(r, t, phase_function)),
+                sigma_t * self.albedo * transmittance,
+            )
+        } else {
+            (None, transmittance)
+        };
+        let pdf = if sampled.is_some() {
+            Vector3::dot(chances, sigma_t * transmittance)
+        } else {
+            Vector3::dot(chances, transmittance)
+        };
+        let weight = if pdf > 0.0 {
+            f / pdf
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+        self.throughput = self.throughput * weight;
+        (sampled, weight)
+    }
+}
+
+//Extinction coefficient and phase function with the scattering albedo
+fn phase_function(sigma_a: f32, sigma_s: f32, color: Vector3<f32>, g: f32) -> (f32, Material) {
+    let sigma_t = sigma_a + sigma_s;
+    let albedo = if sigma_t > 0.0 {
+        sigma_s / sigma_t
+    } else {
+        0.0
+    };
+    (
+        sigma_t,
+        Material::HenyeyGreenstein {
+            color: color * albedo,
+            g,
+        },
+    )
+}
+
+fn height_density(y: f32, height: f32, scale: f32) -> f32 {
+    (-(y - height).max(0.0) / scale).exp()
+}
+
+//Antiderivative of height_density in y, zero at height
+fn height_integral(y: f32, height: f32, scale: f32) -> f32 {
+    if y < height {
+        y - height
+    } else {
+        scale * (1.0 - (-(y - height) / scale).exp())
+    }
+}
+
+fn scattering<'a>(r: &Ray, t: f32, phase_function: &'a Material) -> HitRecord<'a> {
+    HitRecord::new(
+        r.at(t),
+        Vector3::new(1.0, 0.0, 0.0),
+        t,
+        0.0,
+        0.0,
+        r,
+        phase_function,
+    )
+}
+
+//Position of p in the unit cube of bounds
+fn local(bounds: &AABB, p: Vector3<f32>) -> Vector3<f32> {
+    let size = bounds.maximum - bounds.minimum;
+    let local = p - bounds.minimum;
+    Vector3::new(local.x / size.x, local.y / size.y, local.z / size.z)
+}
+
+//Ray parameter where r leaves bounds
+fn exit_distance(bounds: &AABB, r: &Ray) -> f32 {
+    let exit = |origin: f32, direction: f32, minimum: f32, maximum: f32| {
+        let t0 = (minimum - origin) / direction;
+        let t1 = (maximum - origin) / direction;
+        if t0.is_nan() || t1.is_nan() {
+            f32::INFINITY
+        } else {
+            t0.max(t1)
+        }
+    };
+    exit(
+        r.origin.x,
+        r.direction.x,
+        bounds.minimum.x,
+        bounds.maximum.x,
+    )
+    .min(exit(
+        r.origin.y,
+        r.direction.y,
+        bounds.minimum.y,
+        bounds.maximum.y,
+    ))
+    .min(exit(
+        r.origin.z,
+        r.direction.z,
+        bounds.minimum.z,
+        bounds.maximum.z,
+    ))
+}
+
+//Where the search for surfaces resumes past a boundary hit at t, far enough along the ray for f32
+//to tell them apart
+fn past(t: f32) -> f32 {
+    t + 0.001 + t * 1e-6
+}
+
+//Closed surface enclosing a medium. Hits keep the material of the surface, Material::Interface
+//makes the boundary itself invisible
+#[derive(Clone)]
+pub struct MediumBoundary {
+    object: Box<Object>,
+    medium: Arc<Medium>,
+}
+
+impl MediumBoundary {
+    pub fn new(object: Object, medium: Arc<Medium>) -> Self {
+        Self {
+            object: Box::new(object),
+            medium,
+        }
+    }
+}
+
+impl Hittable for MediumBoundary {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.object.hit(r, t_min, t_max).map(|mut hit| {
+            hit.medium = Some(&self.medium);
+            hit
+        })
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.object.bounding_box()
+    }
+}
+
+//Inside of a closed dielectric, the one of highest priority fills where they overlap
+#[derive(Clone)]
+pub struct Interior {
+    pub index_of_refraction: f32,
+    //Per unit length and color channel
+    pub absorption: Vector3<f32>,
+    pub priority: u32,
+}
+
+impl Interior {
+    pub fn new(index_of_refraction: f32, absorption: Vector3<f32>, priority: u32) -> Self {
+        Self {
+            index_of_refraction,
+            absorption,
+            priority,
+        }
+    }
+}
+
+//Closed surface enclosing a dielectric interior
+#[derive(Clone)]
+pub struct InteriorBoundary {
+    object: Box<Object>,
+    interior: Arc<Interior>,
+}
+
+impl InteriorBoundary {
+    pub fn new(object: Object, interior: Arc<Interior>) -> Self {
+        Self {
+            object: Box::new(object),
+            interior,
+        }
+    }
+}
+
+impl Hittable for InteriorBoundary {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.object.hit(r, t_min, t_max).map(|mut hit| {
+            hit.interior = Some(&self.interior);
+            hit
+        })
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.object.bounding_box()
+    }
+}
+
+//Media and dielectric interiors a path is currently in, innermost last
+#[derive(Clone, Copy, Default)]
+pub struct MediumStack<'a> {
+    media: [Option<&'a Medium>; MAX_NESTED_MEDIA],
+    interiors: [Option<&'a Interior>; MAX_NESTED_MEDIA],
+    atmosphere: Option<&'a Medium>,
+    //Walk through the subsurface medium entered last
+    walk: Option<Walk>,
+}
+
+impl<'a> MediumStack<'a> {
+    //Media and interiors containing p. A ray leaving p crosses the boundary of each of them from
+    //the inside the first time it meets it
+    pub fn at(bvh: &'a SceneBVH, p: Vector3<f32>) -> Self {
+        let mut stack = Self {
+            atmosphere: bvh.atmosphere.as_ref(),
+            ..Default::default()
+        };
+        if !bvh.has_media {
+            return stack;
+        }
+        //Skewed to stay clear of the edges of axis aligned boxes
+        let direction = Vector3::new(0.267, 0.873, 0.408);
+        let mut media = vec![];
+        let mut interiors = vec![];
+        //Where the ray leaves each medium, paths starting inside a subsurface body walk with its
+        //properties there
+        let mut exits = vec![];
+        let ray = Ray::new(p, direction);
+        let mut t = 0.0;
+        while let Some(hit) = bvh.hit(&ray, past(t), f32::INFINITY, RayType::Diffuse) {
+            if let Some(medium) = hit.medium {
+                meet(&mut media, medium, !hit.front_face);
+                if !hit.front_face {
+                    exits.push((medium, hit.u, hit.v, hit.p));
+                }
+            }
+            if let Some(interior) = hit.interior {
+                meet(&mut interiors, interior, !hit.front_face);
+            }
+            t = hit.t;
+        }
+        //Boundaries met first are the innermost ones
+        for (medium, _) in media.iter().rev().filter(|(_, inside)| *inside) {
+            push(&mut stack.media, medium);
+        }
+        for (interior, _) in interiors.iter().rev().filter(|(_, inside)| *inside) {
+            push(&mut stack.interiors, interior);
+        }
+        stack.walk = stack.current().and_then(|current| {
+            exits
+                .iter()
+                .find(|(medium, ..)| std::ptr::eq(*medium, current))
+                .and_then(|(medium, u, v, p)| medium.walk(*u, *v, *p))
+        });
+        stack
+    }
+
+    pub fn current(&self) -> Option<&'a Medium> {
+        self.media
+            .iter()
+            .rev()
+            .find_map(|medium| *medium)
+            .or(self.atmosphere)
+    }
+
+    //Interior of highest priority, the innermost one among equals
+    fn current_interior(&self) -> Option<&'a Interior> {
+        self.interiors
+            .iter()
+            .flatten()
+            .copied()
+            .max_by_key(|interior| interior.priority)
+    }
+
+    fn index_of_refraction(&self) -> f32 {
+        self.current_interior()
+            .map_or(1.0, |interior| interior.index_of_refraction)
+    }
+
+    //Enters or leaves what is bounded at hit, depending on the side direction points to
+    pub fn cross(&mut self, hit: &HitRecord<'a>, direction: Vector3<f32>) {
+        if hit.medium.is_none() && hit.interior.is_none() {
+            return;
+        }
+        let outward = if hit.front_face {
+            hit.normal
+        } else {
+            hit.normal * (-1.0)
+        };
+        let entering = Vector3::dot(direction, outward) < 0.0;
+        if let Some(medium) = hit.medium {
+            if entering {
+                push(&mut self.media, medium);
+                if let Some(walk) = medium.walk(hit.u, hit.v, hit.p) {
+                    self.walk = Some(walk);
+                }
+            } else {
+                self.leave(medium);
+            }
+        }
+        if let Some(interior) = hit.interior {
+            if entering {
+                push(&mut self.interiors, interior);
+            } else {
+                remove(&mut self.interiors, interior);
+            }
+        }
+    }
+
+    fn leave(&mut self, medium: &'a Medium) {
+        remove(&mut self.media, medium);
+        if matches!(medium, Medium::Subsurface { .. }) {
+            self.walk = None;
+        }
+    }
+
+    //Whether hit is a scattering event of the subsurface walk the path is on, see MAX_WALK_STEPS
+    pub fn walk_step(&self, hit: &HitRecord) -> bool {
+        self.walk.is_some() && hit.material.is_medium()
+    }
+
+    pub fn towards(&self, hit: &HitRecord<'a>, direction: Vector3<f32>) -> Self {
+        let mut media = *self;
+        media.cross(hit, direction);
+        media
+    }
+
+    //Ratio of the indices of refraction at a dielectric boundary hit, None if the surface lies
+    //inside an interior of higher priority and has to be skipped
+    fn refraction(&self, hit: &HitRecord<'a>, interior: &'a Interior) -> Option<f32> {
+        let current = self.current_interior();
+        let outside = if hit.front_face {
+            if current.is_some_and(|current| current.priority > interior.priority) {
+                return None;
+            }
+            interior.index_of_refraction
+        } else {
+            if !current.is_some_and(|current| std::ptr::eq(current, interior)) {
+                return None;
+            }
+            let mut behind = *self;
+            remove(&mut behind.interiors, interior);
+            behind.index_of_refraction()
+        };
+        Some(self.index_of_refraction() / outside)
+    }
+
+    //First interaction along r before t_max, either a surface or a scattering event in the
+    //current medium. Invisible boundaries and skipped dielectric surfaces on the way only update
+    //the stack. Distances are also sampled toward a point on one of the lights, if any. The
+    //weight of the interaction comes along, with the absorption inside dielectrics, and so does
+    //the radiance emitted by the medium up to it, already weighted
+    pub fn trace(
+        &mut self,
+        bvh: &'a SceneBVH,
+        r: &Ray,
+        t_max: f32,
+        ray_type: RayType,
+        light: &[Object],
+        rng: &mut impl Rng,
+    ) -> (Option<HitRecord<'a>>, Vector3<f32>, Vector3<f32>) {
+        let mut t_start = 0.0;
+        let mut weight = Vector3::new(1.0, 1.0, 1.0);
+        loop {
+            let hit = bvh.hit(r, past(t_start), t_max, ray_type);
+            //A ray escaping to infinity from inside a bounded medium went through a leaking
+            //boundary, it is left unscattered. The atmosphere goes on forever
+            let t_end = hit.as_ref().map_or(t_max, |hit| hit.t);
+            let bounded = self.media.iter().any(Option::is_some);
+            //Subsurface bodies hold nothing but themselves. Meeting any other surface, or their
+            //own from the outside, or escaping to infinity means the path got out through a hole
+            //in the mesh
+            if let Some(medium @ Medium::Subsurface { .. }) = self.current() {
+                let leaked = match &hit {
+                    Some(hit) => {
+                        hit.front_face || !hit.medium.is_some_and(|m| std::ptr::eq(m, medium))
+                    }
+                    None => t_max.is_infinite(),
+                };
+                if leaked {
+                    self.leave(medium);
+                    continue;
+                }
+            }
+            let mut scattering = None;
+            if let Some(medium) = self.current().filter(|_| t_end.is_finite() || !bounded) {
+                let (sampled, w) = match medium {
+                    Medium::Subsurface { phase_function, .. } => match self.walk {
+                        Some(mut walk) => {
+                            let step = walk.step(r, t_start, t_end, phase_function, rng);
+                            self.walk = Some(walk);
+                            step
+                        }
+                        None => (None, Vector3::new(1.0, 1.0, 1.0)),
+                    },
+                    _ => match light_point(light, rng) {
+                        Some(light) => {
+                            let (sampled, w) = medium.sample_toward(r, t_start, t_end, light, rng);
+                            (sampled, Vector3::new(w, w, w))
+                        }
+                        None => (
+                            medium.sample(r, t_start, t_end, rng),
+                            Vector3::new(1.0, 1.0, 1.0),
+                        ),
+                    },
+                };
+                weight = weight * w;
+                scattering = sampled.map(|sampled| (sampled, medium));
+            }
+            if let Some(interior) = self.current_interior() {
+                let t = scattering.as_ref().map_or(t_end, |(sampled, _)| sampled.t);
+                let length = (t - t_start) * r.direction.magnitude();
+                if length.is_finite() {
+                    weight = weight * (interior.absorption * (-length)).exp();
+                }
+            }
+            if let Some((scattering, medium)) = scattering {
+                let emitted = medium.emitted(scattering.p) * weight;
+                return (Some(scattering), weight, emitted);
+            }
+            match hit {
+                Some(hit) if matches!(hit.material, Material::Interface) => {
+                    self.cross(&hit, r.direction);
+                    t_start = hit.t;
+                }
+                Some(mut hit) if hit.interior.is_some() => {
+                    match self.refraction(&hit, hit.interior.unwrap()) {
+                        Some(eta) => {
+                            hit.eta = Some(eta);
+                            return (Some(hit), weight, Vector3::new(0.0, 0.0, 0.0));
+                        }
+                        None => {
+                            self.cross(&hit, r.direction);
+                            t_start = hit.t;
+                        }
+                    }
+                }
+                hit => return (hit, weight, Vector3::new(0.0, 0.0, 0.0)),
+            }
+        }
+    }
+}
+
+//Point on a random area light, media sample distances toward it. Distant lights like the sun
+//and the environment are left to free flights, every point of the ray sees them from the same
+//direction and only their transmittance changes along it
+fn light_point(light: &[Object], rng: &mut impl Rng) -> Option<Vector3<f32>> {
+    let emitters = || light.iter().filter(|light| light.emitter_area().is_some());
+    let n = emitters().count();
+    if n == 0 {
+        return None;
+    }
+    let (p, _) = emitters()
+        .nth(rng.gen_range(0..n))
+        .unwrap()
+        .sample_surface(rng);
+    Some(p)
+}
+
+//Records whether the first boundary met of each medium or interior was met from the inside
+fn meet<'a, T>(met: &mut Vec<(&'a T, bool)>, item: &'a T, inside: bool) {
+    if !met.iter().any(|(m, _)| std::ptr::eq(*m, item)) {
+        met.push((item, inside));
+    }
+}
+
+fn push<'a, T>(slots: &mut [Option<&'a T>], item: &'a T) {
+    if slots.iter().flatten().any(|m| std::ptr::eq(*m, item)) {
+        return;
+    }
+    if let Some(slot) = slots.iter_mut().find(|m| m.is_none()) {
+        *slot = Some(item);
+    }
+}
+
+fn remove<'a, T>(slots: &mut [Option<&'a T>], item: &'a T) {
+    if let Some(i) = slots
+        .iter()
+        .position(|m| m.is_some_and(|m| std::ptr::eq(m, item)))
+    {
+        slots[i..].rotate_left(1);
+        slots[slots.len() - 1] = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+    use crate::background::Sun;
+
+    #[test]
+    fn blackbody_emission() {
+        let bounds = AABB::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+        let field = |t: f32| Density::Grid {
+            voxels: Arc::new(vec![t]),
+            resolution: [1, 1, 1],
+            max: t,
+        };
+        let p = Vector3::new(0.5, 0.5, 0.5);
+        //An empty field gives no light instead of dividing by its zero maximum
+        let empty = Emission::blackbody(field(0.0), bounds.clone(), 600.0, 2200.0, 1.0);
+        assert_eq!(empty.radiance(p), Vector3::new(0.0, 0.0, 0.0));
+        let hot = Emission::blackbody(field(1.0), bounds, 600.0, 2200.0, 1.0).radiance(p);
+        assert!((hot - blackbody(2200.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn walk_budget() {
+        let medium = Medium::subsurface(Vector3::new(1.0, 1.0, 1.0), 0.1, 1.0, 0.0);
+        let phase_function = Material::Isotropic {
+            color: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let mut walk = medium.walk(0.0, 0.0, Vector3::new(0.0, 0.0, 0.0)).unwrap();
+        let r = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..MAX_WALK_STEPS {
+            let (sampled, _) = walk.step(&r, 0.0, f32::INFINITY, &phase_function, &mut rng);
+            assert!(sampled.is_some());
+        }
+        //Out of steps, the walk is absorbed
+        let (sampled, weight) = walk.step(&r, 0.0, f32::INFINITY, &phase_function, &mut rng);
+        assert!(sampled.is_none());
+        assert_eq!(weight, Vector3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn walk_from_inside() {
+        let albedo = Vector3::new(0.8, 0.5, 0.3);
+        let body = Object::build_sphere(
+            Vector3::new(0.0, 0.0, 0.0),
+            1.0,
+            Material::Dielectric {
+                index_of_refraction: 1.4,
+            },
+        )
+        .with_medium(Arc::new(Medium::subsurface(albedo, 0.1, 1.0, 0.0)));
+        let elsewhere = Object::build_sphere(
+            Vector3::new(5.0, 0.0, 0.0),
+            1.0,
+            Material::Lambertian { albedo },
+        );
+        let bvh = SceneBVH::from(vec![body, elsewhere]);
+        let stack = MediumStack::at(&bvh, Vector3::new(0.0, 0.0, 0.0));
+        assert!(matches!(stack.current(), Some(Medium::Subsurface { .. })));
+        assert!(stack.walk.is_some_and(|walk| walk.steps == 0));
+        //Paths starting outside do not walk
+        assert!(MediumStack::at(&bvh, Vector3::new(0.0, 3.0, 0.0))
+            .walk
+            .is_none());
+    }
+
+    #[test]
+    fn distant_lights_are_not_aimed_at() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let sun = Object::Sun(Sun::new(
+            Vector3::new(0.0, 1.0, 0.0),
+            0.999,
+            Vector3::new(1.0, 1.0, 1.0),
+        ));
+        assert!(light_point(&[sun.clone()], &mut rng).is_none());
+        let lamp = Object::build_sphere(
+            Vector3::new(0.0, 3.0, 0.0),
+            0.5,
+            Material::DiffuseLight {
+                texture: Texture::blackbody(2700.0, 1.0),
+            },
+        );
+        for _ in 0..16 {
+            let p = light_point(&[sun.clone(), lamp.clone()], &mut rng).unwrap();
+            assert!(((p - Vector3::new(0.0, 3.0, 0.0)).magnitude() - 0.5).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use rand::Rng;
+
+use crate::utilities::vector3::Vector3;
+
+const PI: f32 = std::f32::consts::PI;
+
+//Anisotropic GGX (Trowbridge-Reitz) distribution of microfacet normals [13]. Directions are in a
+//local frame whose z axis is the shading normal, alpha_x and alpha_y are the roughnesses along x
+//and y
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
+pub struct GGX {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl GGX {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        Self {
+            alpha_x: alpha_x.max(1e-3),
+            alpha_y: alpha_y.max(1e-3),
+        }
+    }
+
+    //Disney's mapping from perceptual roughness and anisotropy to the two alphas
+    pub fn from_roughness(roughness: f32, anisotropic: f32) -> Self {
+        let aspect = (1.0 - 0.9 * anisotropic).sqrt();
+        let alpha = roughness * roughness;
+        Self::new(alpha / aspect, alpha * aspect)
+    }
+
+    pub fn d(&self, h: Vector3<f32>) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (h.x / self.alpha_x).powi(2) + (h.y / self.alpha_y).powi(2) + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: Vector3<f32>) -> f32 {
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        ((1.0 + tan2).sqrt() - 1.0) / 2.0
+    }
+
+    //Smith masking of a direction on either side of the surface
+    pub fn g1(&self, w: Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    //Height-correlated masking and shadowing
+    pub fn g(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    //Microfacet normal distributed as d(h) h.z
+    pub fn sample_h(&self, rng: &mut impl Rng) -> Vector3<f32> {
+        let (u1, u2) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let phi =
+            (self.alpha_y * (2.0 * PI * u1).sin()).atan2(self.alpha_x * (2.0 * PI * u1).cos());
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let alpha2 = 1.0 / ((cos_phi / self.alpha_x).powi(2) + (sin_phi / self.alpha_y).powi(2));
+        let tan2 = alpha2 * u2 / (1.0 - u2).max(1e-7);
+        let cos_theta = 1.0 / (1.0 + tan2).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        Vector3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+    }
+
+    pub fn pdf_h(&self, h: Vector3<f32>) -> f32 {
+        self.d(h) * h.z
+    }
+
+    //Microfacet normal among those visible from wo, distributed as g1(wo) max(wo.h, 0) d(h) / wo.z
+    //[14]. Samples are never wasted on facets facing away from wo
+    pub fn sample_visible(&self, wo: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
+        //Stretch to the hemisphere configuration of roughness 1
+        let v = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).norm();
+        let length2 = v.x * v.x + v.y * v.y;
+        let t1 = if length2 > 0.0 {
+            Vector3::new(-v.y, v.x, 0.0) / length2.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vector3::cross(v, t1);
+
+        //Uniform point on the projected disk, warped onto the visible half
+        let r = rng.gen::<f32>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let n = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        Vector3::new(self.alpha_x * n.x, self.alpha_y * n.y, n.z.max(1e-6)).norm()
+    }
+
+    pub fn pdf_visible(&self, wo: Vector3<f32>, h: Vector3<f32>) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vector3::dot(wo, h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+//Generalized Trowbridge-Reitz distribution with exponent 1, the long tailed highlight of Disney's
+//clearcoat
+pub fn gtr1(cos_h: f32, alpha: f32) -> f32 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+pub fn sample_gtr1(alpha: f32, rng: &mut impl Rng) -> Vector3<f32> {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - a2.powf(1.0 - rng.gen::<f32>())) / (1.0 - a2))
+        .max(0.0)
+        .sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f32>();
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub fn schlick_weight(cosine: f32) -> f32 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+//Unpolarized Fresnel reflectance of a dielectric interface, eta is n_transmitted / n_incident
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(-1.0, 1.0).abs();
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_p = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (r_s * r_s + r_p * r_p) / 2.0
+}
+
+//Reflectance of a conductor of complex index of refraction eta + i k per channel, from a medium
+//of index 1
+pub fn fresnel_conductor(cos_i: f32, eta: Vector3<f32>, k: Vector3<f32>) -> Vector3<f32> {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        0.5 * (r_s + r_p)
+    };
+    Vector3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+//Mirror direction of wo about h, both pointing away from the surface
+pub fn reflect(wo: Vector3<f32>, h: Vector3<f32>) -> Vector3<f32> {
+    h * (2.0 * Vector3::dot(wo, h)) - wo
+}
+
+//Direction transmitted from wo through a microfacet h on its side, None under total internal
+//reflection. eta is n_transmitted / n_incident
+pub fn refract(wo: Vector3<f32>, h: Vector3<f32>, eta: f32) -> Option<Vector3<f32>> {
+    let cos_i = Vector3::dot(wo, h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(wo * (-1.0 / eta) + h * (cos_i / eta - cos_t))
+}
+
+//Half vector of a refraction from wo to wi, on the side of the normal
+pub fn half_vector_refraction(wo: Vector3<f32>, wi: Vector3<f32>, eta: f32) -> Vector3<f32> {
+    let h = (wo + wi * eta).norm();
+    if h.z < 0.0 {
+        h * (-1.0)
+    } else {
+        h
+    }
+}
+
+//Scattering between wo and wi through the facets of a rough dielectric interface, a reflection when
+//wi is above the surface and a refraction below it [13]
+pub struct DielectricScattering {
+    pub h: Vector3<f32>,
+    //Fresnel reflectance or transmittance of the facet
+    pub fresnel: f32,
+    //Converts a density of h into one of wi
+    pub jacobian: f32,
+    //BSDF times the cosine towards wi
+    pub value: f32,
+}
+
+pub fn dielectric_scattering(
+    distribution: &GGX,
+    wo: Vector3<f32>,
+    wi: Vector3<f32>,
+    eta: f32,
+) -> Option<DielectricScattering> {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return None;
+    }
+    let (h, fresnel, jacobian) = if wi.z > 0.0 {
+        let h = (wo + wi).norm();
+        let dot_o = Vector3::dot(wo, h);
+        (h, fresnel_dielectric(dot_o, eta), 1.0 / (4.0 * dot_o))
+    } else {
+        let h = half_vector_refraction(wo, wi, eta);
+        let (dot_o, dot_i) = (Vector3::dot(wo, h), Vector3::dot(wi, h));
+        if dot_o <= 0.0 || dot_i >= 0.0 {
+            return None;
+        }
+        let denominator = dot_o + eta * dot_i;
+        //Throughput is not scaled by the squared eta, like the smooth dielectrics
+        (
+            h,
+            1.0 - fresnel_dielectric(dot_o, eta),
+            eta * eta * dot_i.abs() / (denominator * denominator),
+        )
+    };
+    let value =
+        fresnel * distribution.d(h) * distribution.g(wo, wi) * Vector3::dot(wo, h) * jacobian
+            / wo.z;
+    Some(DielectricScattering {
+        h,
+        fresnel,
+        jacobian,
+        value,
+    })
+}
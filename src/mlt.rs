@@ -0,0 +1,374 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::{rngs::SmallRng, Error, Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+
+use crate::{
+    background::{Distribution1D, Environment},
+    bdpt::SplatFilm,
+    camera::Camera,
+    integrator::ray_color,
+    medium::MediumStack,
+    object::Object,
+    simd_bvh::SceneBVH,
+    utilities::{math::seeded_rng, vector3::Vector3},
+};
+
+const PI: f32 = std::f32::consts::PI;
+const BOOTSTRAP_SAMPLES: usize = 100_000;
+const CHAINS: usize = 1000;
+//Standard deviation of small step mutations
+const SIGMA: f32 = 0.01;
+const LARGE_STEP_PROBABILITY: f32 = 0.3;
+
+//Primary sample space Metropolis light transport [6]. ray_color reads its random numbers from a
+//vector of primary samples, and Markov chains mutate the vector with small perturbations and
+//large independent steps, so paths close to a bright one are explored more. Chains start from
+//bootstrap samples whose mean luminance scales the image to its absolute brightness
+pub struct Mlt<'a> {
+    bvh: &'a SceneBVH,
+    camera: &'a Camera,
+    camera_media: MediumStack<'a>,
+    background: &'a Environment,
+    light: &'a [Object],
+    depth: i32,
+    width: usize,
+    height: usize,
+    spectral: bool,
+    seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f32,
+    last_modified: u64,
+    value_backup: f32,
+    modify_backup: u64,
+}
+
+//Random number stream backed by a mutable vector of primary samples. Samples are mutated lazily
+//when read, catching up with the small steps they missed since their last use
+pub struct PrimarySamples {
+    x: Vec<PrimarySample>,
+    index: usize,
+    iteration: u64,
+    last_large_iteration: u64,
+    large_step: bool,
+    rng: SmallRng,
+}
+
+impl PrimarySamples {
+    pub fn new(rng: SmallRng) -> Self {
+        Self {
+            x: Vec::new(),
+            index: 0,
+            iteration: 0,
+            last_large_iteration: 0,
+            large_step: true,
+            rng,
+        }
+    }
+
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < LARGE_STEP_PROBABILITY;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_iteration = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        let iteration = self.iteration;
+        self.x
+            .iter_mut()
+            .filter(|sample| sample.last_modified == iteration)
+            .for_each(|sample| {
+                sample.value = sample.value_backup;
+                sample.last_modified = sample.modify_backup;
+            });
+        self.iteration -= 1;
+    }
+
+    fn next(&mut self) -> f32 {
+        //New samples start uniform, otherwise rejection sampling loops could mutate them forever
+        if self.index >= self.x.len() {
+            self.x.push(PrimarySample {
+                value: self.rng.gen(),
+                last_modified: self.last_large_iteration,
+                ..Default::default()
+            });
+        }
+        let sample = &mut self.x[self.index];
+        self.index += 1;
+
+        if sample.last_modified < self.last_large_iteration {
+            sample.value = self.rng.gen();
+            sample.last_modified = self.last_large_iteration;
+        }
+        sample.value_backup = sample.value;
+        sample.modify_backup = sample.last_modified;
+
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            //Box-Muller normal sample, the missed small steps add up to a wider one
+            let u1 = 1.0 - self.rng.gen::<f32>();
+            let u2 = self.rng.gen::<f32>();
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            let steps = (self.iteration - sample.last_modified) as f32;
+            sample.value += normal * SIGMA * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modified = self.iteration;
+        sample.value
+    }
+}
+
+impl RngCore for PrimarySamples {
+    fn next_u32(&mut self) -> u32 {
+        (self.next() as f64 * 4294967296.0) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next() as f64 * 18446744073709551616.0) as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<'a> Mlt<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        bvh: &'a SceneBVH,
+        camera: &'a Camera,
+        background: &'a Environment,
+        light: &'a [Object],
+        depth: i32,
+        width: usize,
+        height: usize,
+        spectral: bool,
+        seed: Option<u64>,
+    ) -> Self {
+        Self {
+            bvh,
+            camera,
+            camera_media: MediumStack::at(bvh, camera.origin),
+            background,
+            light,
+            depth,
+            width,
+            height,
+            spectral,
+            seed,
+        }
+    }
+
+    //Film coordinates and radiance of the path given by the primary samples
+    fn sample(&self, samples: &mut PrimarySamples) -> (f32, f32, Vector3<f32>) {
+        let x = samples.gen::<f32>() * self.width as f32;
+        let y = samples.gen::<f32>() * self.height as f32;
+        let u = x / (self.width - 1) as f32;
+        let v = 1.0 - y / (self.height - 1) as f32;
+
+        let r = self.camera.get_ray(u, v, samples);
+        let l = ray_color(
+            self.bvh,
+            r,
+            self.camera_media,
+            self.depth,
+            self.background,
+            self.light,
+            None,
+            self.spectral,
+            samples,
+        );
+        //Single wavelength samples out of the sRGB gamut have negative components, which
+        //average out
+        if l.to_array().iter().all(|c| c.is_finite()) && l.luminance() >= 0.0 {
+            (u, v, l)
+        } else {
+            (u, v, Vector3::new(0.0, 0.0, 0.0))
+        }
+    }
+
+    //Runs mutations_per_pixel mutations per pixel in total, split between the chains
+    pub fn render(&self, mutations_per_pixel: usize, frame: &mut [f32]) {
+        //Chains replay their bootstrap sample, so its stream is seeded even without a seed given
+        let seed = Some(self.seed.unwrap_or_else(|| rand::thread_rng().gen()));
+        let weights: Vec<f32> = (0..BOOTSTRAP_SAMPLES)
+            .into_par_iter()
+            .map(|i| {
+                let mut samples = PrimarySamples::new(seeded_rng(seed, i as u64));
+                self.sample(&mut samples).2.luminance()
+            })
+            .collect();
+        let bootstrap = Distribution1D::new(&weights);
+        let b = bootstrap.integral();
+        if b <= 0.0 {
+            frame.fill(0.0);
+            return;
+        }
+
+        let n_pixels = self.width * self.height;
+        let mutations = (mutations_per_pixel * n_pixels) as u64;
+        //Small renders run fewer mutations than there are chains, every chain runs at least one
+        let per_chain = (mutations / CHAINS as u64).max(1);
+
+        let pb = ProgressBar::new(CHAINS as u64);
+        pb.set_style(ProgressStyle::default_bar().template("{bar:40.green/white}  {percent} %"));
+
+        let film = SplatFilm::new(self.width, self.height);
+        (0..CHAINS).into_par_iter().for_each(|chain| {
+            let mut rng = seeded_rng(seed, (BOOTSTRAP_SAMPLES + chain) as u64);
+            let (_, _, index) = bootstrap.sample_continous(rng.gen());
+
+            //Replays the bootstrap sample, then mutates it with a stream of its own
+            let mut samples = PrimarySamples::new(seeded_rng(seed, index as u64));
+            let (mut u, mut v, mut l) = self.sample(&mut samples);
+            samples.rng = SmallRng::seed_from_u64(rng.gen());
+
+            for _ in 0..per_chain {
+                samples.start_iteration();
+                let proposed = self.sample(&mut samples);
+                let accept = splat(&film, (u, v, l), proposed);
+
+                if rng.gen::<f32>() < accept {
+                    (u, v, l) = proposed;
+                    samples.accept();
+                } else {
+                    samples.reject();
+                }
+            }
+            pb.inc(1);
+        });
+        pb.finish_and_clear();
+
+        let scale = b * n_pixels as f32 / (per_chain * CHAINS as u64) as f32;
+        frame.par_chunks_mut(3).enumerate().for_each(|(i, pixel)| {
+            pixel.copy_from_slice(&(film.get(i) * scale).to_array());
+        });
+    }
+}
+
+//Splats the current and proposed states weighted by their expected values and returns the
+//chance of accepting the proposal. Black states are never splatted, their weight would divide by
+//a zero luminance
+fn splat(
+    film: &SplatFilm,
+    (u, v, l): (f32, f32, Vector3<f32>),
+    (u_proposed, v_proposed, l_proposed): (f32, f32, Vector3<f32>),
+) -> f32 {
+    let lum = l.luminance();
+    let lum_proposed = l_proposed.luminance();
+    let accept = if lum > 0.0 {
+        (lum_proposed / lum).min(1.0)
+    } else {
+        1.0
+    };
+
+    if lum_proposed > 0.0 {
+        film.add(u_proposed, v_proposed, l_proposed * accept / lum_proposed);
+    }
+    if lum > 0.0 {
+        film.add(u, v, l * (1.0 - accept) / lum);
+    }
+    accept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{background::Background, material::Material, texture::Texture};
+
+    //Render of a lit floor with fewer mutations than chains
+    fn small_render(seed: u64) -> Vec<f32> {
+        let floor = Object::build_xz_rect(
+            -5.0,
+            5.0,
+            -5.0,
+            5.0,
+            0.0,
+            Material::Lambertian {
+                albedo: Vector3::new(0.8, 0.8, 0.8),
+            },
+            false,
+        );
+        let light = Object::build_xz_rect(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            3.0,
+            Material::DiffuseLight {
+                texture: Texture::SolidColor {
+                    albedo: Vector3::new(4.0, 4.0, 4.0),
+                },
+            },
+            true,
+        );
+        let bvh = SceneBVH::from(vec![floor, light.clone()]);
+        let background = Environment::from(Background::new_plain(Vector3::new(0.0, 0.0, 0.0)));
+        let camera = Camera::new(
+            Vector3::new(0.0, 2.0, 4.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+            1.0,
+        );
+        let lights = [light];
+        let mlt = Mlt::new(
+            &bvh,
+            &camera,
+            &background,
+            &lights,
+            5,
+            4,
+            4,
+            false,
+            Some(seed),
+        );
+        let mut frame = vec![0.0; 4 * 4 * 3];
+        mlt.render(1, &mut frame);
+        frame
+    }
+
+    #[test]
+    fn small_renders_are_finite_and_seeded() {
+        let frame = small_render(5);
+        assert!(frame.iter().all(|c| c.is_finite()));
+        assert!(frame.iter().any(|&c| c > 0.0));
+        //Chains splat in any order, which only changes the rounding of the sums
+        let again = small_render(5);
+        assert!(frame
+            .iter()
+            .zip(again)
+            .all(|(a, b)| (a - b).abs() <= 1e-4 * a.abs()));
+    }
+
+    #[test]
+    fn dark_paths_leave_the_film_finite() {
+        let film = SplatFilm::new(4, 4);
+        let black = Vector3::new(0.0, 0.0, 0.0);
+        let bright = Vector3::new(1.0, 2.0, 3.0);
+        //Both black, then a black proposal from a bright state and the other way around
+        assert_eq!(splat(&film, (0.2, 0.2, black), (0.8, 0.8, black)), 1.0);
+        assert_eq!(splat(&film, (0.2, 0.2, bright), (0.8, 0.8, black)), 0.0);
+        assert_eq!(splat(&film, (0.2, 0.2, black), (0.8, 0.8, bright)), 1.0);
+        assert!((0..16).all(|i| film.get(i).to_array().iter().all(|c| c.is_finite())));
+    }
+}
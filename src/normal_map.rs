@@ -0,0 +1,107 @@
+use crate::{
+    ray::{HitRecord, Ray},
+    texture::Texture,
+    utilities::{onb::ONB, vector3::Vector3},
+};
+
+//Surface detail that bends the shading normal, read in the tangent frame of the hit. Surfaces
+//without tangents get an arbitrary frame around their normal
+#[derive(Clone)]
+pub enum NormalMap {
+    //Normals stored as colors, red along the tangent, green along the bitangent and blue along
+    //the normal, like the maps baked for MikkTSpace tangents [19]. Strength scales the tilt
+    Tangent { texture: Texture, strength: f32 },
+    //Heights whose slopes tilt the normal, as if the surface were displaced by them [20]. Heights
+    //are in texture coordinate units, a scale of 0.01 lifts white a hundredth of the texture
+    Bump { height: Texture, scale: f32 },
+}
+
+#[allow(dead_code)]
+impl NormalMap {
+    pub fn tangent(texture: Texture) -> Self {
+        Self::Tangent {
+            texture,
+            strength: 1.0,
+        }
+    }
+
+    pub fn bump(height: Texture, scale: f32) -> Self {
+        Self::Bump { height, scale }
+    }
+
+    pub fn with_strength(self, strength: f32) -> Self {
+        match self {
+            Self::Tangent {
+                texture,
+                strength: _,
+            } => Self::Tangent { texture, strength },
+            bump => bump,
+        }
+    }
+
+    //Hit with the normal bent by the map, facing the same side as the original
+    pub fn apply<'a>(&self, r_in: &Ray, hit: &HitRecord<'a>) -> HitRecord<'a> {
+        let outward = hit.outward_normal();
+        let (tangent, bitangent) = if hit.tangent.magnitude2() > 0.0 {
+            (hit.tangent, hit.bitangent)
+        } else {
+            let onb = ONB::build_from(outward);
+            (onb.u, onb.v)
+        };
+
+        let bent = match self {
+            Self::Tangent { texture, strength } => {
+                let color = texture.value(hit.u, hit.v, hit.p);
+                let local = color * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+                tangent * (local.x * strength)
+                    + bitangent * (local.y * strength)
+                    + outward * local.z
+            }
+            Self::Bump { height, scale } => {
+                let (du, dv) = height.texel_size();
+                let height_at = |u: f32, v: f32| height.value(u, v, hit.p).x * scale;
+                let slope_u =
+                    (height_at(hit.u + du, hit.v) - height_at(hit.u - du, hit.v)) / (2.0 * du);
+                let slope_v =
+                    (height_at(hit.u, hit.v + dv) - height_at(hit.u, hit.v - dv)) / (2.0 * dv);
+                outward - tangent * slope_u - bitangent * slope_v
+            }
+        };
+        if bent.magnitude2() == 0.0 {
+            return *hit;
+        }
+        let normal = if hit.front_face { bent } else { bent * (-1.0) };
+
+        let mut mapped = *hit;
+        mapped.normal = valid_reflection(r_in.direction.norm(), hit.normal.norm(), normal.norm());
+        mapped
+    }
+}
+
+//Normals bent away from the viewer reflect light from below the surface, which shows as black
+//patches. The bent normal is tilted back toward the surface normal until the reflection of the
+//incoming direction clears the surface, like Cycles does
+fn valid_reflection(
+    direction: Vector3<f32>,
+    normal: Vector3<f32>,
+    bent: Vector3<f32>,
+) -> Vector3<f32> {
+    let threshold = (-0.9 * Vector3::dot(direction, normal)).min(0.01);
+    let clears =
+        |bent: Vector3<f32>| Vector3::dot(Vector3::reflect(direction, bent), normal) >= threshold;
+    if clears(bent) {
+        return bent;
+    }
+    //Bisection over the blend between both normals, the surface normal always clears
+    let blend = |t: f32| (bent * (1.0 - t) + normal * t).norm();
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..12 {
+        let middle = (low + high) / 2.0;
+        if clears(blend(middle)) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    blend(high)
+}
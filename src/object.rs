@@ -2,19 +2,22 @@ use std::sync::Arc;
 
 use image::Rgb;
 //use enum_dispatch::enum_dispatch;
-use rand::prelude::ThreadRng;
+use rand::Rng;
 
 use crate::{
     aabb::AABB,
-    background::EnviromentalMap,
-    constant_medium::ConstantMedium,
+    background::{EnviromentalMap, Sun},
+    density::Density,
     material::Material,
-    ray::{HitRecord, Ray},
+    medium::{Interior, InteriorBoundary, Medium, MediumBoundary},
+    portal::{Portal, PortalQuad},
+    ray::{HitRecord, Ray, RayType},
     rectangle::{XYRect, XZRect, YZRect},
     sphere::Sphere,
     transformations::{RotateY, Translate},
     triangle_mesh::Triangle,
-    utilities::{math::Point2D, vector3::Vector3},
+    utilities::{math::Point2D, onb::ONB, vector3::Vector3},
+    visibility::{RayVisibility, Visible, ALL_LIGHT_GROUPS},
 };
 //#[enum_dispatch(Hittable)] //removed enum_dispatch crate for easier profiling
 #[derive(Clone)]
@@ -23,11 +26,15 @@ pub enum Object {
     XZRect(XZRect),
     XYRect(XYRect),
     YZRect(YZRect),
-    ConstantMedium(ConstantMedium),
+    MediumBoundary(MediumBoundary),
+    InteriorBoundary(InteriorBoundary),
     Translate(Translate),
     RotateY(RotateY),
     Triangle(Triangle),
     EnviromentalMap(EnviromentalMap),
+    Sun(Sun),
+    Portal(Portal),
+    Visible(Visible),
 }
 #[allow(dead_code)]
 impl Object {
@@ -71,11 +78,48 @@ impl Object {
         Object::XYRect(XYRect::new(x0, x1, y0, y1, k, material, flip_normal))
     }
 
+    //Fills a closed object with a medium, the surface keeps its material
+    pub fn with_medium(self, medium: Arc<Medium>) -> Self {
+        Object::MediumBoundary(MediumBoundary::new(self, medium))
+    }
+    //Makes a closed dielectric object part of the nested interiors tracked along paths
+    pub fn with_interior(self, interior: Arc<Interior>) -> Self {
+        Object::InteriorBoundary(InteriorBoundary::new(self, interior))
+    }
     pub fn build_constant_medium(self, d: f32, color: Vector3<f32>) -> Self {
-        Object::ConstantMedium(ConstantMedium::new(self, d, color))
+        self.with_medium(Arc::new(Medium::homogeneous(d, color)))
+    }
+    //sigma_a and sigma_s are the absorption and scattering coefficients where the density is 1
+    pub fn build_heterogeneous_medium(
+        self,
+        density: Density,
+        sigma_a: f32,
+        sigma_s: f32,
+        color: Vector3<f32>,
+        g: f32,
+    ) -> Self {
+        let bounds = self.bounding_box().clone();
+        self.with_medium(Arc::new(Medium::heterogeneous(
+            density, bounds, sigma_a, sigma_s, color, g,
+        )))
+    }
+    pub fn build_env_map(
+        image_v: Arc<Vec<Rgb<f32>>>,
+        width: f32,
+        height: f32,
+        rotation: ONB,
+    ) -> Self {
+        Object::EnviromentalMap(EnviromentalMap::new(image_v, width, height, rotation))
     }
-    pub fn build_env_map(image_v: Arc<Vec<Rgb<f32>>>, width: f32, height: f32) -> Self {
-        Object::EnviromentalMap(EnviromentalMap::new(image_v, width, height))
+    //Restricts the sampling of an environment map light to the given openings
+    pub fn through_portals(self, quads: Vec<PortalQuad>) -> Self {
+        match self {
+            Object::EnviromentalMap(env_map) => Object::Portal(Portal::new(&env_map, quads)),
+            _ => self,
+        }
+    }
+    pub fn with_visibility(self, visibility: RayVisibility) -> Self {
+        Object::Visible(Visible::new(self, visibility))
     }
     pub fn translate(self, offset: Vector3<f32>) -> Self {
         Object::Translate(Translate::new(self, offset))
@@ -105,21 +149,102 @@ impl Object {
         }
     }
 
+    pub fn set_tangents(
+        &mut self,
+        tangent0: Vector3<f32>,
+        tangent1: Vector3<f32>,
+        tangent2: Vector3<f32>,
+        handedness: f32,
+    ) {
+        if let Self::Triangle(triangle) = self {
+            triangle.set_tangents(tangent0, tangent1, tangent2, handedness);
+        }
+    }
+
+    pub fn set_face_normal(&mut self) {
+        if let Self::Triangle(triangle) = self {
+            triangle.set_face_normal();
+        }
+    }
+
+    #[inline(always)]
+    pub fn visible_to(&self, ray_type: RayType) -> bool {
+        match self {
+            Self::Visible(visible) => visible.visibility.visible_to(ray_type),
+            _ => true,
+        }
+    }
+    //Light groups of an object used as a light
+    pub fn light_group(&self) -> u32 {
+        match self {
+            Self::Visible(visible) => visible.visibility.light_group,
+            _ => ALL_LIGHT_GROUPS,
+        }
+    }
+
+    //Whether the object bounds a medium or an interior somewhere inside its wrappers
+    pub fn has_medium(&self) -> bool {
+        match self {
+            Self::MediumBoundary(_) | Self::InteriorBoundary(_) => true,
+            Self::Translate(translate) => translate.object.has_medium(),
+            Self::RotateY(rotate_y) => rotate_y.object.has_medium(),
+            Self::Visible(visible) => visible.object.has_medium(),
+            _ => false,
+        }
+    }
+
+    //Area of a DiffuseLight primitive that light subpaths can start from
+    pub fn emitter_area(&self) -> Option<f32> {
+        let (area, material) = match self {
+            Self::XZRect(rectangle) => (rectangle.area(), &rectangle.material),
+            Self::XYRect(rectangle) => (rectangle.area(), &rectangle.material),
+            Self::YZRect(rectangle) => (rectangle.area(), &rectangle.material),
+            Self::Sphere(sphere) => (sphere.area(), &sphere.material),
+            Self::Visible(visible) => return visible.object.emitter_area(),
+            _ => return None,
+        };
+        matches!(material, Material::DiffuseLight { texture: _ }).then_some(area)
+    }
+    //Uniform point on an emitter and its outward normal
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            Self::XZRect(rectangle) => rectangle.sample_surface(rng),
+            Self::XYRect(rectangle) => rectangle.sample_surface(rng),
+            Self::YZRect(rectangle) => rectangle.sample_surface(rng),
+            Self::Sphere(sphere) => sphere.sample_surface(rng),
+            Self::Visible(visible) => visible.object.sample_surface(rng),
+            _ => unreachable!(),
+        }
+    }
+    //Radiance leaving a point sampled on an emitter, read by hitting it from the normal side
+    pub fn emitted(&self, p: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+        match self.hit(&Ray::new(p + normal, normal * (-1.0)), 0.5, 1.5) {
+            Some(hit) => hit.material.emit(hit.u, hit.v, hit.p, hit.front_face),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
     pub fn pdf_value(&self, o: Vector3<f32>, direction: Vector3<f32>) -> f32 {
         match self {
             Self::XZRect(rectangle) => rectangle.pdf_value(o, direction),
             Self::Sphere(sphere) => sphere.pdf_value(o, direction),
             Self::XYRect(rectangle) => rectangle.pdf_value(o, direction),
             Self::EnviromentalMap(env_map) => env_map.pdf_value(o, direction),
+            Self::Sun(sun) => sun.pdf_value(o, direction),
+            Self::Portal(portal) => portal.pdf_value(o, direction),
+            Self::Visible(visible) => visible.object.pdf_value(o, direction),
             _ => 1.0,
         }
     }
-    pub fn random(&self, o: Vector3<f32>, rng: &mut ThreadRng) -> Vector3<f32> {
+    pub fn random(&self, o: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
         match self {
             Self::XZRect(rectangle) => rectangle.random(o, rng),
             Self::Sphere(sphere) => sphere.random(o, rng),
             Self::XYRect(rectangle) => rectangle.random(o, rng),
             Self::EnviromentalMap(env_map) => env_map.random(o, rng),
+            Self::Sun(sun) => sun.random(o, rng),
+            Self::Portal(portal) => portal.random(o, rng),
+            Self::Visible(visible) => visible.object.random(o, rng),
             _ => Vector3::new(1.0, 1.0, 1.0),
         }
     }
@@ -140,10 +265,12 @@ impl Hittable for Object {
             Object::XZRect(rectangle) => rectangle.hit(r, t_min, t_max),
             Object::XYRect(rectangle) => rectangle.hit(r, t_min, t_max),
             Object::YZRect(rectangle) => rectangle.hit(r, t_min, t_max),
-            Object::ConstantMedium(constant_medium) => constant_medium.hit(r, t_min, t_max),
+            Object::MediumBoundary(boundary) => boundary.hit(r, t_min, t_max),
+            Object::InteriorBoundary(boundary) => boundary.hit(r, t_min, t_max),
             Object::Translate(translate) => translate.hit(r, t_min, t_max),
             Object::RotateY(rotate_y) => rotate_y.hit(r, t_min, t_max),
             Object::Triangle(triangle) => triangle.hit(r, t_min, t_max),
+            Object::Visible(visible) => visible.hit(r, t_min, t_max),
             _ => unreachable!(),
         }
     }
@@ -154,10 +281,12 @@ impl Hittable for Object {
             Object::XZRect(rectangle) => rectangle.bounding_box(),
             Object::XYRect(rectangle) => rectangle.bounding_box(),
             Object::YZRect(rectangle) => rectangle.bounding_box(),
-            Object::ConstantMedium(constant_medium) => constant_medium.bounding_box(),
+            Object::MediumBoundary(boundary) => boundary.bounding_box(),
+            Object::InteriorBoundary(boundary) => boundary.bounding_box(),
             Object::Translate(translate) => translate.bounding_box(),
             Object::RotateY(rotate_y) => rotate_y.bounding_box(),
             Object::Triangle(triangle) => triangle.bounding_box(),
+            Object::Visible(visible) => visible.bounding_box(),
             _ => unreachable!(),
         }
     }
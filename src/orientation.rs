@@ -0,0 +1,58 @@
+use std::f32::consts::PI;
+
+use crate::{ray::HitRecord, texture::Texture, utilities::vector3::Vector3};
+
+//Direction that anisotropic materials stretch their highlights across, like the grooves of
+//brushed metal. By default the tangent follows the u texture coordinate of the surface, a
+//direction replaces it with its projection onto the surface, which keeps it continuous across the
+//triangles of meshes without texture coordinates. The rotation texture then turns it around the
+//normal, in whole turns
+#[derive(Clone)]
+pub struct Orientation {
+    direction: Option<Vector3<f32>>,
+    rotation: Texture,
+}
+
+#[allow(dead_code)]
+impl Orientation {
+    pub fn along_uv() -> Self {
+        Self {
+            direction: None,
+            rotation: 0.0.into(),
+        }
+    }
+
+    pub fn along(direction: Vector3<f32>) -> Self {
+        Self {
+            direction: Some(direction),
+            rotation: 0.0.into(),
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: impl Into<Texture>) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
+    //Hit with its tangent and bitangent turned to the orientation
+    pub fn apply<'a>(&self, hit: &HitRecord<'a>) -> HitRecord<'a> {
+        let mut oriented = *hit;
+        if let Some(direction) = self.direction {
+            oriented = oriented.with_tangent(direction, 1.0);
+        }
+        let (tangent, bitangent) = if oriented.tangent.magnitude2() > 0.0 {
+            (oriented.tangent, oriented.bitangent)
+        } else {
+            let frame = oriented.shading_frame();
+            (frame.u, frame.v)
+        };
+
+        let angle = 2.0 * PI * self.rotation.value(hit.u, hit.v, hit.p).x;
+        if angle != 0.0 {
+            let (sin, cos) = angle.sin_cos();
+            oriented.tangent = tangent * cos + bitangent * sin;
+            oriented.bitangent = bitangent * cos - tangent * sin;
+        }
+        oriented
+    }
+}
@@ -1,7 +1,11 @@
-use rand::prelude::{SliceRandom, ThreadRng};
+use rand::prelude::IteratorRandom;
 use rand::Rng;
 
+use crate::material::henyey_greenstein;
+use crate::measured::MeasuredBSDF;
+use crate::microfacet::{dielectric_scattering, fresnel_dielectric, reflect, refract, GGX};
 use crate::object::Object;
+use crate::principled::PrincipledBSDF;
 use crate::utilities::math::fmax;
 use crate::utilities::onb::ONB;
 use crate::Vector3;
@@ -13,28 +17,37 @@ pub enum PDFType<'a> {
     PDFSphere { pdf: PDFSphere },
     PDFBlinnPhongSpec { pdf: PDFBlinnPhongSpec },
     PDFAshikhminShirley { pdf: PDFAshikhminShirley },
+    PDFHenyeyGreenstein { pdf: PDFHenyeyGreenstein },
+    PDFPrincipled { pdf: PrincipledBSDF },
+    PDFMeasured { pdf: MeasuredBSDF },
+    PDFConductor { pdf: PDFConductor },
+    PDFRoughDielectric { pdf: PDFRoughDielectric },
+    PDFLayered { pdf: PDFLayered<'a> },
 }
 
 impl PDFType<'_> {
     pub fn value(&self, direction: Vector3<f32>) -> f32 {
         match self {
             Self::PDFObj { pdf } => {
-                if pdf.objects.is_empty() {
+                let count = pdf.linked().count();
+                if count == 0 {
                     return 0.0;
                 };
                 let acc: f32 = pdf
-                    .objects
-                    .iter()
+                    .linked()
                     .map(|object| object.pdf_value(pdf.o, direction))
                     .sum();
 
-                acc / pdf.objects.len() as f32
+                acc / count as f32
             }
             Self::PDFCosine { pdf } => {
                 let cosine = Vector3::dot(direction.norm(), pdf.onb.w);
                 (cosine / PI).max(0.0)
             }
             Self::PDFSphere { pdf: _ } => 1.0 / (4.0 * PI),
+            Self::PDFHenyeyGreenstein { pdf } => {
+                henyey_greenstein(Vector3::dot(direction.norm(), pdf.onb.w), pdf.g)
+            }
             Self::PDFBlinnPhongSpec { pdf } => {
                 let random_normal =
                     ((pdf.r_in_direction * (-1.0)).norm() + direction.norm()).norm();
@@ -61,7 +74,7 @@ impl PDFType<'_> {
                 let exponent = (pdf.nu * Vector3::dot(h, pdf.onb_normal.u).powi(2)
                     + pdf.nv * Vector3::dot(h, pdf.onb_normal.v).powi(2))
                     / (1.0 - Vector3::dot(h, pdf.onb_normal.w).powi(2));
-                    
+
                 let pdf_h = ((pdf.nu + 1.0) * (pdf.nv + 1.0)).sqrt() / (2.0 * PI)
                     * Vector3::dot(pdf.onb_normal.w, h).powf(exponent);
                 let cosine = Vector3::dot(l, pdf.onb_normal.w);
@@ -69,14 +82,55 @@ impl PDFType<'_> {
                 (1.0 - pdf.k_specular) * (cosine).max(0.0) / PI
                     + pdf.k_specular * pdf_h / (4.0 * Vector3::dot(v, h))
             }
+            Self::PDFPrincipled { pdf } => pdf.pdf(direction),
+            Self::PDFMeasured { pdf } => pdf.pdf(direction),
+            Self::PDFLayered { pdf } => {
+                PDFMixture::new(&pdf.coat, &pdf.base).value(pdf.coat_chance, direction)
+            }
+            Self::PDFRoughDielectric { pdf } => {
+                if direction.near_zero() || pdf.wo.z <= 0.0 {
+                    return 0.0;
+                }
+                let wi = pdf.onb.to_local(direction.norm());
+                dielectric_scattering(&pdf.distribution, pdf.wo, wi, pdf.eta).map_or(0.0, |s| {
+                    s.fresnel * pdf.distribution.pdf_visible(pdf.wo, s.h) * s.jacobian
+                })
+            }
+            Self::PDFConductor { pdf } => {
+                if direction.near_zero() {
+                    return 0.0;
+                }
+                let wi = pdf.onb.to_local(direction.norm());
+                if wi.z <= 0.0 {
+                    return 0.0;
+                }
+                let h = (pdf.wo + wi).norm();
+                pdf.distribution.pdf_visible(pdf.wo, h) / (4.0 * Vector3::dot(pdf.wo, h))
+            }
         }
     }
 
-    pub fn sample(&self, rng: &mut ThreadRng) -> Vector3<f32> {
+    pub fn sample(&self, rng: &mut impl Rng) -> Vector3<f32> {
         match self {
-            Self::PDFObj { pdf } => pdf.objects.choose(rng).unwrap().random(pdf.o, rng),
+            Self::PDFObj { pdf } => pdf.linked().choose(rng).unwrap().random(pdf.o, rng),
             Self::PDFCosine { pdf } => pdf.onb.local(Vector3::random_cosine_direction(rng)),
             Self::PDFSphere { pdf: _ } => Vector3::random_in_unit_sphere(rng),
+            Self::PDFHenyeyGreenstein { pdf } => {
+                let g = pdf.g;
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * rng.gen::<f32>()
+                } else {
+                    let square = (1.0 - g * g) / (1.0 - g + 2.0 * g * rng.gen::<f32>());
+                    ((1.0 + g * g - square * square) / (2.0 * g)).clamp(-1.0, 1.0)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                pdf.onb.local(Vector3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ))
+            }
             Self::PDFBlinnPhongSpec { pdf } => {
                 if rng.gen::<f32>() < pdf.k_specular {
                     loop {
@@ -103,6 +157,38 @@ impl PDFType<'_> {
                     pdf.onb_normal.local(Vector3::random_cosine_direction(rng))
                 }
             }
+            Self::PDFPrincipled { pdf } => pdf.sample(rng),
+            Self::PDFMeasured { pdf } => pdf.sample(rng),
+            Self::PDFLayered { pdf } => {
+                PDFMixture::new(&pdf.coat, &pdf.base).sample(pdf.coat_chance, rng)
+            }
+            Self::PDFRoughDielectric { pdf } => {
+                //Picks reflection or refraction by the Fresnel term of the sampled facet.
+                //Directions on the wrong side are lost as the zero direction
+                let lost = Vector3::new(0.0, 0.0, 0.0);
+                if pdf.wo.z <= 0.0 {
+                    return lost;
+                }
+                let h = pdf.distribution.sample_visible(pdf.wo, rng);
+                let fresnel = fresnel_dielectric(Vector3::dot(pdf.wo, h), pdf.eta);
+                let wi = if rng.gen::<f32>() < fresnel {
+                    Some(reflect(pdf.wo, h)).filter(|wi| wi.z > 0.0)
+                } else {
+                    refract(pdf.wo, h, pdf.eta).filter(|wi| wi.z < 0.0)
+                };
+                wi.map_or(lost, |wi| pdf.onb.local(wi))
+            }
+            Self::PDFConductor { pdf } => {
+                //Reflections below the surface are lost as the zero direction
+                if pdf.wo.z <= 0.0 {
+                    return Vector3::new(0.0, 0.0, 0.0);
+                }
+                let wi = reflect(pdf.wo, pdf.distribution.sample_visible(pdf.wo, rng));
+                if wi.z <= 0.0 {
+                    return Vector3::new(0.0, 0.0, 0.0);
+                }
+                pdf.onb.local(wi)
+            }
         }
     }
 }
@@ -111,11 +197,20 @@ impl PDFType<'_> {
 pub struct PDF<'a> {
     o: Vector3<f32>,
     objects: &'a [Object],
+    lit_by: u32,
 }
 
 impl<'a> PDF<'a> {
-    pub fn new(o: Vector3<f32>, objects: &'a [Object]) -> Self {
-        Self { o, objects }
+    //Only lights in one of the lit_by groups are sampled
+    pub fn new(o: Vector3<f32>, objects: &'a [Object], lit_by: u32) -> Self {
+        Self { o, objects, lit_by }
+    }
+
+    pub fn linked(&self) -> impl Iterator<Item = &'a Object> {
+        let lit_by = self.lit_by;
+        self.objects
+            .iter()
+            .filter(move |object| object.light_group() & lit_by != 0)
     }
 }
 
@@ -139,6 +234,21 @@ impl PDFSphere {
     }
 }
 
+pub struct PDFHenyeyGreenstein {
+    onb: ONB,
+    g: f32,
+}
+
+impl PDFHenyeyGreenstein {
+    //Around the propagation direction of the incoming ray
+    pub fn new(r_in_direction: Vector3<f32>, g: f32) -> Self {
+        Self {
+            onb: ONB::build_from(r_in_direction),
+            g,
+        }
+    }
+}
+
 pub struct PDFBlinnPhongSpec {
     r_in_direction: Vector3<f32>,
     onb_normal: ONB,
@@ -176,15 +286,14 @@ pub struct PDFAshikhminShirley {
 }
 
 impl PDFAshikhminShirley {
+    //nu is the exponent along the u axis of the frame and nv along its v axis
     pub fn new(
         r_in_direction: Vector3<f32>,
-        normal: Vector3<f32>,
+        onb_normal: ONB,
         nu: f32,
         nv: f32,
         k_specular: f32,
     ) -> Self {
-        let onb_normal = ONB::build_from(normal);
-
         Self {
             r_in_direction,
             onb_normal,
@@ -195,6 +304,66 @@ impl PDFAshikhminShirley {
     }
 }
 
+//Visible normals of a GGX conductor, in the shading frame of the hit so that alpha_x follows the
+//tangent
+pub struct PDFConductor {
+    onb: ONB,
+    wo: Vector3<f32>,
+    distribution: GGX,
+}
+
+impl PDFConductor {
+    pub fn new(r_in_direction: Vector3<f32>, onb: ONB, distribution: GGX) -> Self {
+        Self {
+            onb,
+            wo: onb.to_local(r_in_direction.norm() * (-1.0)),
+            distribution,
+        }
+    }
+}
+
+//Visible normals of a rough dielectric, eta is the relative index of refraction across it
+pub struct PDFRoughDielectric {
+    onb: ONB,
+    wo: Vector3<f32>,
+    distribution: GGX,
+    eta: f32,
+}
+
+impl PDFRoughDielectric {
+    pub fn new(
+        r_in_direction: Vector3<f32>,
+        normal: Vector3<f32>,
+        distribution: GGX,
+        eta: f32,
+    ) -> Self {
+        let onb = ONB::build_from(normal);
+        Self {
+            onb,
+            wo: onb.to_local(r_in_direction.norm() * (-1.0)),
+            distribution,
+            eta,
+        }
+    }
+}
+
+//Reflection off a coat or scattering by the base under it
+pub struct PDFLayered<'a> {
+    coat: Box<PDFType<'a>>,
+    base: Box<PDFType<'a>>,
+    coat_chance: f32,
+}
+
+impl<'a> PDFLayered<'a> {
+    pub fn new(coat: PDFType<'a>, base: PDFType<'a>, coat_chance: f32) -> Self {
+        Self {
+            coat: Box::new(coat),
+            base: Box::new(base),
+            coat_chance,
+        }
+    }
+}
+
 pub struct PDFMixture<'a> {
     p: &'a PDFType<'a>,
     q: &'a PDFType<'a>,
@@ -210,7 +379,7 @@ impl<'a> PDFMixture<'a> {
         chance * self.p.value(direction) + (1.0 - chance) * self.q.value(direction)
     }
     #[inline(always)]
-    pub fn sample(&self, chance: f32, rng: &mut ThreadRng) -> Vector3<f32> {
+    pub fn sample(&self, chance: f32, rng: &mut impl Rng) -> Vector3<f32> {
         if rng.gen::<f32>() < chance {
             self.p.sample(rng)
         } else {
@@ -0,0 +1,580 @@
+use std::{collections::HashMap, sync::atomic::AtomicU32};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    background::Environment,
+    camera::Camera,
+    material::ScatterRecord,
+    medium::MediumStack,
+    object::Object,
+    pdf::{PDFMixture, PDFType, PDF},
+    ray::{HitRecord, Ray, RayType},
+    simd_bvh::SceneBVH,
+    utilities::{
+        math::{atomic_add, atomic_load, fmax, seeded_rng},
+        onb::ONB,
+        vector3::Vector3,
+    },
+};
+
+const PI: f32 = std::f32::consts::PI;
+//Fraction of the new photons kept when the radius shrinks
+const ALPHA: f32 = 2.0 / 3.0;
+//Initial gather radius, in pixel footprints at the distance of the first visible point
+const INITIAL_RADIUS: f32 = 4.0;
+
+//Stochastic progressive photon mapping [5]. Every iteration traces one camera path per pixel
+//through specular surfaces to a visible point, then shoots photons from the DiffuseLight
+//emitters of the light list and gathers them around the visible points. The gather radius of
+//every pixel shrinks over the iterations, so the estimate converges to the right answer.
+//Direct light is sampled at the visible points and photons carry the indirect light of the
+//emitters. Indirect light from the background is path traced from the visible points.
+//Participating media only receive direct light
+pub struct PhotonMapping<'a> {
+    bvh: &'a SceneBVH,
+    camera: &'a Camera,
+    background: &'a Environment,
+    light: &'a [Object],
+    emitters: Vec<(&'a Object, f32)>,
+    camera_media: MediumStack<'a>,
+    depth: i32,
+    width: usize,
+    height: usize,
+    seed: Option<u64>,
+}
+
+//Progressive estimate of a pixel
+#[derive(Clone, Copy)]
+struct PixelState {
+    //Sum of the light that is sampled instead of gathered
+    traced: Vector3<f32>,
+    radius: f32,
+    n: f32,
+    tau: Vector3<f32>,
+}
+
+//First non specular hit of the camera path of a pixel in the current iteration
+struct VisiblePoint<'a> {
+    hit: HitRecord<'a>,
+    r_in: Ray,
+    attenuation: Vector3<f32>,
+    beta: Vector3<f32>,
+    radius: f32,
+}
+
+//Photons gathered by a visible point in the current iteration
+struct Gathered {
+    phi: [AtomicU32; 3],
+    m: AtomicU32,
+}
+
+impl Gathered {
+    fn new() -> Self {
+        Self {
+            phi: [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)],
+            m: AtomicU32::new(0),
+        }
+    }
+}
+
+//Uniform grid over the visible points, hashed on the cell coordinates
+struct HashGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl HashGrid {
+    fn new(points: &[Option<VisiblePoint>]) -> Self {
+        let cell_size = points
+            .iter()
+            .flatten()
+            .fold(0.0, |max, point| fmax(max, point.radius));
+        let mut grid = Self {
+            cell_size: fmax(cell_size, 1e-6),
+            cells: HashMap::new(),
+        };
+
+        for (i, point) in points.iter().enumerate() {
+            if let Some(point) = point {
+                let radius = Vector3::new(point.radius, point.radius, point.radius);
+                let min = grid.cell(point.hit.p - radius);
+                let max = grid.cell(point.hit.p + radius);
+                for x in min.0..=max.0 {
+                    for y in min.1..=max.1 {
+                        for z in min.2..=max.2 {
+                            grid.cells.entry((x, y, z)).or_default().push(i);
+                        }
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, p: Vector3<f32>) -> (i32, i32, i32) {
+        (
+            (p.x / self.cell_size).floor() as i32,
+            (p.y / self.cell_size).floor() as i32,
+            (p.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn get(&self, p: Vector3<f32>) -> &[usize] {
+        self.cells.get(&self.cell(p)).map_or(&[], |cell| cell)
+    }
+}
+
+impl<'a> PhotonMapping<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        bvh: &'a SceneBVH,
+        camera: &'a Camera,
+        background: &'a Environment,
+        light: &'a [Object],
+        depth: i32,
+        width: usize,
+        height: usize,
+        seed: Option<u64>,
+    ) -> Self {
+        let emitters = light
+            .iter()
+            .filter_map(|light| light.emitter_area().map(|area| (light, area)))
+            .collect();
+        Self {
+            bvh,
+            camera,
+            background,
+            light,
+            emitters,
+            camera_media: MediumStack::at(bvh, camera.origin),
+            depth,
+            width,
+            height,
+            seed,
+        }
+    }
+
+    //Runs the given number of iterations, shooting one photon per pixel in each of them
+    pub fn render(&self, iterations: usize, frame: &mut [f32]) {
+        let pb = ProgressBar::new(iterations as u64);
+        pb.set_style(ProgressStyle::default_bar().template("{bar:40.green/white}  {percent} %"));
+
+        let n_pixels = self.width * self.height;
+        let photons = n_pixels;
+        let mut pixels = vec![
+            PixelState {
+                traced: Vector3::new(0.0, 0.0, 0.0),
+                radius: 0.0,
+                n: 0.0,
+                tau: Vector3::new(0.0, 0.0, 0.0),
+            };
+            n_pixels
+        ];
+
+        for iteration in 0..iterations {
+            //Camera paths and photons of every iteration have streams of their own
+            let stream = (iteration * (n_pixels + photons)) as u64;
+            let points: Vec<Option<VisiblePoint>> = pixels
+                .par_iter_mut()
+                .enumerate()
+                .map(|(i, pixel)| {
+                    let mut rng = seeded_rng(self.seed, stream + i as u64);
+                    self.camera_path(i, pixel, &mut rng)
+                })
+                .collect();
+
+            let grid = HashGrid::new(&points);
+            let gathered: Vec<Gathered> = (0..n_pixels).map(|_| Gathered::new()).collect();
+            (0..photons).into_par_iter().for_each(|i| {
+                let mut rng = seeded_rng(self.seed, stream + (n_pixels + i) as u64);
+                self.trace_photon(&grid, &points, &gathered, &mut rng);
+            });
+
+            pixels
+                .par_iter_mut()
+                .zip(points.par_iter().zip(gathered.par_iter()))
+                .for_each(|(pixel, (point, gathered))| {
+                    if let Some(point) = point {
+                        let m = atomic_load(&gathered.m);
+                        if m > 0.0 {
+                            let phi = Vector3::new(
+                                atomic_load(&gathered.phi[0]),
+                                atomic_load(&gathered.phi[1]),
+                                atomic_load(&gathered.phi[2]),
+                            );
+                            let n = pixel.n + ALPHA * m;
+                            let radius = pixel.radius * (n / (pixel.n + m)).sqrt();
+                            let shrink = (radius / pixel.radius).powi(2);
+                            pixel.tau = (pixel.tau + point.beta * phi) * shrink;
+                            pixel.n = n;
+                            pixel.radius = radius;
+                        }
+                    }
+                });
+            pb.inc(1);
+        }
+        pb.finish_and_clear();
+
+        let total_photons = (iterations * photons) as f32;
+        frame
+            .par_chunks_mut(3)
+            .zip(pixels.par_iter())
+            .for_each(|(color, pixel)| {
+                let mut l = pixel.traced / iterations as f32;
+                if pixel.radius > 0.0 {
+                    l += pixel.tau / (total_photons * PI * pixel.radius * pixel.radius);
+                }
+                color.copy_from_slice(&l.to_array());
+            });
+    }
+
+    //Follows the camera ray of pixel i through specular surfaces. Emission seen directly and
+    //direct light at every non specular vertex is added to the pixel. Glowing media are only seen
+    //this way, they do not shoot photons
+    fn camera_path(
+        &self,
+        i: usize,
+        pixel: &mut PixelState,
+        rng: &mut impl Rng,
+    ) -> Option<VisiblePoint<'a>> {
+        let x = (i % self.width) as f32;
+        let y = (i / self.width) as f32;
+        let u = (x + rng.gen::<f32>()) / (self.width - 1) as f32;
+        let v = 1.0 - (y + rng.gen::<f32>()) / (self.height - 1) as f32;
+        let mut ray = self.camera.get_ray(u, v, rng);
+
+        let mut beta = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray_type = RayType::Camera;
+        let mut specular_bounce = true;
+        let mut travelled = 0.0;
+        let mut media = self.camera_media;
+        let mut bounces = 0;
+        while bounces < self.depth {
+            let (hit, weight, emitted) =
+                media.trace(self.bvh, &ray, f32::INFINITY, ray_type, &[], rng);
+            pixel.traced += beta * emitted;
+            beta = beta * weight;
+            let mut hit = match hit {
+                Some(hit) => hit,
+                None => {
+                    if specular_bounce {
+                        pixel.traced += beta * self.background.value(&ray, ray_type);
+                    }
+                    return None;
+                }
+            };
+            hit.material = hit.material.resolve(rng);
+            travelled += hit.t * ray.direction.magnitude();
+            if !media.walk_step(&hit) {
+                bounces += 1;
+            }
+
+            if specular_bounce {
+                pixel.traced += beta * hit.material.emit(hit.u, hit.v, hit.p, hit.front_face);
+            }
+
+            match hit.material.scatter(&ray, &hit, rng) {
+                None => return None,
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => {
+                    beta = beta * attenuation;
+                    ray_type = ray_type.after_specular(specular_ray.direction, hit.normal);
+                    ray = specular_ray;
+                    media.cross(&hit, ray.direction);
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation })
+                | Some(ScatterRecord::SpecularDiffuse { pdf, attenuation }) => {
+                    let direct = self.direct_light(&ray, &hit, media, attenuation, &pdf, rng);
+                    pixel.traced += beta * direct;
+
+                    if hit.material.is_medium() {
+                        let direction = pdf.sample(rng);
+                        let scattered = Ray::new(hit.p, direction);
+                        beta = beta * hit.material.eval_brdf(&ray, &hit, attenuation, &scattered)
+                            / pdf.value(direction);
+                        ray_type = RayType::Diffuse;
+                        specular_bounce = false;
+                        ray = scattered;
+                        media.cross(&hit, ray.direction);
+                        continue;
+                    }
+
+                    pixel.traced +=
+                        beta * self.background_indirect(&ray, &hit, media, attenuation, &pdf, rng);
+                    if pixel.radius == 0.0 {
+                        let footprint =
+                            self.camera.viewport_height * travelled / self.height as f32;
+                        pixel.radius = INITIAL_RADIUS * footprint;
+                    }
+                    return Some(VisiblePoint {
+                        hit,
+                        r_in: ray,
+                        attenuation,
+                        beta,
+                        radius: pixel.radius,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    //One sample of the light reaching hit straight from an emitter or the background, from the
+    //same mixture of light and BSDF sampling as the path tracer. media are the media r_in went
+    //through
+    fn direct_light(
+        &self,
+        r_in: &Ray,
+        hit: &HitRecord<'a>,
+        media: MediumStack<'a>,
+        attenuation: Vector3<f32>,
+        pdf: &PDFType,
+        rng: &mut impl Rng,
+    ) -> Vector3<f32> {
+        let lit_by = hit.visibility.lit_by;
+        let pdf_light = PDF::new(hit.p, self.light, lit_by);
+        let chance = if pdf_light.linked().next().is_some() {
+            0.5
+        } else {
+            0.0
+        };
+        let pdf_lights = PDFType::PDFObj { pdf: pdf_light };
+        let mixture = PDFMixture::new(&pdf_lights, pdf);
+
+        let scattered = Ray::new(hit.p, mixture.sample(chance, rng));
+        let pdf_val = mixture.value(chance, scattered.direction);
+        let f = hit.material.eval_brdf(r_in, hit, attenuation, &scattered);
+        if pdf_val.is_nan() || pdf_val <= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        let (light, weight, _) = media.towards(hit, scattered.direction).trace(
+            self.bvh,
+            &scattered,
+            f32::INFINITY,
+            RayType::Diffuse,
+            &[],
+            rng,
+        );
+        let emitted = match light {
+            Some(light) if light.visibility.lights(lit_by) => {
+                light
+                    .material
+                    .emit(light.u, light.v, light.p, light.front_face)
+            }
+            Some(_) => return Vector3::new(0.0, 0.0, 0.0),
+            None => self.background.value(&scattered, RayType::Diffuse),
+        };
+        let l = f * weight * emitted / pdf_val;
+        if l.to_array().iter().all(|c| c.is_finite()) {
+            l
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    //Light from the background reaching hit after at least one more bounce. Photons only leave
+    //the emitters, so this part of the indirect light is path traced with BSDF sampling
+    fn background_indirect(
+        &self,
+        r_in: &Ray,
+        hit: &HitRecord<'a>,
+        media: MediumStack<'a>,
+        attenuation: Vector3<f32>,
+        pdf: &PDFType,
+        rng: &mut impl Rng,
+    ) -> Vector3<f32> {
+        let direction = pdf.sample(rng);
+        let pdf_val = pdf.value(direction);
+        if pdf_val.is_nan() || pdf_val <= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let mut ray = Ray::new(hit.p, direction);
+        let mut beta = hit.material.eval_brdf(r_in, hit, attenuation, &ray) / pdf_val;
+        let mut media = media.towards(hit, direction);
+
+        let mut bounces = 0;
+        while bounces < self.depth {
+            let (hit, weight, _) =
+                media.trace(self.bvh, &ray, f32::INFINITY, RayType::Diffuse, &[], rng);
+            beta = beta * weight;
+            let mut hit = match hit {
+                Some(hit) => hit,
+                None if bounces > 0 => {
+                    let l = beta * self.background.value(&ray, RayType::Diffuse);
+                    return if l.to_array().iter().all(|c| c.is_finite()) {
+                        l
+                    } else {
+                        Vector3::new(0.0, 0.0, 0.0)
+                    };
+                }
+                None => break,
+            };
+            hit.material = hit.material.resolve(rng);
+
+            match hit.material.scatter(&ray, &hit, rng) {
+                None => break,
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => {
+                    beta = beta * attenuation;
+                    ray = specular_ray;
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation })
+                | Some(ScatterRecord::SpecularDiffuse { pdf, attenuation }) => {
+                    let direction = pdf.sample(rng);
+                    let scattered = Ray::new(hit.p, direction);
+                    let pdf_val = pdf.value(direction);
+                    if pdf_val.is_nan() || pdf_val <= 0.0 {
+                        break;
+                    }
+                    beta = beta * hit.material.eval_brdf(&ray, &hit, attenuation, &scattered)
+                        / pdf_val;
+                    ray = scattered;
+                }
+            }
+            media.cross(&hit, ray.direction);
+            if media.walk_step(&hit) {
+                continue;
+            }
+
+            //Russian roulette
+            if bounces > 5 {
+                let q = fmax(0.03, 1.0 - beta.max_axis());
+                if rng.gen::<f32>() < q {
+                    break;
+                }
+                beta /= 1.0 - q;
+            }
+            bounces += 1;
+        }
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+
+    fn trace_photon(
+        &self,
+        grid: &HashGrid,
+        points: &[Option<VisiblePoint>],
+        gathered: &[Gathered],
+        rng: &mut impl Rng,
+    ) {
+        if self.emitters.is_empty() {
+            return;
+        }
+        let (emitter, area) = self.emitters[rng.gen_range(0..self.emitters.len())];
+        let (p, normal) = emitter.sample_surface(rng);
+        let pdf_position = 1.0 / (self.emitters.len() as f32 * area);
+        let emitted = emitter.emitted(p, normal);
+
+        //Cosine weighted emission, the cosine cancels with the density
+        let direction = ONB::build_from(normal).local(Vector3::random_cosine_direction(rng));
+        let mut beta = emitted * PI / pdf_position;
+        let mut ray = Ray::new(p, direction);
+        let mut media = MediumStack::at(self.bvh, p);
+
+        let mut depth = 0;
+        while depth < self.depth {
+            if beta.max_axis() <= 0.0 {
+                return;
+            }
+            let (hit, weight, _) =
+                media.trace(self.bvh, &ray, f32::INFINITY, RayType::Diffuse, &[], rng);
+            beta = beta * weight;
+            let mut hit = match hit {
+                Some(hit) => hit,
+                None => return,
+            };
+            hit.material = hit.material.resolve(rng);
+            //As in the path tracer, the emitter only lights the surfaces linked to its group
+            if depth == 0 && emitter.light_group() & hit.visibility.lit_by == 0 {
+                return;
+            }
+
+            match hit.material.scatter(&ray, &hit, rng) {
+                None => return,
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => {
+                    beta = beta * attenuation;
+                    ray = specular_ray;
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation })
+                | Some(ScatterRecord::SpecularDiffuse { pdf, attenuation }) => {
+                    //Photons straight from the emitters are direct light
+                    if depth > 0 && !hit.material.is_medium() {
+                        self.deposit(&hit, ray.direction, beta, grid, points, gathered);
+                    }
+
+                    let direction = pdf.sample(rng);
+                    let scattered = Ray::new(hit.p, direction);
+                    let pdf_val = pdf.value(direction);
+                    let f = hit.material.eval_brdf(&ray, &hit, attenuation, &scattered);
+                    if pdf_val.is_nan() || pdf_val <= 0.0 {
+                        return;
+                    }
+                    beta = beta * f / pdf_val;
+                    ray = scattered;
+                }
+            }
+            media.cross(&hit, ray.direction);
+            if media.walk_step(&hit) {
+                continue;
+            }
+
+            //Russian roulette
+            if depth > 5 {
+                let q = fmax(0.03, 1.0 - beta.max_axis());
+                if rng.gen::<f32>() < q {
+                    return;
+                }
+                beta /= 1.0 - q;
+            }
+            depth += 1;
+        }
+    }
+
+    //Adds a photon arriving along direction to the visible points around the hit
+    fn deposit(
+        &self,
+        hit: &HitRecord,
+        direction: Vector3<f32>,
+        beta: Vector3<f32>,
+        grid: &HashGrid,
+        points: &[Option<VisiblePoint>],
+        gathered: &[Gathered],
+    ) {
+        for &i in grid.get(hit.p) {
+            let point = points[i].as_ref().unwrap();
+            if (point.hit.p - hit.p).magnitude2() > point.radius * point.radius {
+                continue;
+            }
+            //eval_brdf includes the cosine towards the photon, the density estimate does not
+            let towards_photon = direction * (-1.0);
+            let cosine = Vector3::dot(point.hit.normal, towards_photon.norm());
+            if cosine <= 0.0 {
+                continue;
+            }
+            let f = point.hit.material.eval_brdf(
+                &point.r_in,
+                &point.hit,
+                point.attenuation,
+                &Ray::new(point.hit.p, towards_photon),
+            ) / cosine;
+            let phi = beta * f;
+            if !phi.to_array().iter().all(|c| c.is_finite()) {
+                continue;
+            }
+            for (c, value) in gathered[i].phi.iter().zip(phi.to_array()) {
+                atomic_add(c, value);
+            }
+            atomic_add(&gathered[i].m, 1.0);
+        }
+    }
+}
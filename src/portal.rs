@@ -0,0 +1,206 @@
+use rand::Rng;
+
+use crate::{
+    background::EnviromentalMap,
+    ray::Ray,
+    utilities::{math::fmax, onb::ONB, vector3::Vector3},
+};
+
+//Directions per side of the grid that averages the environment behind a portal
+const GRID: usize = 16;
+
+//Environment light sampled through the openings of an interior, BSDF sampling covers the rest
+#[derive(Clone)]
+pub struct Portal {
+    quads: Vec<PortalQuad>,
+    cdf: Vec<f32>,
+}
+
+#[derive(Clone)]
+pub struct PortalQuad {
+    corner: Vector3<f32>,
+    u: Vector3<f32>,
+    v: Vector3<f32>,
+    w: Vector3<f32>,
+    normal: Vector3<f32>,
+    area: f32,
+}
+
+impl PortalQuad {
+    //Parallelogram spanned by the edges u and v from corner
+    pub fn new(corner: Vector3<f32>, u: Vector3<f32>, v: Vector3<f32>) -> Self {
+        let n = Vector3::cross(u, v);
+        Self {
+            corner,
+            u,
+            v,
+            w: n / n.magnitude2(),
+            normal: n.norm(),
+            area: n.magnitude(),
+        }
+    }
+
+    //Returns the ray parameter of the crossing point
+    fn hit(&self, r: &Ray) -> Option<f32> {
+        let denom = Vector3::dot(self.normal, r.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = Vector3::dot(self.normal, self.corner - r.origin) / denom;
+        if t < 0.001 {
+            return None;
+        }
+        let p = r.at(t) - self.corner;
+        let a = Vector3::dot(self.w, Vector3::cross(p, self.v));
+        let b = Vector3::dot(self.w, Vector3::cross(self.u, p));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+        Some(t)
+    }
+
+    fn point(&self, a: f32, b: f32) -> Vector3<f32> {
+        self.corner + self.u * a + self.v * b
+    }
+}
+
+impl Portal {
+    pub fn new(env_map: &EnviromentalMap, quads: Vec<PortalQuad>) -> Self {
+        //Either side of a quad may face the sky, the brighter one is taken
+        let weights: Vec<f32> = quads
+            .iter()
+            .map(|quad| {
+                let behind = fmax(
+                    hemisphere_luminance(env_map, quad.normal),
+                    hemisphere_luminance(env_map, quad.normal * (-1.0)),
+                );
+                quad.area * fmax(behind, 1e-4)
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+        let cdf = weights
+            .iter()
+            .scan(0.0, |sum, weight| {
+                *sum += weight / total;
+                Some(*sum)
+            })
+            .collect();
+        Self { quads, cdf }
+    }
+
+    fn probability(&self, q: usize) -> f32 {
+        if q == 0 {
+            self.cdf[0]
+        } else {
+            self.cdf[q] - self.cdf[q - 1]
+        }
+    }
+
+    pub fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        let r = Ray::new(o, v);
+        self.quads
+            .iter()
+            .enumerate()
+            .filter_map(|(q, quad)| quad.hit(&r).map(|t| (q, quad, t)))
+            .map(|(q, quad, t)| {
+                let pdf_area = self.probability(q) / quad.area;
+
+                let distance_2 = t * t * v.magnitude2();
+                let cosine = Vector3::dot(v, quad.normal).abs() / v.magnitude();
+                if cosine == 0.0 {
+                    return 0.0;
+                }
+                pdf_area * distance_2 / cosine
+            })
+            .sum()
+    }
+
+    pub fn random(&self, o: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
+        let u = rng.gen::<f32>();
+        let chosen = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.quads.len() - 1);
+        let quad = &self.quads[chosen];
+        quad.point(rng.gen::<f32>(), rng.gen::<f32>()) - o
+    }
+}
+
+//Cosine weighted mean luminance of the environment over the hemisphere around normal
+fn hemisphere_luminance(env_map: &EnviromentalMap, normal: Vector3<f32>) -> f32 {
+    let onb = ONB::build_from(normal);
+    let sum: f32 = (0..GRID * GRID)
+        .map(|cell| {
+            let a = ((cell % GRID) as f32 + 0.5) / GRID as f32;
+            let b = ((cell / GRID) as f32 + 0.5) / GRID as f32;
+            let (r, phi) = (a.sqrt(), 2.0 * std::f32::consts::PI * b);
+            let direction = Vector3::new(r * phi.cos(), r * phi.sin(), (1.0 - a).sqrt());
+            env_map.value(onb.local(direction)).luminance()
+        })
+        .sum();
+    sum / (GRID * GRID) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use image::Rgb;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    fn portal() -> Portal {
+        //Sky brighter above the horizon
+        let image_v = (0..8 * 4)
+            .map(|i| {
+                if i < 16 {
+                    Rgb([4.0, 4.0, 4.0])
+                } else {
+                    Rgb([0.5, 0.5, 0.5])
+                }
+            })
+            .collect();
+        let env_map = EnviromentalMap::new(
+            Arc::new(image_v),
+            8.0,
+            4.0,
+            ONB::from_euler(Vector3::new(0.0, 0.0, 0.0)),
+        );
+        let roof = PortalQuad::new(
+            Vector3::new(-1.0, 2.0, -1.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+        );
+        let window = PortalQuad::new(
+            Vector3::new(3.0, 0.0, -0.5),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+        Portal::new(&env_map, vec![roof, window])
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let portal = portal();
+        let mut rng = SmallRng::seed_from_u64(7);
+        let o = Vector3::new(0.2, 0.5, 0.1);
+        let n = 400_000;
+        let sum: f32 = (0..n)
+            .map(|_| portal.pdf_value(o, Vector3::random_unit_vector(&mut rng)))
+            .sum();
+        let integral = sum * 4.0 * std::f32::consts::PI / n as f32;
+        assert!((integral - 1.0).abs() < 0.02, "integral {}", integral);
+    }
+
+    #[test]
+    fn samples_go_through_portals() {
+        let portal = portal();
+        let mut rng = SmallRng::seed_from_u64(3);
+        let o = Vector3::new(0.2, 0.5, 0.1);
+        for _ in 0..1000 {
+            let direction = portal.random(o, &mut rng);
+            assert!(portal.pdf_value(o, direction) > 0.0);
+        }
+    }
+}
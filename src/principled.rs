@@ -0,0 +1,283 @@
+use rand::Rng;
+
+use crate::{
+    material::relative_ior,
+    microfacet::{dielectric_scattering, gtr1, reflect, refract, sample_gtr1, schlick_weight, GGX},
+    ray::HitRecord,
+    texture::Texture,
+    utilities::{onb::ONB, vector3::Vector3},
+};
+
+const PI: f32 = std::f32::consts::PI;
+//Roughness of the clearcoat highlight, Disney's clearcoat gloss of 1
+const CLEARCOAT_ALPHA: f32 = 0.001;
+const SHEEN_TINT: f32 = 0.5;
+
+//Disney's principled BSDF [12]: a Burley diffuse lobe with sheen, an anisotropic GGX specular
+//lobe [13] tinted toward the base color as it turns metallic, a clearcoat layer and rough
+//transmission. Every parameter is a texture, scalar ones read their red channel
+#[derive(Clone)]
+pub struct Principled {
+    base_color: Texture,
+    metallic: Texture,
+    roughness: Texture,
+    specular: Texture,
+    specular_tint: Texture,
+    anisotropic: Texture,
+    sheen: Texture,
+    clearcoat: Texture,
+    transmission: Texture,
+    ior: f32,
+}
+
+#[allow(dead_code)]
+impl Principled {
+    pub fn new(base_color: impl Into<Texture>) -> Self {
+        Self {
+            base_color: base_color.into(),
+            metallic: 0.0.into(),
+            roughness: 0.5.into(),
+            specular: 0.5.into(),
+            specular_tint: 0.0.into(),
+            anisotropic: 0.0.into(),
+            sheen: 0.0.into(),
+            clearcoat: 0.0.into(),
+            transmission: 0.0.into(),
+            ior: 1.5,
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: impl Into<Texture>) -> Self {
+        self.metallic = metallic.into();
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: impl Into<Texture>) -> Self {
+        self.roughness = roughness.into();
+        self
+    }
+
+    pub fn with_specular(mut self, specular: impl Into<Texture>) -> Self {
+        self.specular = specular.into();
+        self
+    }
+
+    pub fn with_specular_tint(mut self, specular_tint: impl Into<Texture>) -> Self {
+        self.specular_tint = specular_tint.into();
+        self
+    }
+
+    pub fn with_anisotropic(mut self, anisotropic: impl Into<Texture>) -> Self {
+        self.anisotropic = anisotropic.into();
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: impl Into<Texture>) -> Self {
+        self.sheen = sheen.into();
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: impl Into<Texture>) -> Self {
+        self.clearcoat = clearcoat.into();
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: impl Into<Texture>) -> Self {
+        self.transmission = transmission.into();
+        self
+    }
+
+    pub fn with_ior(mut self, ior: f32) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    pub fn base_color(&self, hit: &HitRecord) -> Vector3<f32> {
+        self.base_color.value(hit.u, hit.v, hit.p)
+    }
+
+    //Lobes at the hit point with the given base color, for light arriving along r_in_direction
+    pub fn at(
+        &self,
+        hit: &HitRecord,
+        base_color: Vector3<f32>,
+        r_in_direction: Vector3<f32>,
+    ) -> PrincipledBSDF {
+        let scalar = |texture: &Texture| texture.value(hit.u, hit.v, hit.p).x.clamp(0.0, 1.0);
+        PrincipledBSDF {
+            wo: r_in_direction.norm() * (-1.0),
+            onb: hit.shading_frame(),
+            base_color,
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            transmission: scalar(&self.transmission),
+            distribution: GGX::from_roughness(scalar(&self.roughness), scalar(&self.anisotropic)),
+            eta: relative_ior(hit, self.ior),
+        }
+    }
+}
+
+//Principled BSDF evaluated at a hit point, seen from wo
+#[derive(Clone, Copy)]
+pub struct PrincipledBSDF {
+    wo: Vector3<f32>,
+    onb: ONB,
+    base_color: Vector3<f32>,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    clearcoat: f32,
+    transmission: f32,
+    distribution: GGX,
+    eta: f32,
+}
+
+impl PrincipledBSDF {
+    fn tint(&self) -> Vector3<f32> {
+        let luminance = self.base_color.luminance();
+        if luminance > 0.0 {
+            self.base_color / luminance
+        } else {
+            Vector3::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    fn lerp(t: f32, a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+        a * (1.0 - t) + b * t
+    }
+
+    //Chances of sampling the diffuse, specular, clearcoat and transmission lobes
+    fn lobe_weights(&self) -> [f32; 4] {
+        let dielectric = 1.0 - self.metallic;
+        let weights = [
+            dielectric * (1.0 - self.transmission),
+            1.0,
+            0.25 * self.clearcoat,
+            dielectric * self.transmission,
+        ];
+        let total: f32 = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    //BSDF times the cosine towards wi
+    pub fn eval(&self, wi: Vector3<f32>) -> Vector3<f32> {
+        let black = Vector3::new(0.0, 0.0, 0.0);
+        let wo = self.onb.to_local(self.wo);
+        if wo.z <= 0.0 || wi.near_zero() {
+            return black;
+        }
+        let wi = self.onb.to_local(wi.norm());
+        if wi.z == 0.0 {
+            return black;
+        }
+        let ones = Vector3::new(1.0, 1.0, 1.0);
+        let dielectric = 1.0 - self.metallic;
+
+        if wi.z < 0.0 {
+            let transmission = dielectric * self.transmission;
+            if transmission <= 0.0 {
+                return black;
+            }
+            let btdf = dielectric_scattering(&self.distribution, wo, wi, self.eta)
+                .map_or(0.0, |s| s.value);
+            let color = Vector3::new(
+                self.base_color.x.sqrt(),
+                self.base_color.y.sqrt(),
+                self.base_color.z.sqrt(),
+            );
+            return color * (transmission * btdf);
+        }
+
+        let h = (wo + wi).norm();
+        let cos_d = Vector3::dot(wi, h);
+        let (f_l, f_v, f_h) = (
+            schlick_weight(wi.z),
+            schlick_weight(wo.z),
+            schlick_weight(cos_d),
+        );
+
+        //Burley diffuse with retro-reflection at grazing angles, and sheen
+        let f_d90 = 0.5 + 2.0 * cos_d * cos_d * self.roughness;
+        let f_d = (1.0 + (f_d90 - 1.0) * f_l) * (1.0 + (f_d90 - 1.0) * f_v);
+        let sheen = Self::lerp(SHEEN_TINT, ones, self.tint()) * (self.sheen * f_h);
+        let diffuse =
+            (self.base_color * (f_d / PI) + sheen) * (dielectric * (1.0 - self.transmission));
+
+        let specular_0 = Self::lerp(
+            self.metallic,
+            Self::lerp(self.specular_tint, ones, self.tint()) * (self.specular * 0.08),
+            self.base_color,
+        );
+        let fresnel = Self::lerp(f_h, specular_0, ones);
+        let specular =
+            fresnel * (self.distribution.d(h) * self.distribution.g(wo, wi) / (4.0 * wo.z * wi.z));
+
+        let clearcoat_distribution = GGX::new(0.25, 0.25);
+        let clearcoat = 0.25
+            * self.clearcoat
+            * gtr1(h.z, CLEARCOAT_ALPHA)
+            * (0.04 + 0.96 * f_h)
+            * clearcoat_distribution.g1(wo)
+            * clearcoat_distribution.g1(wi)
+            / (4.0 * wo.z * wi.z);
+
+        (diffuse + specular + ones * clearcoat) * wi.z
+    }
+
+    pub fn pdf(&self, wi: Vector3<f32>) -> f32 {
+        let wo = self.onb.to_local(self.wo);
+        if wo.z <= 0.0 || wi.near_zero() {
+            return 0.0;
+        }
+        let wi = self.onb.to_local(wi.norm());
+        let [diffuse, specular, clearcoat, transmission] = self.lobe_weights();
+
+        if wi.z < 0.0 {
+            return dielectric_scattering(&self.distribution, wo, wi, self.eta).map_or(0.0, |s| {
+                transmission * self.distribution.pdf_h(s.h) * s.jacobian
+            });
+        }
+
+        let h = (wo + wi).norm();
+        let dot_o = Vector3::dot(wo, h);
+        if dot_o <= 0.0 {
+            return 0.0;
+        }
+        diffuse * wi.z / PI
+            + specular * self.distribution.pdf_h(h) / (4.0 * dot_o)
+            + clearcoat * gtr1(h.z, CLEARCOAT_ALPHA) * h.z / (4.0 * dot_o)
+    }
+
+    //Microfacets may send a sample to the wrong side of the surface, or reflect it totally. Those
+    //samples are lost, they come out as the zero direction, whose density is zero
+    pub fn sample(&self, rng: &mut impl Rng) -> Vector3<f32> {
+        let lost = Vector3::new(0.0, 0.0, 0.0);
+        let wo = self.onb.to_local(self.wo);
+        let [diffuse, specular, clearcoat, _] = self.lobe_weights();
+        let u = rng.gen::<f32>();
+        let wi = if u < diffuse {
+            Vector3::random_cosine_direction(rng)
+        } else if u < diffuse + specular {
+            reflect(wo, self.distribution.sample_h(rng))
+        } else if u < diffuse + specular + clearcoat {
+            reflect(wo, sample_gtr1(CLEARCOAT_ALPHA, rng))
+        } else {
+            let h = self.distribution.sample_h(rng);
+            match refract(wo, h, self.eta) {
+                Some(wi) if wi.z < 0.0 && Vector3::dot(wo, h) > 0.0 => return self.onb.local(wi),
+                _ => return lost,
+            }
+        };
+        if wi.z > 0.0 {
+            self.onb.local(wi)
+        } else {
+            lost
+        }
+    }
+}
@@ -1,19 +1,52 @@
-use crate::{material::Material, utilities::vector3::Vector3};
+use crate::{
+    material::Material,
+    medium::{Interior, Medium},
+    utilities::{onb::ONB, vector3::Vector3},
+    visibility::RayVisibility,
+};
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Vector3<f32>,
     pub direction: Vector3<f32>,
+    //Hero wavelength in nm of a spectral path, dispersive materials refract by it
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
     pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            wavelength: None,
+        }
     }
     pub fn at(&self, t: f32) -> Vector3<f32> {
         self.origin + self.direction * t
     }
 }
 
+//Kind of event that produced a ray. Once a path has scattered diffusely it stays Diffuse
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RayType {
+    Camera,
+    Reflection,
+    Refraction,
+    Diffuse,
+}
+
+impl RayType {
+    //Ray type after a specular event that sent the ray to the given side of the surface
+    pub fn after_specular(self, direction: Vector3<f32>, normal: Vector3<f32>) -> Self {
+        match self {
+            Self::Diffuse => Self::Diffuse,
+            _ if Vector3::dot(direction, normal) > 0.0 => Self::Reflection,
+            _ => Self::Refraction,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub p: Vector3<f32>,
     pub normal: Vector3<f32>,
@@ -22,6 +55,16 @@ pub struct HitRecord<'a> {
     pub v: f32,
     pub front_face: bool,
     pub material: &'a Material,
+    pub visibility: RayVisibility,
+    //Medium enclosed by the surface hit, if it bounds one
+    pub medium: Option<&'a Medium>,
+    //Dielectric interior enclosed by the surface hit, if it bounds one
+    pub interior: Option<&'a Interior>,
+    //Ratio of the indices of refraction across the surface, set when interiors are tracked
+    pub eta: Option<f32>,
+    //Unit directions of increasing u and v on the surface, zero where they are unknown
+    pub tangent: Vector3<f32>,
+    pub bitangent: Vector3<f32>,
 }
 impl<'a> HitRecord<'a> {
     pub fn new(
@@ -45,6 +88,38 @@ impl<'a> HitRecord<'a> {
             v,
             front_face,
             material,
+            visibility: RayVisibility::default(),
+            medium: None,
+            interior: None,
+            eta: None,
+            tangent: Vector3::new(0.0, 0.0, 0.0),
+            bitangent: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    //Tangent frame from a direction along u, made orthogonal to the normal. The bitangent is
+    //the cross product of the outward normal and the tangent, flipped by a negative handedness
+    pub fn with_tangent(mut self, tangent: Vector3<f32>, handedness: f32) -> Self {
+        let outward = self.outward_normal();
+        let tangent = tangent - outward * Vector3::dot(outward, tangent);
+        if tangent.magnitude2() > 1e-12 {
+            self.tangent = tangent.norm();
+            self.bitangent = Vector3::cross(outward, self.tangent) * handedness.signum();
+        }
+        self
+    }
+
+    //Frame of the shading normal whose u axis follows the tangent, anisotropic materials stretch
+    //their highlights along it
+    pub fn shading_frame(&self) -> ONB {
+        ONB::from_tangent(self.normal, self.tangent)
+    }
+
+    pub fn outward_normal(&self) -> Vector3<f32> {
+        if self.front_face {
+            self.normal.norm()
+        } else {
+            self.normal.norm() * (-1.0)
         }
     }
 }
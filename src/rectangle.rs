@@ -1,11 +1,11 @@
-use std::mem;
+use std::{mem, sync::Arc};
 
-use rand::prelude::ThreadRng;
 use rand::Rng;
 
 use crate::{
     aabb::AABB,
     material::Material,
+    medium::{Interior, Medium},
     object::{Hittable, Object},
     ray::{HitRecord, Ray},
     utilities::vector3::Vector3,
@@ -89,13 +89,21 @@ impl XYRect {
         0.0
     }
 
-    pub fn random(&self, origin: Vector3<f32>, rng: &mut ThreadRng) -> Vector3<f32> {
+    pub fn random(&self, origin: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
         Vector3::new(
             rng.gen_range(self.x0..self.x1),
             rng.gen_range(self.y0..self.y1),
             self.k,
         ) - origin
     }
+    pub fn area(&self) -> f32 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    //Uniform point on the rectangle and its outward normal
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vector3<f32>, Vector3<f32>) {
+        (self.random(Vector3::new(0.0, 0.0, 0.0), rng), self.normal)
+    }
 }
 
 impl Hittable for XYRect {
@@ -114,15 +122,10 @@ impl Hittable for XYRect {
 
         let u = (x - self.x0) / (self.x1 - self.x0);
         let v = (y - self.y0) / (self.y1 - self.y0);
-        Some(HitRecord::new(
-            r.at(t),
-            self.normal,
-            t,
-            u,
-            v,
-            r,
-            &self.material,
-        ))
+        Some(
+            HitRecord::new(r.at(t), self.normal, t, u, v, r, &self.material)
+                .with_tangent(Vector3::new(1.0, 0.0, 0.0), self.normal.z),
+        )
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -174,13 +177,20 @@ impl XZRect {
         0.0
     }
 
-    pub fn random(&self, origin: Vector3<f32>, rng: &mut ThreadRng) -> Vector3<f32> {
+    pub fn random(&self, origin: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
         Vector3::new(
             rng.gen_range(self.x0..self.x1),
             self.k,
             rng.gen_range(self.z0..self.z1),
         ) - origin
     }
+    pub fn area(&self) -> f32 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vector3<f32>, Vector3<f32>) {
+        (self.random(Vector3::new(0.0, 0.0, 0.0), rng), self.normal)
+    }
 }
 impl Hittable for XZRect {
     #[inline(always)]
@@ -198,15 +208,10 @@ impl Hittable for XZRect {
 
         let u = (x - self.x0) / (self.x1 - self.x0);
         let v = (z - self.z0) / (self.z1 - self.z0);
-        Some(HitRecord::new(
-            r.at(t),
-            self.normal,
-            t,
-            u,
-            v,
-            r,
-            &self.material,
-        ))
+        Some(
+            HitRecord::new(r.at(t), self.normal, t, u, v, r, &self.material)
+                .with_tangent(Vector3::new(1.0, 0.0, 0.0), -self.normal.y),
+        )
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -244,6 +249,19 @@ impl YZRect {
             bounding_box,
         }
     }
+
+    pub fn area(&self) -> f32 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vector3<f32>, Vector3<f32>) {
+        let point = Vector3::new(
+            self.k,
+            rng.gen_range(self.y0..self.y1),
+            rng.gen_range(self.z0..self.z1),
+        );
+        (point, self.normal)
+    }
 }
 impl Hittable for YZRect {
     #[inline(always)]
@@ -261,15 +279,10 @@ impl Hittable for YZRect {
 
         let u = (y - self.y0) / (self.y1 - self.y0);
         let v = (z - self.z0) / (self.z1 - self.z0);
-        Some(HitRecord::new(
-            r.at(t),
-            self.normal,
-            t,
-            u,
-            v,
-            r,
-            &self.material,
-        ))
+        Some(
+            HitRecord::new(r.at(t), self.normal, t, u, v, r, &self.material)
+                .with_tangent(Vector3::new(0.0, 1.0, 0.0), self.normal.x),
+        )
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -309,6 +322,22 @@ impl Prism {
         self
     }
 
+    //All faces bound the same dielectric interior
+    pub fn with_interior(mut self, interior: Arc<Interior>) -> Prism {
+        self.faces
+            .iter_mut()
+            .for_each(|face| *face = face.clone().with_interior(interior.clone()));
+        self
+    }
+
+    //All faces bound the same medium
+    pub fn with_medium(mut self, medium: Arc<Medium>) -> Prism {
+        self.faces
+            .iter_mut()
+            .for_each(|face| *face = face.clone().with_medium(medium.clone()));
+        self
+    }
+
     pub fn push_to_objects(&mut self, objects: &mut Vec<Object>) {
         objects.extend(mem::take(&mut self.faces));
     }
@@ -1,14 +1,24 @@
 use std::{path::Path, sync::Arc};
 
 use crate::{
-    background::{load_hdri, Background},
+    background::{load_hdri, Background, Environment},
     camera::Camera,
-    material::Material,
-    object::Object,
+    density::Density,
+    layered::Clearcoat,
+    material::{ComplexIor, Dispersion, Material},
+    measured::Measured,
+    medium::{Emission, Interior, Medium},
+    normal_map::NormalMap,
+    object::{Hittable, Object},
+    orientation::Orientation,
+    portal::PortalQuad,
+    principled::Principled,
     rectangle::Prism,
     texture::Texture,
+    thin_film::ThinFilm,
     triangle_mesh::TriangleMesh,
     utilities::vector3::Vector3,
+    visibility::RayVisibility,
 };
 use rand::Rng;
 #[allow(dead_code)]
@@ -20,11 +30,27 @@ pub enum Scenes {
     RectangleLight,
     CornellBox,
     Volumes,
+    Smoke,
+    Fire,
+    MeshVolume,
+    Liquid,
     Balls,
     Model3D,
     David,
     Sponza,
+    SponzaSky,
     Teapots,
+    Diamond,
+    Principled,
+    Frosted,
+    Iridescence,
+    Clearcoat,
+    Subsurface,
+    Bump,
+    Cutout,
+    Measured,
+    LightLinking,
+    Helmet,
 }
 
 impl Scenes {
@@ -221,7 +247,8 @@ impl Scenes {
                 )
             }
             Self::HDRITest => {
-                let (env_map, hdri) = load_hdri("HDRIs/tokyo.hdr", 0.0);
+                let (mut lights, background) =
+                    load_hdri("HDRIs/tokyo.hdr", Vector3::new(0.0, 0.0, 0.0), false);
 
                 let look_from = Vector3::new(-6.0, 1.0, 0.0);
                 let look_at = Vector3::new(0.0, 0.0, 0.0);
@@ -248,7 +275,7 @@ impl Scenes {
                     fuzz: 0.0,
                 };
                 let material_ground = Material::TexturedLambertian {
-                    texture: Texture::load_texture("textures/marble4.jpg"),
+                    texture: Texture::load_texture("textures/marble4.jpg").unwrap(),
                 };
 
                 let objects = vec![
@@ -257,15 +284,16 @@ impl Scenes {
                     Object::build_xz_rect(-5.0, 5.0, -5.0, 5.0, -0.98, material_ground, false),
                 ];
 
-                SceneConfig::new(
-                    objects,
-                    camera,
-                    vec![
-                        env_map,
-                        Object::build_sphere(Vector3::new(0.0, 0.0, -1.0), 0.98, cr),
-                    ],
-                    Background::new_hdri(hdri),
-                )
+                lights.push(Object::build_sphere(Vector3::new(0.0, 0.0, -1.0), 0.98, cr));
+
+                //The camera sees the map projected onto the ground past the marble slab, the
+                //lighting keeps it infinitely far away
+                let dome = background
+                    .clone()
+                    .with_dome(Vector3::new(0.0, -0.98, 0.0), 1.7, 30.0);
+                let environment = Environment::new(dome).with_lighting(background);
+
+                SceneConfig::new(objects, camera, lights, environment)
             }
 
             Self::HDRISun => {
@@ -385,7 +413,8 @@ impl Scenes {
                     SceneConfig::new(objects, camera, vec![env_map], Background::new_hdri(hdri))
                 }*/
 
-                let (env_map, hdri) = load_hdri("HDRIs/room.hdr", 120.0);
+                let (lights, background) =
+                    load_hdri("HDRIs/room.hdr", Vector3::new(0.0, 120.0, 0.0), true);
 
                 let look_from = Vector3::new(-6.0, 0.8, 0.0);
                 let look_at = Vector3::new(0.0, 0.0, 0.0);
@@ -405,9 +434,9 @@ impl Scenes {
                 );
 
                 let material_ground = Material::TexturedLambertian {
-                    texture: Texture::load_texture("textures/marble4.jpg"),
+                    texture: Texture::load_texture("textures/marble4.jpg").unwrap(),
                 };
-                let tex_wood = Texture::load_texture("textures/wood.jpg");
+                let tex_wood = Texture::load_texture("textures/wood.jpg").unwrap();
                 let mut teapot = TriangleMesh::load(
                     "objs/teapot_highpoly.obj",
                     0.06,
@@ -439,8 +468,12 @@ impl Scenes {
                         k_specular: 0.1,
                         nu: 1000.0,
                         nv: 1000.0,
-                    },*/ 
-                     Material::ColoredDielectric { index_of_refraction: 1.5, absorption: 10.3, color: Vector3::new(0.0,1.0,1.0)}
+                    },*/
+                    Material::ColoredDielectric {
+                        index_of_refraction: 1.5,
+                        absorption: 10.3,
+                        color: Vector3::new(0.0, 1.0, 1.0),
+                    },
                 );
 
                 let mut bunny = TriangleMesh::load(
@@ -492,7 +525,14 @@ impl Scenes {
                 teapot.push_to_objects(&mut objects);
                 bunny.push_to_objects(&mut objects);
 
-                SceneConfig::new(objects, camera, vec![env_map], Background::new_hdri(hdri))
+                //Lit by the room, shown in front of a plain studio backdrop that the glass
+                //refracts too. The metal ball still reflects the room
+                let backdrop = Background::new_plain(Vector3::new(0.18, 0.18, 0.2));
+                let environment = Environment::new(background)
+                    .with_camera(backdrop.clone())
+                    .with_refraction(backdrop);
+
+                SceneConfig::new(objects, camera, lights, environment)
             }
 
             Self::RectangleLight => {
@@ -513,7 +553,7 @@ impl Scenes {
                     2.0,
                 );
                 let marble_material = Material::TexturedLambertian {
-                    texture: Texture::load_texture("textures/marble.jpg"),
+                    texture: Texture::load_texture("textures/marble.jpg").unwrap(),
                 };
 
                 let mut objects = vec![Object::build_sphere(
@@ -701,6 +741,305 @@ impl Scenes {
                     // Background::new_hdri(hdri)
                 )
             }
+            Scenes::Smoke => {
+                let look_from = Vector3::new(278.0, 278.0, -800.0);
+                let look_at = Vector3::new(278.0, 278.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = 10.0;
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    40.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    3.0,
+                );
+
+                let red = Material::Lambertian {
+                    albedo: Vector3::new(0.65, 0.05, 0.05),
+                };
+                let white = Material::Lambertian {
+                    albedo: Vector3::new(0.73, 0.73, 0.73),
+                };
+                let green = Material::Lambertian {
+                    albedo: Vector3::new(0.12, 0.45, 0.15),
+                };
+                let difflight = Material::DiffuseLight {
+                    texture: Texture::SolidColor {
+                        albedo: Vector3::new(25.0, 25.0, 25.0),
+                    },
+                };
+
+                let mut objects = vec![
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 555.0, green, false),
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 0.0, red, false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 0.0, white.clone(), false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 555.0, white.clone(), false),
+                    Object::build_xy_rect(0.0, 555.0, 0.0, 555.0, 555.0, white, false),
+                ];
+                let rect_light =
+                    Object::build_xz_rect(213.0, 343.0, 227.0, 332.0, 554.0, difflight, true);
+                objects.push(rect_light.clone());
+
+                //Forward scattering cloud read from a .vol grid, fractal noise when there is none
+                let boundary = Object::build_sphere(
+                    Vector3::new(278.0, 250.0, 278.0),
+                    180.0,
+                    Material::Interface,
+                );
+                let density = Density::load_vol("volumes/cloud.vol").unwrap_or_else(|e| {
+                    println!("{}, using noise instead", e);
+                    Density::noise(7, 0.012, 5)
+                });
+                objects.push(boundary.build_heterogeneous_medium(
+                    density,
+                    0.002,
+                    0.03,
+                    Vector3::new(1.0, 1.0, 1.0),
+                    0.6,
+                ));
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![rect_light],
+                    Background::new_plain(Vector3::new(0.0, 0.0, 0.0)),
+                )
+            }
+            Scenes::Fire => {
+                let look_from = Vector3::new(278.0, 278.0, -800.0);
+                let look_at = Vector3::new(278.0, 278.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = 10.0;
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    40.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    3.0,
+                );
+
+                let red = Material::Lambertian {
+                    albedo: Vector3::new(0.65, 0.05, 0.05),
+                };
+                let white = Material::Lambertian {
+                    albedo: Vector3::new(0.73, 0.73, 0.73),
+                };
+                let green = Material::Lambertian {
+                    albedo: Vector3::new(0.12, 0.45, 0.15),
+                };
+                let difflight = Material::DiffuseLight {
+                    texture: Texture::SolidColor {
+                        albedo: Vector3::new(2.0, 2.0, 2.0),
+                    },
+                };
+
+                let mut objects = vec![
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 555.0, green, false),
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 0.0, red, false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 0.0, white.clone(), false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 555.0, white.clone(), false),
+                    Object::build_xy_rect(0.0, 555.0, 0.0, 555.0, 555.0, white, false),
+                ];
+                let rect_light =
+                    Object::build_xz_rect(213.0, 343.0, 227.0, 332.0, 554.0, difflight, true);
+                objects.push(rect_light.clone());
+
+                //Smoky fireball glowing with the blackbody color of a noisy temperature field, it
+                //lights the room by itself
+                let boundary = Object::build_sphere(
+                    Vector3::new(278.0, 230.0, 278.0),
+                    160.0,
+                    Material::Interface,
+                );
+                let bounds = boundary.bounding_box().clone();
+                let fire = Medium::heterogeneous(
+                    Density::noise(3, 0.01, 5),
+                    bounds.clone(),
+                    0.02,
+                    0.01,
+                    Vector3::new(0.5, 0.5, 0.5),
+                    0.3,
+                )
+                .with_emission(Emission::blackbody(
+                    Density::noise(11, 0.015, 5),
+                    bounds,
+                    600.0,
+                    2200.0,
+                    0.15,
+                ));
+                objects.push(boundary.with_medium(Arc::new(fire)));
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![rect_light],
+                    Background::new_plain(Vector3::new(0.0, 0.0, 0.0)),
+                )
+            }
+            Scenes::MeshVolume => {
+                let look_from = Vector3::new(278.0, 278.0, -800.0);
+                let look_at = Vector3::new(278.0, 278.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = 10.0;
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    40.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    3.0,
+                );
+
+                let red = Material::Lambertian {
+                    albedo: Vector3::new(0.65, 0.05, 0.05),
+                };
+                let white = Material::Lambertian {
+                    albedo: Vector3::new(0.73, 0.73, 0.73),
+                };
+                let green = Material::Lambertian {
+                    albedo: Vector3::new(0.12, 0.45, 0.15),
+                };
+                let difflight = Material::DiffuseLight {
+                    texture: Texture::SolidColor {
+                        albedo: Vector3::new(15.0, 15.0, 15.0),
+                    },
+                };
+
+                let mut objects = vec![
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 555.0, green, false),
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 0.0, red, false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 0.0, white.clone(), false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 555.0, white.clone(), false),
+                    Object::build_xy_rect(0.0, 555.0, 0.0, 555.0, 555.0, white, false),
+                ];
+                let rect_light =
+                    Object::build_xz_rect(213.0, 343.0, 227.0, 332.0, 554.0, difflight, true);
+                objects.push(rect_light.clone());
+
+                //Any closed mesh can bound a medium
+                let mut suzanne = TriangleMesh::load(
+                    "objs/suzanne.obj",
+                    150.0,
+                    Vector3::new(278.0, 250.0, 278.0),
+                    180.0,
+                    1,
+                    Material::Interface,
+                );
+                suzanne.push_medium_to_objects(
+                    &mut objects,
+                    Medium::homogeneous(0.02, Vector3::new(0.9, 0.6, 0.3)),
+                );
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![rect_light],
+                    Background::new_plain(Vector3::new(0.0, 0.0, 0.0)),
+                )
+            }
+            Scenes::Liquid => {
+                let look_from = Vector3::new(278.0, 278.0, -800.0);
+                let look_at = Vector3::new(278.0, 278.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = 10.0;
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    40.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    3.0,
+                );
+
+                let red = Material::Lambertian {
+                    albedo: Vector3::new(0.65, 0.05, 0.05),
+                };
+                let white = Material::Lambertian {
+                    albedo: Vector3::new(0.73, 0.73, 0.73),
+                };
+                let green = Material::Lambertian {
+                    albedo: Vector3::new(0.12, 0.45, 0.15),
+                };
+                let difflight = Material::DiffuseLight {
+                    texture: Texture::SolidColor {
+                        albedo: Vector3::new(15.0, 15.0, 15.0),
+                    },
+                };
+
+                let mut objects = vec![
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 555.0, green, false),
+                    Object::build_yz_rect(0.0, 555.0, 0.0, 555.0, 0.0, red, false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 0.0, white.clone(), false),
+                    Object::build_xz_rect(0.0, 555.0, 0.0, 555.0, 555.0, white.clone(), false),
+                    Object::build_xy_rect(0.0, 555.0, 0.0, 555.0, 555.0, white, false),
+                ];
+                let rect_light =
+                    Object::build_xz_rect(213.0, 343.0, 227.0, 332.0, 554.0, difflight, true);
+                objects.push(rect_light.clone());
+
+                //Glass ball filled with wine, with an air bubble in the wine and an ice cube
+                //sticking out of the ball. Each interior overrides the ones of lower priority
+                let glass = Material::Dielectric {
+                    index_of_refraction: 1.5,
+                };
+                let center = Vector3::new(278.0, 180.0, 278.0);
+                objects.push(
+                    Object::build_sphere(center, 150.0, glass.clone()).with_interior(Arc::new(
+                        Interior::new(1.5, Vector3::new(0.0, 0.0, 0.0), 1),
+                    )),
+                );
+                objects.push(
+                    Object::build_sphere(center, 140.0, glass.clone()).with_interior(Arc::new(
+                        Interior::new(1.33, Vector3::new(0.002, 0.02, 0.015), 2),
+                    )),
+                );
+                objects.push(
+                    Object::build_sphere(Vector3::new(230.0, 150.0, 250.0), 35.0, glass.clone())
+                        .with_interior(Arc::new(Interior::new(
+                            1.0,
+                            Vector3::new(0.0, 0.0, 0.0),
+                            3,
+                        ))),
+                );
+                Prism::build_prism(
+                    Vector3::new(0.0, 0.0, 0.0),
+                    Vector3::new(100.0, 100.0, 100.0),
+                    glass,
+                )
+                .rotate_y(30.0)
+                .translate(Vector3::new(290.0, 250.0, 200.0))
+                .with_interior(Arc::new(Interior::new(
+                    1.31,
+                    Vector3::new(0.001, 0.0005, 0.0),
+                    3,
+                )))
+                .push_to_objects(&mut objects);
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![rect_light],
+                    Background::new_plain(Vector3::new(0.0, 0.0, 0.0)),
+                )
+            }
             Scenes::Volumes => {
                 let look_from = Vector3::new(478.0, 278.0, -600.0);
                 let look_at = Vector3::new(278.0, 278.0, 0.0);
@@ -784,22 +1123,18 @@ impl Scenes {
                         index_of_refraction: 1.5,
                     },
                 );
-                objects.push(glossy.clone());
-                objects.push(Object::build_constant_medium(
-                    glossy,
-                    0.2,
-                    Vector3::new(0.2, 0.4, 0.9),
-                ));
+                objects.push(glossy.build_constant_medium(0.2, Vector3::new(0.2, 0.4, 0.9)));
 
+                //Fog around the whole scene, the camera starts inside it
                 let boundary =
-                    Object::build_sphere(Vector3::new(0.0, 0.0, 0.0), 5000.0, Material::default());
+                    Object::build_sphere(Vector3::new(0.0, 0.0, 0.0), 5000.0, Material::Interface);
                 objects.push(Object::build_constant_medium(
                     boundary,
                     0.0001,
                     Vector3::new(1.0, 1.0, 1.0),
                 ));
                 let emat = Material::TexturedLambertian {
-                    texture: Texture::load_texture("textures/earthmap.jpg"),
+                    texture: Texture::load_texture("textures/earthmap.jpg").unwrap(),
                 };
                 objects.push(Object::build_sphere(
                     Vector3::new(280.0, 240.0, 400.0),
@@ -1194,13 +1529,12 @@ impl Scenes {
                     dist_to_focus,
                     4.0,
                 );
-                let mut sponza = TriangleMesh::load(
+                let mut sponza = TriangleMesh::load_mtl(
                     "objs/sponza.obj",
                     0.1,
                     Vector3::new(0.0, -0.0, -0.0),
                     0.0,
                     1,
-                    Material::default(),
                 );
 
                 let mut objects = vec![];
@@ -1225,8 +1559,74 @@ impl Scenes {
                     Background::new_plain(Vector3::new(0.0, 0.0, 0.0)),
                 )
             }
+            Self::SponzaSky => {
+                let (lights, background) =
+                    load_hdri("HDRIs/street.hdr", Vector3::new(0.0, 0.0, 0.0), true);
+
+                let look_from = Vector3::new(-80.0, 10.0, 0.0);
+                let look_at = Vector3::new(100.0, 100.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = 2.0;
+                let aperture = 0.00;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    40.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    4.0,
+                );
+                let mut sponza = TriangleMesh::load_mtl(
+                    "objs/sponza.obj",
+                    0.1,
+                    Vector3::new(0.0, -0.0, -0.0),
+                    0.0,
+                    1,
+                );
+
+                let mut objects = vec![];
+                sponza.push_to_objects(&mut objects);
+
+                //The sky only reaches the atrium through its open roof
+                let roof = PortalQuad::new(
+                    Vector3::new(-135.0, 130.0, -45.0),
+                    Vector3::new(265.0, 0.0, 0.0),
+                    Vector3::new(0.0, 0.0, 95.0),
+                );
+                let mut lights: Vec<Object> = lights
+                    .into_iter()
+                    .map(|light| light.through_portals(vec![roof.clone()]))
+                    .collect();
+
+                //Lanterns under the arcades, the haze is sampled toward them
+                let lanterns = [
+                    Vector3::new(-40.0, 25.0, -38.0),
+                    Vector3::new(10.0, 25.0, 38.0),
+                    Vector3::new(60.0, 25.0, -38.0),
+                ];
+                for center in lanterns {
+                    let lantern = Object::build_sphere(
+                        center,
+                        1.0,
+                        Material::DiffuseLight {
+                            texture: Texture::blackbody(2700.0, 100.0),
+                        },
+                    );
+                    objects.push(lantern.clone());
+                    lights.push(lantern);
+                }
+
+                //Hazy air in the atrium makes the sunlight through the arches visible
+                SceneConfig::new(objects, camera, lights, background).with_atmosphere(
+                    Medium::height_fog(0.0005, 0.003, Vector3::new(1.0, 1.0, 1.0), 0.5, 30.0, 60.0),
+                )
+            }
             Self::Teapots => {
-                let (env_map, hdri) = load_hdri("HDRIs/studio.hdr", 120.0);
+                let (lights, background) =
+                    load_hdri("HDRIs/studio.hdr", Vector3::new(0.0, 120.0, 0.0), false);
 
                 let look_from = Vector3::new(-7.0, 1.4, 0.0);
                 let look_at = Vector3::new(0.0, 0.0, 0.0);
@@ -1245,7 +1645,7 @@ impl Scenes {
                     0.8,
                 );
 
-                let marble = Texture::load_texture("textures/marble4.jpg");
+                let marble = Texture::load_texture("textures/marble4.jpg").unwrap();
 
                 let material_ground = Material::TexturedAshikhminShirley {
                     texture: marble,
@@ -1254,16 +1654,17 @@ impl Scenes {
                     nu: 10.0,
                     nv: 10.0,
                 };
-                let tex = Texture::load_texture("textures/wood.jpg");
+                let tex = Texture::load_texture("textures/wood.jpg").unwrap();
                 let mut teapot1 = TriangleMesh::load(
                     "objs/teapot_highpoly.obj",
                     0.06,
                     Vector3::new(-2.1, -0.98, 0.0),
                     60.0,
                     1,
-                    Material::Metal {
-                        albedo: Vector3::new(1.000, 0.766, 0.336),
-                        fuzz: 0.,
+                    Material::Conductor {
+                        ior: ComplexIor::gold(),
+                        alpha_x: 0.08,
+                        alpha_y: 0.08,
                     },
                 );
 
@@ -1273,13 +1674,8 @@ impl Scenes {
                     Vector3::new(-2.1, -0.98, 1.5),
                     60.0,
                     1,
-                    Material::TexturedAshikhminShirley {
-                        texture: tex,
-                        r_s: Vector3::new(0.6, 0.6, 0.6),
-                        k_specular: 0.1,
-                        nu: 1000.0,
-                        nv: 1000.0,
-                    },
+                    Material::TexturedLambertian { texture: tex }
+                        .with_clearcoat(Clearcoat::new(1.5).with_tint(Vector3::new(0.9, 0.7, 0.5))),
                 );
 
                 let mut teapot3 = TriangleMesh::load(
@@ -1301,13 +1697,15 @@ impl Scenes {
                     Vector3::new(1.0, -0.98, -0.2),
                     60.0,
                     1,
+                    //Brushed along the body, so the highlights stay put across the triangles
                     Material::AshikhminShirley {
                         r_d: Vector3::new(0.0, 0.0, 0.0),
                         r_s: Vector3::new(0.983, 0.991, 0.995),
                         k_specular: 1.0,
                         nu: 1000.0,
                         nv: 10.0,
-                    },
+                    }
+                    .with_orientation(Orientation::along(Vector3::new(0.0, 1.0, 0.0))),
                 );
 
                 let mut teapot5 = TriangleMesh::load(
@@ -1320,14 +1718,13 @@ impl Scenes {
                         color: Vector3::new(0.12, 0.45, 0.15),
                         k_specular: 0.08,
                         exponent: 50.0,
-                    }
-                    /*Material::AshikhminShirley {
-                        r_d: Vector3::new(0.12, 0.45, 0.15),
-                        r_s: Vector3::new(1.0, 1.0, 1.0),
-                        k_specular: 0.08,
-                        nu: 50.0,
-                        nv: 50.0,
-                    },*/
+                    }, /*Material::AshikhminShirley {
+                           r_d: Vector3::new(0.12, 0.45, 0.15),
+                           r_s: Vector3::new(1.0, 1.0, 1.0),
+                           k_specular: 0.08,
+                           nu: 50.0,
+                           nv: 50.0,
+                       },*/
                 );
 
                 let mut teapot6 = TriangleMesh::load(
@@ -1380,31 +1777,1034 @@ impl Scenes {
                 teapot6.push_to_objects(&mut objects);
                 dragon.push_to_objects(&mut objects);
 
-                SceneConfig::new(objects, camera, vec![env_map], Background::new_hdri(hdri))
+                SceneConfig::new(objects, camera, lights, background)
             }
-        }
-    }
-}
+            Self::Diamond => {
+                let look_from = Vector3::new(0.0, 5.0, -7.0);
+                let look_at = Vector3::new(0.0, -0.6, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
 
-pub struct SceneConfig {
-    pub objects: Vec<Object>,
-    pub camera: Camera,
-    pub light: Vec<Object>,
-    pub background: Background,
-}
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    30.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
 
-impl SceneConfig {
-    pub fn new(
-        objects: Vec<Object>,
-        camera: Camera,
-        light: Vec<Object>,
-        background: Background,
-    ) -> Self {
-        Self {
-            objects,
-            camera,
-            light,
-            background,
-        }
+                //Brilliant cut with its table tilted toward the camera, lit by small lamps so
+                //the spectral colors split by the dispersion stay apart (run with --spectral)
+                let mut diamond = TriangleMesh::load(
+                    "objs/diamond.obj",
+                    1.0,
+                    Vector3::new(0.0, 0.0, 0.0),
+                    -20.0,
+                    0,
+                    Material::DispersiveDielectric {
+                        dispersion: Dispersion::diamond(),
+                    },
+                )
+                .flat_shaded();
+
+                let floor = Material::Lambertian {
+                    albedo: Vector3::new(0.05, 0.05, 0.05),
+                };
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0, 20.0, -20.0, 20.0, -2.4, floor, false,
+                )];
+                diamond.push_to_objects(&mut objects);
+
+                let lamps = [
+                    (Vector3::new(-3.0, 6.0, -4.0), 6500.0),
+                    (Vector3::new(4.0, 5.0, -2.0), 5000.0),
+                    (Vector3::new(0.5, 7.0, 3.0), 3500.0),
+                ];
+                let lights: Vec<Object> = lamps
+                    .iter()
+                    .map(|(center, kelvin)| {
+                        Object::build_sphere(
+                            *center,
+                            0.15,
+                            Material::DiffuseLight {
+                                texture: Texture::blackbody(*kelvin, 400.0),
+                            },
+                        )
+                    })
+                    .collect();
+                objects.extend(lights.iter().cloned());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    lights,
+                    Background::new_plain(Vector3::new(0.05, 0.05, 0.05)),
+                )
+            }
+            Self::Principled => {
+                let look_from = Vector3::new(0.0, 2.5, -11.0);
+                let look_at = Vector3::new(0.0, 0.8, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //One principled sphere per feature: plastic, rough gold, brushed aluminium,
+                //velvet, clearcoated paint and frosted glass
+                let red = Vector3::new(0.6, 0.05, 0.04);
+                let materials = [
+                    Principled::new(red).with_roughness(0.3),
+                    Principled::new(Vector3::new(1.0, 0.77, 0.34))
+                        .with_metallic(1.0)
+                        .with_roughness(0.4),
+                    Principled::new(Vector3::new(0.91, 0.92, 0.92))
+                        .with_metallic(1.0)
+                        .with_roughness(0.35)
+                        .with_anisotropic(0.9),
+                    Principled::new(Vector3::new(0.15, 0.05, 0.3))
+                        .with_roughness(1.0)
+                        .with_sheen(1.0),
+                    Principled::new(Vector3::new(0.02, 0.1, 0.4))
+                        .with_roughness(0.6)
+                        .with_clearcoat(1.0),
+                    Principled::new(Vector3::new(0.9, 1.0, 0.95))
+                        .with_roughness(0.15)
+                        .with_transmission(1.0),
+                ];
+
+                let floor = Principled::new(Texture::Checker {
+                    color1: Vector3::new(0.2, 0.2, 0.2),
+                    color2: Vector3::new(0.6, 0.6, 0.6),
+                })
+                .with_roughness(0.6);
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0,
+                    20.0,
+                    -20.0,
+                    20.0,
+                    0.0,
+                    Material::Principled {
+                        bsdf: Arc::new(floor),
+                    },
+                    false,
+                )];
+                objects.extend(materials.into_iter().enumerate().map(|(i, bsdf)| {
+                    Object::build_sphere(
+                        Vector3::new(i as f32 * 2.2 - 5.5, 0.9, 0.0),
+                        0.9,
+                        Material::Principled {
+                            bsdf: Arc::new(bsdf),
+                        },
+                    )
+                }));
+
+                let light = Object::build_xz_rect(
+                    -4.0,
+                    4.0,
+                    -6.0,
+                    -2.0,
+                    8.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(6.0, 6.0, 6.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.3, 0.35, 0.4)),
+                )
+            }
+            Self::Frosted => {
+                let look_from = Vector3::new(0.0, 2.5, -10.0);
+                let look_at = Vector3::new(0.0, 1.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //Checkered backdrop, blurred by a frosted glass sphere, an acrylic slab etched in
+                //a checker pattern and a block of rough ice
+                let floor = Material::Lambertian {
+                    albedo: Vector3::new(0.6, 0.6, 0.6),
+                };
+                let backdrop = Material::TexturedLambertian {
+                    texture: Texture::Checker {
+                        color1: Vector3::new(0.1, 0.1, 0.1),
+                        color2: Vector3::new(0.8, 0.8, 0.8),
+                    },
+                };
+                let mut objects = vec![
+                    Object::build_xz_rect(-20.0, 20.0, -20.0, 20.0, 0.0, floor, false),
+                    Object::build_xy_rect(-20.0, 20.0, 0.0, 20.0, 4.0, backdrop, true),
+                ];
+
+                objects.push(
+                    Object::build_sphere(
+                        Vector3::new(-2.6, 1.0, 0.0),
+                        1.0,
+                        Material::RoughDielectric {
+                            index_of_refraction: 1.5,
+                            roughness: 0.3.into(),
+                        },
+                    )
+                    .with_interior(Arc::new(Interior::new(
+                        1.5,
+                        Vector3::new(0.0, 0.0, 0.0),
+                        1,
+                    ))),
+                );
+
+                let etched = Material::RoughDielectric {
+                    index_of_refraction: 1.49,
+                    roughness: Texture::Checker {
+                        color1: Vector3::new(0.05, 0.05, 0.05),
+                        color2: Vector3::new(0.5, 0.5, 0.5),
+                    },
+                };
+                Prism::build_prism(
+                    Vector3::new(-0.8, 0.0, -0.15),
+                    Vector3::new(0.8, 2.2, 0.15),
+                    etched,
+                )
+                .with_interior(Arc::new(Interior::new(
+                    1.49,
+                    Vector3::new(0.0, 0.0, 0.0),
+                    1,
+                )))
+                .push_to_objects(&mut objects);
+
+                let ice = Material::RoughDielectric {
+                    index_of_refraction: 1.31,
+                    roughness: 0.5.into(),
+                };
+                Prism::build_prism(
+                    Vector3::new(-0.8, 0.0, -0.8),
+                    Vector3::new(0.8, 1.6, 0.8),
+                    ice,
+                )
+                .rotate_y(30.0)
+                .translate(Vector3::new(2.6, 0.0, 0.0))
+                .with_interior(Arc::new(Interior::new(
+                    1.31,
+                    Vector3::new(0.15, 0.05, 0.02),
+                    1,
+                )))
+                .push_to_objects(&mut objects);
+
+                let light = Object::build_xz_rect(
+                    -3.0,
+                    3.0,
+                    -4.0,
+                    -1.0,
+                    7.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(8.0, 8.0, 8.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.2, 0.22, 0.25)),
+                )
+            }
+            Self::Iridescence => {
+                let look_from = Vector3::new(0.0, 2.0, -9.0);
+                let look_at = Vector3::new(0.0, 1.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //A soap bubble whose thickness swirls like marble, an oil slick on a puddle, a
+                //window pane and a sphere of anodized aluminum, on dark asphalt
+                let asphalt = Material::Lambertian {
+                    albedo: Vector3::new(0.04, 0.04, 0.04),
+                };
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0, 20.0, -20.0, 20.0, 0.0, asphalt, false,
+                )];
+
+                let oil = ThinFilm::new(0.45, 1.47);
+                objects.push(Object::build_xz_rect(
+                    -2.0,
+                    2.0,
+                    -4.0,
+                    -1.5,
+                    0.01,
+                    Material::Dielectric {
+                        index_of_refraction: 1.33,
+                    }
+                    .with_film(oil),
+                    false,
+                ));
+
+                let soap =
+                    ThinFilm::new(Texture::load_texture("textures/marble.jpg").unwrap(), 1.33);
+                objects.push(Object::build_sphere(
+                    Vector3::new(-1.6, 1.3, 0.0),
+                    1.1,
+                    Material::ThinDielectric {
+                        index_of_refraction: 1.0,
+                    }
+                    .with_film(soap),
+                ));
+
+                let oxide = ThinFilm::new(0.3, 2.2);
+                objects.push(Object::build_sphere(
+                    Vector3::new(1.4, 0.9, 0.0),
+                    0.9,
+                    Material::Conductor {
+                        ior: ComplexIor::aluminum(),
+                        alpha_x: 0.05,
+                        alpha_y: 0.05,
+                    }
+                    .with_film(oxide),
+                ));
+
+                objects.push(Object::build_xy_rect(
+                    -3.5,
+                    3.5,
+                    0.0,
+                    3.0,
+                    2.5,
+                    Material::ThinDielectric {
+                        index_of_refraction: 1.5,
+                    },
+                    false,
+                ));
+
+                let light = Object::build_xz_rect(
+                    -2.0,
+                    2.0,
+                    -7.0,
+                    -5.0,
+                    6.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(10.0, 10.0, 10.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.35, 0.4, 0.5)),
+                )
+            }
+            Self::Clearcoat => {
+                let look_from = Vector3::new(0.0, 2.5, -9.0);
+                let look_at = Vector3::new(0.0, 0.9, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //Car paint with metallic flakes, lacquered wood and coated blue plastic on a
+                //varnished wooden floor
+                let wood = Texture::load_texture("textures/wood.jpg").unwrap();
+                let floor = Material::TexturedLambertian {
+                    texture: wood.clone(),
+                }
+                .with_clearcoat(
+                    Clearcoat::new(1.5)
+                        .with_roughness(0.25)
+                        .with_tint(Vector3::new(0.95, 0.85, 0.7)),
+                );
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0, 20.0, -20.0, 20.0, 0.0, floor, false,
+                )];
+
+                let car_paint = Material::Metal {
+                    albedo: Vector3::new(0.6, 0.04, 0.05),
+                    fuzz: 0.35,
+                }
+                .with_clearcoat(Clearcoat::new(1.5));
+                let lacquer = Material::TexturedLambertian { texture: wood }
+                    .with_clearcoat(Clearcoat::new(1.55).with_tint(Vector3::new(0.9, 0.6, 0.3)));
+                let plastic = Material::AshikhminShirley {
+                    r_s: Vector3::new(0.05, 0.05, 0.05),
+                    r_d: Vector3::new(0.05, 0.15, 0.6),
+                    k_specular: 0.1,
+                    nu: 20.0,
+                    nv: 20.0,
+                }
+                .with_clearcoat(Clearcoat::new(1.5).with_roughness(0.1));
+                for (i, material) in [car_paint, lacquer, plastic].into_iter().enumerate() {
+                    objects.push(Object::build_sphere(
+                        Vector3::new(2.4 - i as f32 * 2.4, 1.0, 0.0),
+                        1.0,
+                        material,
+                    ));
+                }
+
+                let light = Object::build_xz_rect(
+                    -3.0,
+                    3.0,
+                    -5.0,
+                    -2.0,
+                    7.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(8.0, 8.0, 8.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.3, 0.35, 0.4)),
+                )
+            }
+            Self::Subsurface => {
+                let look_from = Vector3::new(0.0, 1.6, -6.0);
+                let look_at = Vector3::new(0.0, 0.9, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //A wax skull and a bunny with skin on a veined marble slab, lit from above and
+                //behind so the light shines through their thin parts. A scene unit is 10 cm
+                let millimeter = 0.01;
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0,
+                    20.0,
+                    -20.0,
+                    20.0,
+                    0.0,
+                    Material::Lambertian {
+                        albedo: Vector3::new(0.2, 0.2, 0.2),
+                    },
+                    false,
+                )];
+                //Rough surfaces let light be sampled where walks come out
+                let surface =
+                    |index_of_refraction: f32, roughness: f32| Material::RoughDielectric {
+                        index_of_refraction,
+                        roughness: roughness.into(),
+                    };
+
+                let marble = Medium::subsurface(
+                    Texture::load_texture("textures/marble2.jpg").unwrap(),
+                    Vector3::new(0.456, 0.381, 0.333),
+                    millimeter,
+                    0.0,
+                );
+                Prism::build_prism(
+                    Vector3::new(-2.6, 0.0, -1.2),
+                    Vector3::new(2.6, 0.3, 1.2),
+                    surface(1.5, 0.2),
+                )
+                .with_medium(Arc::new(marble))
+                .push_to_objects(&mut objects);
+
+                let wax = Medium::subsurface(
+                    Vector3::new(0.95, 0.8, 0.5),
+                    Vector3::new(4.0, 2.5, 1.2),
+                    millimeter,
+                    0.0,
+                );
+                TriangleMesh::load(
+                    "objs/Upper_skull.obj",
+                    0.6,
+                    Vector3::new(1.1, 0.87, 0.0),
+                    200.0,
+                    1,
+                    surface(1.45, 0.3),
+                )
+                .push_medium_to_objects(&mut objects, wax);
+
+                TriangleMesh::load(
+                    "objs/bunny.obj",
+                    10.0,
+                    Vector3::new(-1.2, -0.03, 0.0),
+                    150.0,
+                    1,
+                    surface(1.4, 0.5),
+                )
+                .push_medium_to_objects(&mut objects, Medium::skin(millimeter));
+
+                let light = Object::build_xz_rect(
+                    -2.0,
+                    2.0,
+                    1.0,
+                    3.0,
+                    4.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(8.0, 8.0, 8.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.2, 0.22, 0.25)),
+                )
+            }
+            Self::Bump => {
+                let look_from = Vector3::new(0.0, 2.6, -9.5);
+                let look_at = Vector3::new(0.0, 0.8, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //A skull carved with marble veins, hammered gold and a dimpled ball on a floor
+                //with raised wood grain, lit from above at an angle that brings out the relief
+                let wood = Texture::load_texture("textures/wood.jpg").unwrap();
+                let floor = Material::Principled {
+                    bsdf: Arc::new(Principled::new(wood).with_roughness(0.3)),
+                }
+                .with_normal_map(NormalMap::bump(
+                    Texture::load_data("textures/wood.jpg").unwrap(),
+                    0.002,
+                ));
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0, 20.0, -20.0, 20.0, 0.0, floor, false,
+                )];
+
+                let bone = Material::Principled {
+                    bsdf: Arc::new(
+                        Principled::new(Vector3::new(0.8, 0.75, 0.65)).with_roughness(0.4),
+                    ),
+                }
+                .with_normal_map(NormalMap::bump(
+                    Texture::load_data("textures/marble.jpg").unwrap(),
+                    0.004,
+                ));
+                TriangleMesh::load(
+                    "objs/Upper_skull.obj",
+                    0.6,
+                    Vector3::new(-2.3, 0.57, 0.3),
+                    200.0,
+                    1,
+                    bone,
+                )
+                .push_to_objects(&mut objects);
+
+                let hammered = Material::Conductor {
+                    ior: ComplexIor::gold(),
+                    alpha_x: 0.1,
+                    alpha_y: 0.1,
+                }
+                .with_normal_map(NormalMap::bump(
+                    Texture::load_data("textures/granite.jpg").unwrap(),
+                    0.002,
+                ));
+                objects.push(Object::build_sphere(
+                    Vector3::new(0.0, 1.0, 0.0),
+                    1.0,
+                    hammered,
+                ));
+
+                //Tangent space normals of round dimples in a grid
+                let (size, cells) = (256, 16);
+                let mut image_v = Vec::with_capacity(size * size * 3);
+                for j in 0..size {
+                    for i in 0..size {
+                        let u = (i as f32 + 0.5) / size as f32 * cells as f32;
+                        let v = (1.0 - (j as f32 + 0.5) / size as f32) * cells as f32;
+                        let (x, y) = (u.fract() - 0.5, v.fract() - 0.5);
+                        let (x, y) = if x * x + y * y < 0.16 {
+                            (x, y)
+                        } else {
+                            (0.0, 0.0)
+                        };
+                        let normal = Vector3::new(-x, -y, 0.4).norm();
+                        image_v.extend([normal.x, normal.y, normal.z].map(|n| n * 0.5 + 0.5));
+                    }
+                }
+                let dimples = Texture::Data {
+                    image_v: Arc::new(image_v),
+                    width: size as f32,
+                    height: size as f32,
+                };
+                let plastic = Material::Principled {
+                    bsdf: Arc::new(
+                        Principled::new(Vector3::new(0.9, 0.9, 0.9))
+                            .with_roughness(0.2)
+                            .with_clearcoat(1.0),
+                    ),
+                }
+                .with_normal_map(NormalMap::tangent(dimples));
+                objects.push(Object::build_sphere(
+                    Vector3::new(2.3, 0.8, 0.0),
+                    0.8,
+                    plastic,
+                ));
+
+                let light = Object::build_xz_rect(
+                    -4.0,
+                    -1.0,
+                    -4.0,
+                    -1.0,
+                    5.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(12.0, 12.0, 12.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.2, 0.22, 0.25)),
+                )
+            }
+            Self::Cutout => {
+                let look_from = Vector3::new(0.0, 2.5, -9.5);
+                let look_at = Vector3::new(0.0, 1.1, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //A lace curtain cut out by a mask, whose holes let the light behind it through
+                //onto the floor, a hollow ball cut out by marble veins and a half transparent one
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0,
+                    20.0,
+                    -20.0,
+                    20.0,
+                    0.0,
+                    Material::Lambertian {
+                        albedo: Vector3::new(0.6, 0.6, 0.6),
+                    },
+                    false,
+                )];
+
+                //Round holes in a grid
+                let (size, cells) = (256, 12);
+                let mut image_v = Vec::with_capacity(size * size * 3);
+                for j in 0..size {
+                    for i in 0..size {
+                        let u = (i as f32 + 0.5) / size as f32 * cells as f32;
+                        let v = (j as f32 + 0.5) / size as f32 * cells as f32;
+                        let (x, y) = (u.fract() - 0.5, v.fract() - 0.5);
+                        let opacity = if x * x + y * y < 0.12 { 0.0 } else { 1.0 };
+                        image_v.extend([opacity; 3]);
+                    }
+                }
+                let lace = Texture::Data {
+                    image_v: Arc::new(image_v),
+                    width: size as f32,
+                    height: size as f32,
+                };
+                let curtain = Material::Lambertian {
+                    albedo: Vector3::new(0.9, 0.85, 0.75),
+                }
+                .with_cutout(lace, 0.5);
+                objects.push(Object::build_xy_rect(
+                    -1.5, 1.5, 0.0, 3.0, 2.0, curtain, true,
+                ));
+
+                let hollow = Material::Lambertian {
+                    albedo: Vector3::new(0.2, 0.4, 0.8),
+                }
+                .with_cutout(Texture::load_data("textures/marble.jpg").unwrap(), 0.6);
+                objects.push(Object::build_sphere(
+                    Vector3::new(-2.3, 1.0, -0.5),
+                    1.0,
+                    hollow,
+                ));
+
+                let ghost = Material::Lambertian {
+                    albedo: Vector3::new(0.8, 0.2, 0.2),
+                }
+                .with_opacity(0.5);
+                objects.push(Object::build_sphere(
+                    Vector3::new(2.3, 1.0, -0.5),
+                    1.0,
+                    ghost,
+                ));
+
+                let light = Object::build_xz_rect(
+                    -1.0,
+                    1.0,
+                    4.0,
+                    6.0,
+                    8.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(40.0, 40.0, 40.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.15, 0.17, 0.2)),
+                )
+            }
+            Self::Measured => {
+                let look_from = Vector3::new(0.0, 2.5, -13.0);
+                let look_at = Vector3::new(0.0, 1.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //Spheres of MERL measurements, which are not bundled. The tables go into BRDFs/
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0,
+                    20.0,
+                    -20.0,
+                    20.0,
+                    0.0,
+                    Material::Lambertian {
+                        albedo: Vector3::new(0.5, 0.5, 0.5),
+                    },
+                    false,
+                )];
+                let brdfs = [
+                    "BRDFs/gold-metallic-paint.binary",
+                    "BRDFs/chrome-steel.binary",
+                    "BRDFs/blue-acrylic.binary",
+                    "BRDFs/red-fabric.binary",
+                ];
+                for (i, path) in brdfs.iter().enumerate() {
+                    let material = Measured::load(path)
+                        .map(|brdf| Material::Measured {
+                            brdf: Arc::new(brdf),
+                        })
+                        .unwrap_or_else(|e| {
+                            println!("{}, using a gray diffuse material instead", e);
+                            Material::Lambertian {
+                                albedo: Vector3::new(0.5, 0.5, 0.5),
+                            }
+                        });
+                    objects.push(Object::build_sphere(
+                        Vector3::new(3.3 - 2.2 * i as f32, 1.0, 0.0),
+                        1.0,
+                        material,
+                    ));
+                }
+
+                let light = Object::build_xz_rect(
+                    -3.0,
+                    3.0,
+                    -4.0,
+                    -2.0,
+                    7.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(15.0, 15.0, 15.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.15, 0.17, 0.2)),
+                )
+            }
+
+            Self::Helmet => {
+                let look_from = Vector3::new(1.5, 0.8, 3.5);
+                let look_at = Vector3::new(0.0, 0.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //Khronos' DamagedHelmet sample, which is not bundled, with its principled
+                //materials. A gray sphere stands in for it when it is missing
+                let mut objects = vec![];
+                match TriangleMesh::load_gltf(
+                    "models/DamagedHelmet.glb",
+                    1.0,
+                    Vector3::new(0.0, 0.0, 0.0),
+                    0.0,
+                    1,
+                ) {
+                    Ok(mut helmet) => helmet.push_to_objects(&mut objects),
+                    Err(e) => {
+                        println!("{}, using a gray sphere instead", e);
+                        objects.push(Object::build_sphere(
+                            Vector3::new(0.0, 0.0, 0.0),
+                            1.0,
+                            Material::Lambertian {
+                                albedo: Vector3::new(0.5, 0.5, 0.5),
+                            },
+                        ));
+                    }
+                }
+
+                let light = Object::build_xz_rect(
+                    -2.0,
+                    2.0,
+                    0.0,
+                    3.0,
+                    4.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(6.0, 6.0, 6.0),
+                        },
+                    },
+                    true,
+                );
+                objects.push(light.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![light],
+                    Background::new_plain(Vector3::new(0.3, 0.32, 0.35)),
+                )
+            }
+
+            Self::LightLinking => {
+                let look_from = Vector3::new(0.0, 2.5, -13.0);
+                let look_at = Vector3::new(0.0, 1.0, 0.0);
+                let vup = Vector3::new(0.0, 1.0, 0.0);
+                let dist_to_focus = (look_at - look_from).magnitude();
+                let aperture = 0.0;
+
+                let camera = Camera::new(
+                    look_from,
+                    look_at,
+                    vup,
+                    35.0,
+                    width / height,
+                    aperture,
+                    dist_to_focus,
+                    1.0,
+                );
+
+                //Light groups
+                const KEY: u32 = 1;
+                const FILL: u32 = 2;
+
+                let mut objects = vec![Object::build_xz_rect(
+                    -20.0,
+                    20.0,
+                    -20.0,
+                    20.0,
+                    0.0,
+                    Material::Lambertian {
+                        albedo: Vector3::new(0.5, 0.5, 0.5),
+                    },
+                    false,
+                )];
+                //Both spheres are lit by the key light, only the right one by the fill card
+                objects.push(
+                    Object::build_sphere(
+                        Vector3::new(-1.8, 1.0, 0.0),
+                        1.0,
+                        Material::Lambertian {
+                            albedo: Vector3::new(0.8, 0.3, 0.2),
+                        },
+                    )
+                    .with_visibility(RayVisibility::default().with_lit_by(KEY)),
+                );
+                objects.push(Object::build_sphere(
+                    Vector3::new(1.8, 1.0, 0.0),
+                    1.0,
+                    Material::Lambertian {
+                        albedo: Vector3::new(0.8, 0.3, 0.2),
+                    },
+                ));
+
+                let key = Object::build_xz_rect(
+                    -3.0,
+                    3.0,
+                    1.0,
+                    3.0,
+                    7.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(10.0, 10.0, 10.0),
+                        },
+                    },
+                    true,
+                )
+                .with_visibility(RayVisibility::default().with_light_group(KEY));
+                //Fill card above the camera, lighting the spheres from the front without showing up
+                let fill = Object::build_xz_rect(
+                    -3.0,
+                    3.0,
+                    -6.0,
+                    -4.0,
+                    4.0,
+                    Material::DiffuseLight {
+                        texture: Texture::SolidColor {
+                            albedo: Vector3::new(3.0, 4.0, 6.0),
+                        },
+                    },
+                    true,
+                )
+                .with_visibility(RayVisibility::hidden_from_camera().with_light_group(FILL));
+                objects.push(key.clone());
+                objects.push(fill.clone());
+
+                SceneConfig::new(
+                    objects,
+                    camera,
+                    vec![key, fill],
+                    Background::new_plain(Vector3::new(0.02, 0.02, 0.02)),
+                )
+            }
+        }
+    }
+}
+
+pub struct SceneConfig {
+    pub objects: Vec<Object>,
+    pub camera: Camera,
+    pub light: Vec<Object>,
+    pub background: Environment,
+    pub atmosphere: Option<Medium>,
+}
+
+impl SceneConfig {
+    pub fn new(
+        objects: Vec<Object>,
+        camera: Camera,
+        light: Vec<Object>,
+        background: impl Into<Environment>,
+    ) -> Self {
+        Self {
+            objects,
+            camera,
+            light,
+            background: background.into(),
+            atmosphere: None,
+        }
+    }
+    //Fills the space around the objects with a medium
+    pub fn with_atmosphere(mut self, atmosphere: Medium) -> Self {
+        self.atmosphere = Some(atmosphere);
+        self
     }
 }
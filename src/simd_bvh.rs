@@ -2,6 +2,7 @@ use std::ops::Neg;
 
 use crate::aabb::surrounding_box;
 use crate::aabb::AABB;
+use crate::medium::Medium;
 use crate::object::*;
 use crate::ray::*;
 
@@ -16,6 +17,10 @@ thread_local! {
 pub struct SceneBVH {
     objects: Vec<Object>,
     trees: Vec<Bvh>,
+    //Whether any object bounds a participating medium or a dielectric interior
+    pub has_media: bool,
+    //Medium filling the space outside of all bounded ones
+    pub atmosphere: Option<Medium>,
 }
 
 const TY_SHIFT: U32 = 31;
@@ -29,9 +34,12 @@ fn mk_object_id(index: usize) -> U32 {
 
 impl SceneBVH {
     pub fn from(objects: Vec<Object>) -> SceneBVH {
+        let has_media = objects.iter().any(Object::has_medium);
         let mut scene = SceneBVH {
             objects,
             trees: vec![],
+            has_media,
+            atmosphere: None,
         };
 
         let mut indices: Vec<usize> = (0..scene.objects.len()).collect();
@@ -41,6 +49,11 @@ impl SceneBVH {
         return scene;
     }
 
+    pub fn with_atmosphere(mut self, atmosphere: Option<Medium>) -> Self {
+        self.atmosphere = atmosphere;
+        self
+    }
+
     pub fn from_objects(
         objects: &mut [Object],
         trees: &mut Vec<Tree>,
@@ -164,12 +177,20 @@ impl SceneBVH {
         }
     }
 
-    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        QUEUE.with(|queue| self._hit(&mut queue.borrow_mut(), r, t_min, t_max))
+    //Objects hidden from ray_type are skipped
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32, ray_type: RayType) -> Option<HitRecord> {
+        QUEUE.with(|queue| self._hit(&mut queue.borrow_mut(), r, t_min, t_max, ray_type))
     }
 
     #[inline(never)]
-    fn _hit(&self, queue: &mut Vec<U32>, r: &Ray, t_min: f32, mut t_max: f32) -> Option<HitRecord> {
+    fn _hit(
+        &self,
+        queue: &mut Vec<U32>,
+        r: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        ray_type: RayType,
+    ) -> Option<HitRecord> {
         queue[0] = 0;
         let mut queue_index = 0;
 
@@ -186,7 +207,8 @@ impl SceneBVH {
                 TY_OBJECT => {
                     let object = &self.objects[index];
 
-                    if let Some(hr) = object.hit(r, t_min, t_max) {
+                    if !object.visible_to(ray_type) {
+                    } else if let Some(hr) = opaque_hit(object, r, t_min, t_max) {
                         t_max = crate::utilities::math::fmin(t_max, hr.t);
                         result = Some(hr);
                     }
@@ -230,6 +252,24 @@ impl SceneBVH {
     }
 }
 
+//Closest hit on the object that its opacity mask does not cut out. Hits behind a hole are
+//searched for past it, like the far side of a sphere
+#[inline(always)]
+fn opaque_hit<'a>(
+    object: &'a Object,
+    r: &Ray,
+    mut t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'a>> {
+    loop {
+        let hit = object.hit(r, t_min, t_max)?;
+        if hit.material.opaque(r, &hit) {
+            return Some(hit);
+        }
+        t_min = hit.t + 1e-4;
+    }
+}
+
 /*#[inline(always)]
  fn shuffle(ids: Simd<u32,4>, hit_number: i32)->U32x4{
 
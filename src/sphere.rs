@@ -1,6 +1,6 @@
 use std::f32::consts::PI;
 
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
 
 use crate::{
     aabb::AABB,
@@ -39,6 +39,10 @@ impl Sphere {
 
     pub fn pdf_value(&self, origin: Vector3<f32>, v: Vector3<f32>) -> f32 {
         if let Some(_hit) = self.hit(&Ray::new(origin, v), 0.001, f32::MAX) {
+            //Points inside, like scattering events in a medium the light is in, see all of it
+            if (self.center - origin).magnitude2() <= self.radius * self.radius {
+                return 1.0 / (4.0 * PI);
+            }
             let cos_theta_max =
                 (1.0 - self.radius * self.radius / (self.center - origin).magnitude2()).sqrt();
             let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
@@ -47,8 +51,8 @@ impl Sphere {
         0.0
     }
 
-    pub fn random(&self, origin: Vector3<f32>, rng: &mut ThreadRng) -> Vector3<f32> {
-        pub fn random_to_sphere(radius: f32, distance_2: f32, rng: &mut ThreadRng) -> Vector3<f32> {
+    pub fn random(&self, origin: Vector3<f32>, rng: &mut impl Rng) -> Vector3<f32> {
+        pub fn random_to_sphere(radius: f32, distance_2: f32, rng: &mut impl Rng) -> Vector3<f32> {
             let r1 = rng.gen::<f32>();
             let r2 = rng.gen::<f32>();
             let z = 1.0 + r2 * ((1.0 - radius * radius / distance_2).sqrt() - 1.0);
@@ -59,9 +63,21 @@ impl Sphere {
         }
         let direction = self.center - origin;
         let distance_2 = direction.magnitude2();
+        if distance_2 <= self.radius * self.radius {
+            return Vector3::random_unit_vector(rng);
+        }
         let uvw = ONB::build_from(direction);
         uvw.local(random_to_sphere(self.radius, distance_2, rng))
     }
+    pub fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    //Uniform point on the sphere and its outward normal
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vector3<f32>, Vector3<f32>) {
+        let normal = Vector3::random_unit_vector(rng);
+        (self.center + normal * self.radius, normal)
+    }
 }
 impl Hittable for Sphere {
     #[inline(always)]
@@ -90,15 +106,11 @@ impl Hittable for Sphere {
             } else {
                 (0.0, 0.0)
             };
-            Some(HitRecord::new(
-                r.at(root),
-                outward_normal,
-                root,
-                u,
-                v,
-                r,
-                &self.material,
-            ))
+            Some(
+                HitRecord::new(r.at(root), outward_normal, root, u, v, r, &self.material)
+                    //u runs around the y axis and v up from the south pole
+                    .with_tangent(Vector3::new(outward_normal.z, 0.0, -outward_normal.x), 1.0),
+            )
         }
     }
 
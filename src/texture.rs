@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use crate::utilities::vector3::Vector3;
-use image::Rgb;
+use crate::utilities::{color::blackbody_with_scale, vector3::Vector3};
+use image::{DynamicImage, Rgb};
 use num::clamp;
 
 #[allow(dead_code)]
@@ -24,26 +24,101 @@ pub enum Texture {
         width: f32,
         height: f32,
     },
+    //Image of values rather than colors, like normals or heights. It is read without the gamma
+    //curve and filtered bilinearly, so that slopes between texels are smooth
+    Data {
+        image_v: Arc<Vec<f32>>,
+        width: f32,
+        height: f32,
+    },
+    //Glow of a blackbody, spectral paths evaluate Planck's law exactly and scale it by scale
+    Blackbody {
+        kelvin: f32,
+        color: Vector3<f32>,
+        scale: f32,
+    },
+}
+
+impl From<Vector3<f32>> for Texture {
+    fn from(albedo: Vector3<f32>) -> Self {
+        Self::SolidColor { albedo }
+    }
+}
+
+impl From<f32> for Texture {
+    fn from(value: f32) -> Self {
+        Self::SolidColor {
+            albedo: Vector3::new(value, value, value),
+        }
+    }
 }
 
 impl Texture {
-    pub fn load_texture(path: &str) -> Self {
-        let image = image::open(path)
-            .map_err(|e| format!("Failed to read image from {:?}: {}", path, e))
-            .unwrap();
-        let width = image.width() as f32;
-        let height = image.height() as f32;
+    pub fn load_texture(path: &str) -> Result<Self, String> {
+        read_image(path).map(Self::image)
+    }
+
+    //Normal or height map, without the gamma curve of color images
+    pub fn load_data(path: &str) -> Result<Self, String> {
+        read_image(path).map(Self::data)
+    }
 
+    pub fn image(image: DynamicImage) -> Self {
         Self::Image {
+            width: image.width() as f32,
+            height: image.height() as f32,
+            image_v: Arc::new(image.into_rgb32f().into_vec()),
+        }
+    }
+
+    pub fn data(image: DynamicImage) -> Self {
+        Self::Data {
+            width: image.width() as f32,
+            height: image.height() as f32,
             image_v: Arc::new(image.into_rgb32f().into_vec()),
-            width,
-            height,
+        }
+    }
+
+    //Step in texture coordinates between neighboring texels
+    pub fn texel_size(&self) -> (f32, f32) {
+        match self {
+            Self::Image {
+                image_v: _,
+                width,
+                height,
+            }
+            | Self::Data {
+                image_v: _,
+                width,
+                height,
+            }
+            | Self::Hdri {
+                image_v: _,
+                width,
+                height,
+            } => (1.0 / width, 1.0 / height),
+            _ => (1e-3, 1e-3),
+        }
+    }
+
+    //Blackbody glow with the given luminance
+    pub fn blackbody(kelvin: f32, intensity: f32) -> Self {
+        let (color, scale) = blackbody_with_scale(kelvin);
+        Self::Blackbody {
+            kelvin,
+            color: color * intensity,
+            scale: scale * intensity,
         }
     }
 
     pub fn value(&self, u: f32, v: f32, p: Vector3<f32>) -> Vector3<f32> {
         match self {
             Self::SolidColor { albedo } => *albedo,
+            Self::Blackbody {
+                kelvin: _,
+                color,
+                scale: _,
+            } => *color,
             Self::Checker { color1, color2 } => {
                 let sines = (10.0 * p.x).sin() * (10.0 * p.y).sin() * (10.0 * p.z).sin();
                 if sines < 0.0 {
@@ -77,6 +152,31 @@ impl Texture {
                 let pixel = &image_v[(i + j * w as usize) * 3..(i + j * w as usize) * 3 + 3];
                 Vector3::new(pixel[0].powf(2.2), pixel[1].powf(2.2), pixel[2].powf(2.2))
             }
+            Self::Data {
+                image_v,
+                width,
+                height,
+            } => {
+                if image_v.is_empty() {
+                    return Vector3::new(0.5, 0.5, 1.0);
+                }
+                let x = clamp(u * width - 0.5, 0.0, width - 1.0);
+                let y = clamp((1.0 - v) * height - 0.5, 0.0, height - 1.0);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (dx, dy) = (x - x0, y - y0);
+
+                let w = *width as usize;
+                let (i0, j0) = (x0 as usize, y0 as usize);
+                let i1 = (i0 + 1).min(w - 1);
+                let j1 = (j0 + 1).min(*height as usize - 1);
+
+                let pixel = |i: usize, j: usize| {
+                    let pixel = &image_v[(i + j * w) * 3..(i + j * w) * 3 + 3];
+                    Vector3::new(pixel[0], pixel[1], pixel[2])
+                };
+                (pixel(i0, j0) * (1.0 - dx) + pixel(i1, j0) * dx) * (1.0 - dy)
+                    + (pixel(i0, j1) * (1.0 - dx) + pixel(i1, j1) * dx) * dy
+            }
             Self::Hdri {
                 image_v,
                 width,
@@ -88,14 +188,30 @@ impl Texture {
                 let u = clamp(u, 0.0, 1.0);
                 let v = 1.0 - clamp(v, 0.0, 1.0);
 
+                //Bilinear filtering, wrapping around in u
                 let w = *width as usize;
                 let h = *height as usize;
-                let i = ((u * width) as usize).min(w - 1);
-                let j = ((v * height) as usize).min(h - 1);
+                let x = u * width - 0.5;
+                let y = clamp(v * height - 0.5, 0.0, height - 1.0);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (dx, dy) = (x - x0, y - y0);
 
-                let pixel = image_v[i + j * w];
-                Vector3::new(pixel[0], pixel[1], pixel[2])
+                let i0 = (x0 as i64).rem_euclid(w as i64) as usize;
+                let i1 = (i0 + 1) % w;
+                let j0 = y0 as usize;
+                let j1 = (j0 + 1).min(h - 1);
+
+                let pixel = |i: usize, j: usize| {
+                    let pixel = image_v[i + j * w];
+                    Vector3::new(pixel[0], pixel[1], pixel[2])
+                };
+                (pixel(i0, j0) * (1.0 - dx) + pixel(i1, j0) * dx) * (1.0 - dy)
+                    + (pixel(i0, j1) * (1.0 - dx) + pixel(i1, j1) * dx) * dy
             }
         }
     }
 }
+
+fn read_image(path: &str) -> Result<DynamicImage, String> {
+    image::open(path).map_err(|e| format!("Failed to read image from {:?}: {}", path, e))
+}
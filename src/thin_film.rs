@@ -0,0 +1,192 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::{
+    material::ComplexIor,
+    ray::HitRecord,
+    texture::Texture,
+    utilities::{color::spectrum_rgb, vector3::Vector3},
+};
+
+const PI: f32 = std::f32::consts::PI;
+
+//Thin transparent coating like soap or oil, whose reflections off its two faces interfere. The
+//reflectance of the stack is summed over the visible range into a color, so it changes with the
+//thickness and the viewing angle [15]. Spectral paths take it at their hero wavelength instead.
+//Thicknesses are in um, so image textures span 0 to 1000 nm
+#[derive(Clone)]
+pub struct ThinFilm {
+    thickness: Texture,
+    ior: f32,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: impl Into<Texture>, ior: f32) -> Self {
+        Self {
+            thickness: thickness.into(),
+            ior,
+        }
+    }
+
+    //Thickness at the hit point in nm
+    pub fn thickness(&self, hit: &HitRecord) -> f32 {
+        self.thickness.value(hit.u, hit.v, hit.p).x.max(0.0) * 1000.0
+    }
+
+    //Reflectance of the film over a dielectric, seen from a medium of index outside
+    pub fn dielectric_reflectance(
+        &self,
+        thickness: f32,
+        cos_i: f32,
+        outside: f32,
+        substrate: f32,
+        wavelength: Option<f32>,
+    ) -> Vector3<f32> {
+        Self::spectrum(wavelength, |lambda| {
+            self.reflectance(
+                thickness,
+                cos_i,
+                outside,
+                Complex::new(substrate, 0.0),
+                lambda,
+            )
+        })
+    }
+
+    //Reflectance of the film over a conductor in air. The index of refraction of the conductor is
+    //interpolated between the red, green and blue channels at 650, 550 and 450 nm
+    pub fn conductor_reflectance(
+        &self,
+        thickness: f32,
+        cos_i: f32,
+        substrate: &ComplexIor,
+        wavelength: Option<f32>,
+    ) -> Vector3<f32> {
+        let channel = |lambda: f32, values: Vector3<f32>| {
+            let t = ((650.0 - lambda) / 100.0).clamp(0.0, 2.0);
+            if t < 1.0 {
+                values.x + (values.y - values.x) * t
+            } else {
+                values.y + (values.z - values.y) * (t - 1.0)
+            }
+        };
+        Self::spectrum(wavelength, |lambda| {
+            let substrate =
+                Complex::new(channel(lambda, substrate.eta), channel(lambda, substrate.k));
+            self.reflectance(thickness, cos_i, 1.0, substrate, lambda)
+        })
+    }
+
+    //Reflectance at the hero wavelength in every channel, or its color over the visible range
+    fn spectrum(wavelength: Option<f32>, reflectance: impl Fn(f32) -> f32) -> Vector3<f32> {
+        match wavelength {
+            Some(lambda) => Vector3::new(1.0, 1.0, 1.0) * reflectance(lambda),
+            None => spectrum_rgb(reflectance),
+        }
+    }
+
+    //Airy sum of the waves reflected back and forth inside the film, for each polarization
+    fn reflectance(
+        &self,
+        thickness: f32,
+        cos_i: f32,
+        outside: f32,
+        substrate: Complex,
+        lambda: f32,
+    ) -> f32 {
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        let sin2_i = 1.0 - cos_i * cos_i;
+        let n0 = Complex::new(outside, 0.0);
+        let n1 = Complex::new(self.ior, 0.0);
+        let n2 = substrate;
+        //Snell's law with complex cosines, imaginary beyond the critical angle
+        let cos_in = |n: Complex| {
+            let ratio = n0 / n;
+            (Complex::new(1.0, 0.0) - ratio * ratio * Complex::new(sin2_i, 0.0)).sqrt()
+        };
+        let (c0, c1, c2) = (Complex::new(cos_i, 0.0), cos_in(n1), cos_in(n2));
+
+        let s = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            (na * ca - nb * cb) / (na * ca + nb * cb)
+        };
+        let p = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            (nb * ca - na * cb) / (nb * ca + na * cb)
+        };
+        //Phase difference of a round trip through the film
+        let phase = (Complex::new(0.0, 4.0 * PI * thickness / lambda) * n1 * c1).exp();
+        //Grazing light over a vanishing film leaves both terms at zero, it is all reflected
+        let airy = |r01: Complex, r12: Complex| {
+            let denominator = Complex::new(1.0, 0.0) + r01 * r12 * phase;
+            if denominator.norm2() == 0.0 {
+                return 1.0;
+            }
+            ((r01 + r12 * phase) / denominator).norm2()
+        };
+        let r_s = airy(s(n0, c0, n1, c1), s(n1, c1, n2, c2));
+        let r_p = airy(p(n0, c0, n1, c1), p(n1, c1, n2, c2));
+        ((r_s + r_p) / 2.0).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    fn norm2(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    //Principal square root, with a non-negative real part
+    fn sqrt(self) -> Self {
+        let modulus = self.norm2().sqrt();
+        let re = ((modulus + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((modulus - self.re) / 2.0).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Self {
+        let scale = self.re.exp();
+        Self::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, other: Self) -> Self {
+        let denominator = other.norm2();
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
@@ -7,7 +7,7 @@ use crate::{
 
 #[derive(Clone)]
 pub struct Translate {
-    object: Box<Object>,
+    pub object: Box<Object>,
     offset: Vector3<f32>,
     bounding_box: AABB,
 }
@@ -42,7 +42,7 @@ impl Hittable for Translate {
 
 #[derive(Clone)]
 pub struct RotateY {
-    object: Box<Object>,
+    pub object: Box<Object>,
     sin_theta: f32,
     cos_theta: f32,
     bounding_box: AABB,
@@ -94,6 +94,8 @@ impl Hittable for RotateY {
         if let Some(mut hit) = self.object.hit(&rotated_r, t_min, t_max) {
             hit.p = rot(hit.p, self.sin_theta, self.cos_theta);
             hit.normal = rot(hit.normal, self.sin_theta, self.cos_theta);
+            hit.tangent = rot(hit.tangent, self.sin_theta, self.cos_theta);
+            hit.bitangent = rot(hit.bitangent, self.sin_theta, self.cos_theta);
             return Some(hit);
         }
 
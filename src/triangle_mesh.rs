@@ -1,10 +1,17 @@
-use std::mem;
+use std::{mem, path::Path, sync::Arc};
+
+use image::DynamicImage;
 
 use crate::{
     aabb::AABB,
     material::Material,
+    medium::Medium,
+    normal_map::NormalMap,
     object::{Hittable, Object},
+    orientation::Orientation,
+    principled::Principled,
     ray::HitRecord,
+    texture::Texture,
     utilities::{math::Point2D, vector3::Vector3},
 };
 
@@ -14,6 +21,10 @@ pub struct Triangle {
     normal0: Vector3<f32>,
     normal1: Vector3<f32>,
     normal2: Vector3<f32>,
+    tangent0: Vector3<f32>,
+    tangent1: Vector3<f32>,
+    tangent2: Vector3<f32>,
+    handedness: f32,
     tex0: Point2D<f32>,
     tex1: Point2D<f32>,
     tex2: Point2D<f32>,
@@ -44,6 +55,10 @@ impl Triangle {
             normal0: Vector3::new(0.0, 0.0, 0.0),
             normal1: Vector3::new(0.0, 0.0, 0.0),
             normal2: Vector3::new(0.0, 0.0, 0.0),
+            tangent0: Vector3::new(0.0, 0.0, 0.0),
+            tangent1: Vector3::new(0.0, 0.0, 0.0),
+            tangent2: Vector3::new(0.0, 0.0, 0.0),
+            handedness: 1.0,
             tex0,
             tex1,
             tex2,
@@ -68,6 +83,30 @@ impl Triangle {
         self.normal1 = normal1;
         self.normal2 = normal2;
     }
+
+    //Tangents along u at the three corners, the bitangents along v follow from the handedness
+    pub fn set_tangents(
+        &mut self,
+        tangent0: Vector3<f32>,
+        tangent1: Vector3<f32>,
+        tangent2: Vector3<f32>,
+        handedness: f32,
+    ) {
+        self.tangent0 = tangent0;
+        self.tangent1 = tangent1;
+        self.tangent2 = tangent2;
+        self.handedness = handedness;
+    }
+
+    //Normal of the plane of the triangle at its three corners
+    pub fn set_face_normal(&mut self) {
+        let normal = Vector3::cross(
+            Vector3::new(self.a, self.b, self.c),
+            Vector3::new(self.d, self.e, self.f),
+        )
+        .norm();
+        self.set_normals(normal, normal, normal);
+    }
 }
 
 impl Hittable for Triangle {
@@ -105,15 +144,20 @@ impl Hittable for Triangle {
             let normal =
                 self.normal0 * (1.0 - beta - gamma) + self.normal1 * beta + self.normal2 * gamma;
             let texcoord = self.tex0 * (1.0 - beta - gamma) + self.tex1 * beta + self.tex2 * gamma;
-            Some(HitRecord::new(
-                r.at(t),
-                normal,
-                t,
-                texcoord.x,
-                texcoord.y,
-                r,
-                &self.material,
-            ))
+            let tangent =
+                self.tangent0 * (1.0 - beta - gamma) + self.tangent1 * beta + self.tangent2 * gamma;
+            Some(
+                HitRecord::new(
+                    r.at(t),
+                    normal,
+                    t,
+                    texcoord.x,
+                    texcoord.y,
+                    r,
+                    &self.material,
+                )
+                .with_tangent(tangent, self.handedness),
+            )
         } else {
             None
         }
@@ -137,6 +181,83 @@ impl TriangleMesh {
         axis: u8,
         material: Material,
     ) -> TriangleMesh {
+        let (models, _) = Self::read(filename);
+        Self::from_models(&models, scale, offset, rotation_angle, axis, |_| {
+            material.clone()
+        })
+    }
+
+    //Each model gets its material from the .mtl library of the file, as a principled material
+    pub fn load_mtl(
+        filename: &str,
+        scale: f32,
+        offset: Vector3<f32>,
+        rotation_angle: f32,
+        axis: u8,
+    ) -> TriangleMesh {
+        let (models, materials) = Self::read(filename);
+        let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+        let materials: Vec<Material> = match materials {
+            Ok(materials) => materials
+                .iter()
+                .map(|material| principled_from_mtl(material, directory))
+                .collect(),
+            Err(e) => {
+                println!("Failed to load the materials of {:?}: {}", filename, e);
+                vec![]
+            }
+        };
+        Self::from_models(
+            &models,
+            scale,
+            offset,
+            rotation_angle,
+            axis,
+            |material_id| {
+                material_id
+                    .and_then(|id| materials.get(id))
+                    .cloned()
+                    .unwrap_or_default()
+            },
+        )
+    }
+
+    //Meshes of a .gltf or .glb file placed by their nodes, with principled materials made from
+    //their metallic-roughness materials. Buffers and images are read from the binary chunk or
+    //from files next to it, data URIs are not supported
+    pub fn load_gltf(
+        filename: &str,
+        scale: f32,
+        offset: Vector3<f32>,
+        rotation_angle: f32,
+        axis: u8,
+    ) -> Result<TriangleMesh, String> {
+        let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+        let (models, materials) = gltf::Gltf::open(filename)
+            .map_err(|e| e.to_string())
+            .and_then(|gltf| read_gltf(&gltf, directory))
+            .map_err(|e| format!("Failed to read glTF from {:?}: {}", filename, e))?;
+        Ok(Self::from_models(
+            &models,
+            scale,
+            offset,
+            rotation_angle,
+            axis,
+            |material_id| {
+                material_id
+                    .and_then(|id| materials.get(id))
+                    .cloned()
+                    .unwrap_or_default()
+            },
+        ))
+    }
+
+    fn read(
+        filename: &str,
+    ) -> (
+        Vec<tobj::Model>,
+        Result<Vec<tobj::Material>, tobj::LoadError>,
+    ) {
         let object = tobj::load_obj(
             filename,
             &tobj::LoadOptions {
@@ -147,14 +268,25 @@ impl TriangleMesh {
             },
         );
         assert!(object.is_ok());
+        object.expect("Failed to load OBJ file")
+    }
+
+    fn from_models(
+        models: &[tobj::Model],
+        scale: f32,
+        offset: Vector3<f32>,
+        rotation_angle: f32,
+        axis: u8,
+        material_of: impl Fn(Option<usize>) -> Material,
+    ) -> TriangleMesh {
         let mut triangles = vec![];
         let cos = rotation_angle.to_radians().cos();
         let sin = rotation_angle.to_radians().sin();
 
-        let (models, _) = object.expect("Failed to load OBJ file");
         let mut i_t = 0;
         for (m_i, m) in models.iter().enumerate() {
             let mesh = &m.mesh;
+            let material = material_of(mesh.material_id);
             println!(
                 "loading model {}: \'{}\' with {} vertices",
                 m_i,
@@ -162,7 +294,9 @@ impl TriangleMesh {
                 mesh.positions.len() / 3
             );
 
-            let mut v_normal = vec![Vector3::new(0.0, 0.0, 0.0); mesh.indices.len() / 3];
+            let mut v_normal = vec![Vector3::new(0.0, 0.0, 0.0); mesh.positions.len() / 3];
+            let mut v_tangent = vec![Vector3::new(0.0, 0.0, 0.0); mesh.positions.len() / 3];
+            let mut v_bitangent = vec![Vector3::new(0.0, 0.0, 0.0); mesh.positions.len() / 3];
             assert!(mesh.positions.len() % 3 == 0);
             for i in 0..mesh.indices.len() / 3 {
                 let ind0 = mesh.indices[3 * i] as usize;
@@ -212,6 +346,28 @@ impl TriangleMesh {
                     )
                 };
 
+                //Directions of increasing u and v across the face, added to its corners weighted
+                //by their angles like MikkTSpace [19]
+                if !mesh.texcoords.is_empty() {
+                    let (e1, e2) = (p1 - p0, p2 - p0);
+                    let (du1, dv1) = (tex1.x - tex0.x, tex1.y - tex0.y);
+                    let (du2, dv2) = (tex2.x - tex0.x, tex2.y - tex0.y);
+                    let det = du1 * dv2 - du2 * dv1;
+                    let tangent = (e1 * dv2 - e2 * dv1) / det;
+                    let bitangent = (e2 * du1 - e1 * du2) / det;
+                    if det != 0.0 && tangent.magnitude2() > 0.0 && bitangent.magnitude2() > 0.0 {
+                        for (ind, a, b) in [
+                            (ind0, p1 - p0, p2 - p0),
+                            (ind1, p2 - p1, p0 - p1),
+                            (ind2, p0 - p2, p1 - p2),
+                        ] {
+                            let angle = Vector3::dot(a.norm(), b.norm()).clamp(-1.0, 1.0).acos();
+                            v_tangent[ind] += tangent.norm() * angle;
+                            v_bitangent[ind] += bitangent.norm() * angle;
+                        }
+                    }
+                }
+
                 triangles.push(Object::build_triangle(
                     p0 * scale + offset,
                     p1 * scale + offset,
@@ -227,26 +383,45 @@ impl TriangleMesh {
                 let ind1 = mesh.indices[3 * i + 1] as usize;
                 let ind2 = mesh.indices[3 * i + 2] as usize;
 
-                if mesh.normals.is_empty() {
-                    triangles[i + i_t].set_normals(
-                        v_normal[ind0].norm(),
-                        v_normal[ind1].norm(),
-                        v_normal[ind2].norm(),
-                    )
+                let indices = [ind0, ind1, ind2];
+                let normals = if mesh.normals.is_empty() {
+                    indices.map(|ind| v_normal[ind].norm())
                 } else {
-                    let mut normals = Vec::with_capacity(3);
-                    for ind in [ind0, ind1, ind2].iter() {
-                        let normal_x = mesh.normals[3 * *ind];
-                        let normal_y = mesh.normals[3 * *ind + 1];
-                        let normal_z = mesh.normals[3 * *ind + 2];
-                        normals.push(
-                            Vector3::new(normal_x, normal_y, normal_z).rotate(axis, cos, sin),
-                        );
-                    }
-                    /*dbg!(&normals,v_normal[ind0].norm(),
-                    v_normal[ind1].norm(),
-                    v_normal[ind2].norm(),);*/
-                    triangles[i + i_t].set_normals(normals[0], normals[1], normals[2])
+                    indices.map(|ind| {
+                        let normal_x = mesh.normals[3 * ind];
+                        let normal_y = mesh.normals[3 * ind + 1];
+                        let normal_z = mesh.normals[3 * ind + 2];
+                        Vector3::new(normal_x, normal_y, normal_z).rotate(axis, cos, sin)
+                    })
+                };
+                triangles[i + i_t].set_normals(normals[0], normals[1], normals[2]);
+
+                if !mesh.texcoords.is_empty() {
+                    let tangents = [0, 1, 2].map(|k| {
+                        let tangent = v_tangent[indices[k]];
+                        let normal = normals[k].norm();
+                        let tangent = tangent - normal * Vector3::dot(normal, tangent);
+                        if tangent.magnitude2() > 0.0 {
+                            tangent.norm()
+                        } else {
+                            tangent
+                        }
+                    });
+                    //Mirrored texture coordinates flip the bitangent
+                    let handedness =
+                        if Vector3::dot(Vector3::cross(normals[0], tangents[0]), v_bitangent[ind0])
+                            < 0.0
+                        {
+                            -1.0
+                        } else {
+                            1.0
+                        };
+                    triangles[i + i_t].set_tangents(
+                        tangents[0],
+                        tangents[1],
+                        tangents[2],
+                        handedness,
+                    );
                 }
             }
             i_t += mesh.indices.len() / 3;
@@ -255,6 +430,12 @@ impl TriangleMesh {
         Self { triangles }
     }
 
+    //Sharp edges for faceted models, whose shared vertices would smooth the facets out
+    pub fn flat_shaded(mut self) -> TriangleMesh {
+        self.triangles.iter_mut().for_each(Object::set_face_normal);
+        self
+    }
+
     #[allow(dead_code)]
     pub fn rotate_y(mut self, angle: f32) -> TriangleMesh {
         self.triangles
@@ -274,4 +455,449 @@ impl TriangleMesh {
     pub fn push_to_objects(&mut self, objects: &mut Vec<Object>) {
         objects.extend(mem::take(&mut self.triangles));
     }
+
+    //Uses the closed mesh as the boundary of a medium, all faces share it
+    pub fn push_medium_to_objects(&mut self, objects: &mut Vec<Object>, medium: Medium) {
+        let medium = Arc::new(medium);
+        objects.extend(
+            mem::take(&mut self.triangles)
+                .into_iter()
+                .map(|face| face.with_medium(medium.clone())),
+        );
+    }
+}
+
+//Principled material of an .mtl entry. Besides Kd, map_Kd, Ns, Ni, d, map_d, map_Bump and norm it
+//reads the PBR extension Pr, Pm, Ps, Pc, aniso and anisor, roughness comes from Ns when Pr is
+//missing
+fn principled_from_mtl(material: &tobj::Material, directory: &Path) -> Material {
+    let param = |key: &str| {
+        material
+            .unknown_param
+            .get(key)
+            .and_then(|value| value.trim().parse::<f32>().ok())
+    };
+    //Textures that fail to load are reported and left out, the base color falls back to Kd
+    let texture = |file: &str, load: fn(&str) -> Result<Texture, String>| {
+        load(&directory.join(file).to_string_lossy())
+            .map_err(|e| println!("{}", e))
+            .ok()
+    };
+    let diffuse = material
+        .diffuse
+        .map_or(Vector3::new(0.73, 0.73, 0.73), |kd| {
+            Vector3::from_array(&kd)
+        });
+    let base_color = material
+        .diffuse_texture
+        .as_ref()
+        .and_then(|file| texture(file, Texture::load_texture))
+        .unwrap_or_else(|| diffuse.into());
+    let roughness = param("Pr")
+        .or_else(|| material.shininess.map(|ns| (2.0 / (ns + 2.0)).sqrt()))
+        .unwrap_or(0.5);
+
+    let mut bsdf = Principled::new(base_color)
+        .with_roughness(roughness)
+        .with_metallic(param("Pm").unwrap_or(0.0))
+        .with_sheen(param("Ps").unwrap_or(0.0))
+        .with_clearcoat(param("Pc").unwrap_or(0.0))
+        .with_anisotropic(param("aniso").unwrap_or(0.0))
+        .with_transmission(1.0 - material.dissolve.unwrap_or(1.0));
+    if let Some(ior) = material.optical_density {
+        bsdf = bsdf.with_ior(ior);
+    }
+    let principled = Material::Principled {
+        bsdf: Arc::new(bsdf),
+    };
+    let principled = match param("anisor") {
+        Some(rotation) => {
+            principled.with_orientation(Orientation::along_uv().with_rotation(rotation))
+        }
+        None => principled,
+    };
+
+    //norm is a tangent space normal map. map_Bump and bump are height maps, where -bm gives the
+    //height of white in texels
+    let load = |statement: &str| {
+        let file = statement.split_whitespace().last().unwrap_or_default();
+        texture(file, Texture::load_data)
+    };
+    let norm = material
+        .unknown_param
+        .get("norm")
+        .and_then(|norm| load(norm));
+    let bump = material
+        .normal_texture
+        .as_ref()
+        .and_then(|bump| Some((load(bump)?, bump)));
+    let mapped = match (norm, bump) {
+        (Some(norm), _) => principled.with_normal_map(NormalMap::tangent(norm)),
+        (None, Some((height, bump))) => {
+            let scale = map_option(bump, "-bm").unwrap_or(1.0) * height.texel_size().0;
+            principled.with_normal_map(NormalMap::bump(height, scale))
+        }
+        (None, None) => principled,
+    };
+
+    //map_d masks like the leaves and curtains of Sponza cut the surface out where they are dark
+    match material
+        .dissolve_texture
+        .as_ref()
+        .and_then(|mask| load(mask))
+    {
+        Some(mask) => mapped.with_cutout(mask, 0.5),
+        None => mapped,
+    }
+}
+
+//Triangle primitives of the default scene as .obj models in world space, and the materials they
+//index
+fn read_gltf(
+    gltf: &gltf::Gltf,
+    directory: &Path,
+) -> Result<(Vec<tobj::Model>, Vec<Material>), String> {
+    let buffers = gltf
+        .buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf
+                    .blob
+                    .clone()
+                    .ok_or_else(|| "missing binary chunk".to_string()),
+                gltf::buffer::Source::Uri(uri) => {
+                    std::fs::read(directory.join(uri)).map_err(|e| format!("{}: {}", uri, e))
+                }
+            }?;
+            if data.len() < buffer.length() {
+                return Err(format!("buffer {} is too short", buffer.index()));
+            }
+            Ok(data)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    //Textures that fail to load are reported and left out
+    let images: Vec<Option<DynamicImage>> = gltf
+        .images()
+        .map(|texture| {
+            let decoded = match texture.source() {
+                gltf::image::Source::Uri { uri, .. } => {
+                    image::open(directory.join(uri)).map_err(|e| format!("{}: {}", uri, e))
+                }
+                gltf::image::Source::View { view, .. } => {
+                    let data = &buffers[view.buffer().index()];
+                    image::load_from_memory(&data[view.offset()..view.offset() + view.length()])
+                        .map_err(|e| e.to_string())
+                }
+            };
+            decoded
+                .map_err(|e| println!("Failed to read a glTF texture: {}", e))
+                .ok()
+        })
+        .collect();
+    let materials = gltf
+        .materials()
+        .map(|material| principled_from_gltf(&material, &images))
+        .collect();
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| "no scene".to_string())?;
+    let identity = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    let mut nodes: Vec<_> = scene.nodes().map(|node| (node, identity)).collect();
+    let mut models = vec![];
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = multiply(parent, node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh
+                .primitives()
+                .filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles)
+            {
+                models.push(tobj::Model::new(
+                    read_primitive(&primitive, &buffers, transform)?,
+                    mesh.name().unwrap_or_default().to_string(),
+                ));
+            }
+        }
+    }
+    Ok((models, materials))
+}
+
+//Product of two column-major 4x4 matrices
+fn multiply(a: [[f32; 4]; 4], b: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut product = [[0.0; 4]; 4];
+    for (column, b) in product.iter_mut().zip(b) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[k]).sum();
+        }
+    }
+    product
+}
+
+//Vertices of a primitive transformed to world space, with texture coordinates flipped to the .obj
+//convention of v going up
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    transform: [[f32; 4]; 4],
+) -> Result<tobj::Mesh, String> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let column = |c: usize| Vector3::new(transform[c][0], transform[c][1], transform[c][2]);
+    let (x, y, z) = (column(0), column(1), column(2));
+    //Normals transform with the cofactor matrix, mirroring transforms also flip the winding
+    let mirrored = Vector3::dot(Vector3::cross(x, y), z) < 0.0;
+    let sign = if mirrored { -1.0 } else { 1.0 };
+
+    let positions: Vec<f32> = reader
+        .read_positions()
+        .ok_or_else(|| "primitive without positions".to_string())?
+        .flat_map(|[px, py, pz]| (x * px + y * py + z * pz + column(3)).to_array())
+        .collect();
+    let n = positions.len() / 3;
+    let normals: Vec<f32> = reader.read_normals().map_or(vec![], |normals| {
+        normals
+            .flat_map(|[nx, ny, nz]| {
+                let normal = Vector3::cross(y, z) * nx
+                    + Vector3::cross(z, x) * ny
+                    + Vector3::cross(x, y) * nz;
+                (normal * sign).norm().to_array()
+            })
+            .collect()
+    });
+    let texcoords: Vec<f32> = reader.read_tex_coords(0).map_or(vec![], |texcoords| {
+        texcoords
+            .into_f32()
+            .flat_map(|[u, v]| [u, 1.0 - v])
+            .collect()
+    });
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..n as u32).collect(),
+    };
+    if !indices.len().is_multiple_of(3) || indices.iter().any(|&i| i as usize >= n) {
+        return Err("triangle indices out of range".to_string());
+    }
+    if mirrored {
+        indices.chunks_exact_mut(3).for_each(|face| face.swap(1, 2));
+    }
+
+    Ok(tobj::Mesh {
+        normals: if normals.len() == n * 3 {
+            normals
+        } else {
+            vec![]
+        },
+        texcoords: if texcoords.len() == n * 2 {
+            texcoords
+        } else {
+            vec![]
+        },
+        positions,
+        indices,
+        material_id: primitive.material().index(),
+        ..Default::default()
+    })
+}
+
+//Principled material of a glTF metallic-roughness material, with its normal map, alpha mask,
+//transmission and index of refraction
+fn principled_from_gltf(material: &gltf::Material, images: &[Option<DynamicImage>]) -> Material {
+    let image = |texture: gltf::Texture| images.get(texture.source().index())?.as_ref();
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base_image = pbr
+        .base_color_texture()
+        .and_then(|info| image(info.texture()));
+    let base_color = match base_image {
+        //The factor scales linear colors, the texture is read through the gamma curve
+        Some(texture) => {
+            let factor = [r, g, b].map(|c| c.powf(1.0 / 2.2));
+            let mut rgb = texture.to_rgb32f();
+            for pixel in rgb.pixels_mut() {
+                for (value, factor) in pixel.0.iter_mut().zip(factor) {
+                    *value *= factor;
+                }
+            }
+            Texture::image(rgb.into())
+        }
+        None => Vector3::new(r, g, b).into(),
+    };
+
+    //Roughness is read from the green channel of the texture, metallic from the blue one
+    let metallic_roughness = pbr
+        .metallic_roughness_texture()
+        .and_then(|info| image(info.texture()));
+    let parameter = |c: usize, factor: f32| match metallic_roughness {
+        Some(texture) => channel(texture, c, factor),
+        None => factor.into(),
+    };
+    let mut bsdf = Principled::new(base_color)
+        .with_metallic(parameter(2, pbr.metallic_factor()))
+        .with_roughness(parameter(1, pbr.roughness_factor()))
+        .with_transmission(
+            material
+                .transmission()
+                .map_or(0.0, |transmission| transmission.transmission_factor()),
+        );
+    if let Some(ior) = material.ior() {
+        bsdf = bsdf.with_ior(ior);
+    }
+    let principled = Material::Principled {
+        bsdf: Arc::new(bsdf),
+    };
+    let mapped = match material
+        .normal_texture()
+        .and_then(|normal| image(normal.texture()))
+    {
+        Some(normal) => {
+            principled.with_normal_map(NormalMap::tangent(Texture::data(normal.clone())))
+        }
+        None => principled,
+    };
+
+    //Masked materials are cut out where the alpha of the base color is below the cutoff
+    match material.alpha_mode() {
+        gltf::material::AlphaMode::Mask => {
+            let opacity = match base_image {
+                Some(texture) => channel(texture, 3, alpha),
+                None => alpha.into(),
+            };
+            mapped.with_cutout(opacity, material.alpha_cutoff().unwrap_or(0.5))
+        }
+        _ => mapped,
+    }
+}
+
+//Channel c of an image scaled by factor, as a data texture
+fn channel(image: &DynamicImage, c: usize, factor: f32) -> Texture {
+    let values = image
+        .to_rgba32f()
+        .pixels()
+        .flat_map(|pixel| [pixel[c] * factor; 3])
+        .collect();
+    Texture::Data {
+        image_v: Arc::new(values),
+        width: image.width() as f32,
+        height: image.height() as f32,
+    }
+}
+
+//Value of an option like -bm 0.5 in a texture map statement
+fn map_option(statement: &str, option: &str) -> Option<f32> {
+    let mut words = statement.split_whitespace();
+    words.find(|word| *word == option)?;
+    words.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    //Base color of a principled material, seen head on
+    fn base_color(material: &Material) -> Vector3<f32> {
+        let r = Ray::new(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let hit = HitRecord::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            normal,
+            1.0,
+            0.5,
+            0.5,
+            &r,
+            material,
+        );
+        match material {
+            Material::Principled { bsdf } => bsdf.base_color(&hit),
+            _ => panic!("not a bare principled material"),
+        }
+    }
+
+    #[test]
+    fn mtl_with_missing_textures() {
+        let mtl = "newmtl broken\nKd 0.2 0.4 0.6\nPr nonsense\nmap_Kd missing.png\n\
+                   norm missing_normal.png\nmap_Bump missing_height.png\nmap_d missing_mask.png\n";
+        let (materials, _) = tobj::load_mtl_buf(&mut mtl.as_bytes()).unwrap();
+        //Missing maps are left out and the base color falls back to Kd
+        let material = principled_from_mtl(&materials[0], Path::new("missing"));
+        assert_eq!(base_color(&material), Vector3::new(0.2, 0.4, 0.6));
+    }
+
+    //Binary glTF with a single triangle, its node transform and vertex indices
+    fn glb(node: &str, indices: [u16; 3]) -> Vec<u8> {
+        let json = format!(
+            r#"{{"asset":{{"version":"2.0"}},"scene":0,"scenes":[{{"nodes":[0]}}],
+            "nodes":[{{"mesh":0,{}}}],
+            "meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1}},"indices":2,"material":0}}]}}],
+            "materials":[{{"pbrMetallicRoughness":{{"baseColorFactor":[0.2,0.4,0.6,1.0]}}}}],
+            "buffers":[{{"byteLength":78}}],
+            "bufferViews":[{{"buffer":0,"byteLength":72}},{{"buffer":0,"byteOffset":72,"byteLength":6}}],
+            "accessors":[
+                {{"bufferView":0,"componentType":5126,"count":3,"type":"VEC3","min":[0,0,0],"max":[1,1,0]}},
+                {{"bufferView":0,"byteOffset":36,"componentType":5126,"count":3,"type":"VEC3"}},
+                {{"bufferView":1,"componentType":5123,"count":3,"type":"SCALAR"}}]}}"#,
+            node
+        );
+        let positions = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let normals = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0f32];
+        let mut bin: Vec<u8> = positions
+            .iter()
+            .chain(&normals)
+            .flat_map(|x: &f32| x.to_le_bytes())
+            .chain(indices.iter().flat_map(|i| i.to_le_bytes()))
+            .collect();
+        let mut json = json.into_bytes();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        bin.resize(bin.len().div_ceil(4) * 4, 0);
+
+        let mut bytes = b"glTF".to_vec();
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        for x in [2, length as u32, json.len() as u32, 0x4E4F534A] {
+            bytes.extend(x.to_le_bytes());
+        }
+        bytes.extend(json);
+        for x in [bin.len() as u32, 0x004E4942] {
+            bytes.extend(x.to_le_bytes());
+        }
+        bytes.extend(bin);
+        bytes
+    }
+
+    fn read(glb: &[u8]) -> Result<(Vec<tobj::Model>, Vec<Material>), String> {
+        let gltf = gltf::Gltf::from_slice(glb).map_err(|e| e.to_string())?;
+        read_gltf(&gltf, Path::new(""))
+    }
+
+    #[test]
+    fn gltf_triangle() {
+        let (models, materials) = read(&glb(r#""translation":[0,1,0]"#, [0, 1, 2])).unwrap();
+        let mesh = &models[0].mesh;
+        assert_eq!(mesh.positions[3..6], [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.material_id, Some(0));
+        assert_eq!(base_color(&materials[0]), Vector3::new(0.2, 0.4, 0.6));
+
+        //Mirroring flips the winding and keeps the normals pointing out
+        let (models, _) = read(&glb(r#""scale":[-1,1,1]"#, [0, 1, 2])).unwrap();
+        let mesh = &models[0].mesh;
+        assert_eq!(mesh.positions[3..6], [-1.0, 0.0, 0.0]);
+        assert_eq!(mesh.indices, [0, 2, 1]);
+        assert_eq!(mesh.normals[0..3], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn malformed_gltf() {
+        assert!(read(&glb(r#""translation":[0,1,0]"#, [0, 1, 5])).is_err());
+        let mut truncated = glb(r#""translation":[0,1,0]"#, [0, 1, 2]);
+        truncated.truncate(truncated.len() - 40);
+        assert!(read(&truncated).is_err());
+        assert!(read(b"glTF").is_err());
+    }
 }
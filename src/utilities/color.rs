@@ -0,0 +1,215 @@
+use std::sync::OnceLock;
+
+use crate::utilities::vector3::Vector3;
+
+//Second radiation constant of Planck's law, in um K
+const C2: f32 = 14388.0;
+//Visible range sampled by spectral paths, in nm
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+//Spectra of Smits' RGB to spectrum conversion [11], in 10 bins from 380 to 720 nm
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+//CIE 1931 color matching functions at wavelength lambda in nm, multi-lobe gaussian fit of Wyman
+//et al.
+pub fn cie_xyz(lambda: f32) -> Vector3<f32> {
+    let g = |mu: f32, sigma_below: f32, sigma_above: f32| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vector3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+//CIE XYZ to linear sRGB
+pub fn xyz_to_rgb(xyz: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+//Integral of the color matching functions over the visible range
+fn cie_integral() -> Vector3<f32> {
+    static INTEGRAL: OnceLock<Vector3<f32>> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| {
+        (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).fold(Vector3::new(0.0, 0.0, 0.0), |xyz, lambda| {
+            xyz + cie_xyz(lambda as f32)
+        })
+    })
+}
+
+//Linear sRGB of a spectrum given the integral of its product with the color matching functions.
+//It is balanced so that the constant spectrum of value 1 is white, like an RGB albedo of 1
+fn film_rgb(xyz: Vector3<f32>) -> Vector3<f32> {
+    let white = cie_integral();
+    let rgb = xyz_to_rgb(xyz / white.y);
+    let balance = xyz_to_rgb(white / white.y);
+    Vector3::new(rgb.x / balance.x, rgb.y / balance.y, rgb.z / balance.z)
+}
+
+//Color of a reflectance spectrum given at wavelengths in nm, clipped to the sRGB gamut. A constant
+//reflectance of 1 is white
+pub fn spectrum_rgb(reflectance: impl Fn(f32) -> f32) -> Vector3<f32> {
+    let integrate = |reflectance: &dyn Fn(f32) -> f32| {
+        (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).step_by(10).fold(
+            Vector3::new(0.0, 0.0, 0.0),
+            |xyz, lambda| {
+                let lambda = lambda as f32;
+                xyz + cie_xyz(lambda) * (reflectance(lambda) * 10.0)
+            },
+        )
+    };
+    //Corrects the coarser steps so that white stays white
+    static BALANCE: OnceLock<Vector3<f32>> = OnceLock::new();
+    let balance = *BALANCE.get_or_init(|| {
+        let (white, coarse) = (cie_integral(), integrate(&|_| 1.0));
+        Vector3::new(white.x / coarse.x, white.y / coarse.y, white.z / coarse.z)
+    });
+    let rgb = film_rgb(integrate(&reflectance) * balance);
+    Vector3::new(
+        rgb.x.clamp(0.0, 1.0),
+        rgb.y.clamp(0.0, 1.0),
+        rgb.z.clamp(0.0, 1.0),
+    )
+}
+
+//Relative spectral radiance of a blackbody
+fn planck(lambda: f32, kelvin: f32) -> f32 {
+    let lambda = lambda * 1e-3;
+    1.0 / (lambda.powi(5) * ((C2 / (lambda * kelvin)).exp() - 1.0))
+}
+
+//Color of a blackbody at kelvin degrees with luminance 1, and the factor that takes its relative
+//spectral radiance to that luminance. Colors out of the sRGB gamut are clipped
+pub fn blackbody_with_scale(kelvin: f32) -> (Vector3<f32>, f32) {
+    let xyz = (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).step_by(5).fold(
+        Vector3::new(0.0, 0.0, 0.0),
+        |xyz, lambda| {
+            let lambda = lambda as f32;
+            xyz + cie_xyz(lambda) * (planck(lambda, kelvin) * 5.0)
+        },
+    );
+    let rgb = film_rgb(xyz);
+    let luminance = rgb.luminance();
+    if !(luminance.is_finite() && luminance > 0.0) {
+        return (Vector3::new(0.0, 0.0, 0.0), 0.0);
+    }
+    (
+        (rgb / luminance).max(Vector3::new(0.0, 0.0, 0.0)),
+        1.0 / luminance,
+    )
+}
+
+pub fn blackbody(kelvin: f32) -> Vector3<f32> {
+    blackbody_with_scale(kelvin).0
+}
+
+//Wavelengths in nm carried by a spectral path, evenly spread over the visible range from a
+//uniform hero wavelength [10]. Radiance along the path is a Vector3 with a value for each of them
+#[derive(Clone, Copy)]
+pub struct Wavelengths {
+    lambda: Vector3<f32>,
+}
+
+impl Wavelengths {
+    pub fn sample(u: f32) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let rotate = |i: f32| LAMBDA_MIN + (u + i / 3.0).fract() * range;
+        Self {
+            lambda: Vector3::new(rotate(0.0), rotate(1.0), rotate(2.0)),
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda.x
+    }
+
+    //Smits' upsampling of an RGB reflectance or radiance, it scales with the RGB value
+    pub fn upsample(&self, rgb: Vector3<f32>) -> Vector3<f32> {
+        let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+        let spectrum = |lambda: f32| {
+            let bin = (((lambda - LAMBDA_MIN) / 34.0) as usize).min(9);
+            if r <= g && r <= b {
+                r * SMITS_WHITE[bin]
+                    + if g <= b {
+                        (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+                    } else {
+                        (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+                    }
+            } else if g <= r && g <= b {
+                g * SMITS_WHITE[bin]
+                    + if r <= b {
+                        (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+                    } else {
+                        (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+                    }
+            } else {
+                b * SMITS_WHITE[bin]
+                    + if r <= g {
+                        (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+                    } else {
+                        (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+                    }
+            }
+        };
+        Vector3::new(
+            spectrum(self.lambda.x),
+            spectrum(self.lambda.y),
+            spectrum(self.lambda.z),
+        )
+    }
+
+    //Relative spectral radiance of a blackbody, see blackbody_with_scale
+    pub fn planck(&self, kelvin: f32) -> Vector3<f32> {
+        Vector3::new(
+            planck(self.lambda.x, kelvin),
+            planck(self.lambda.y, kelvin),
+            planck(self.lambda.z, kelvin),
+        )
+    }
+
+    //Only the hero wavelength goes on after an event that sends each wavelength elsewhere
+    pub fn terminate_secondary(&self, values: Vector3<f32>) -> Vector3<f32> {
+        Vector3::new(values.x * 3.0, 0.0, 0.0)
+    }
+
+    //Film color estimated from the radiance at each wavelength
+    pub fn to_rgb(self, values: Vector3<f32>) -> Vector3<f32> {
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let xyz = cie_xyz(self.lambda.x) * values.x
+            + cie_xyz(self.lambda.y) * values.y
+            + cie_xyz(self.lambda.z) * values.z;
+        film_rgb(xyz / (3.0 * pdf))
+    }
+}
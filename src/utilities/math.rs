@@ -1,5 +1,7 @@
 use num::Num;
+use rand::{rngs::SmallRng, SeedableRng};
 use std::ops::{Add, Div, DivAssign, Mul, MulAssign};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 #[inline(always)]
 pub fn fmin(a: f32, b: f32) -> f32 {
@@ -18,6 +20,39 @@ pub fn fmax(a: f32, b: f32) -> f32 {
     }
 }
 
+//f32 stored as bits, for accumulators shared between threads
+#[inline(always)]
+pub fn atomic_add(a: &AtomicU32, value: f32) {
+    let _ = a.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((f32::from_bits(bits) + value).to_bits())
+    });
+}
+#[inline(always)]
+pub fn atomic_load(a: &AtomicU32) -> f32 {
+    f32::from_bits(a.load(Ordering::Relaxed))
+}
+
+//Independent random stream for each index when seeded, renders are then reproducible
+pub fn seeded_rng(seed: Option<u64>, stream: u64) -> SmallRng {
+    match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15)),
+        None => SmallRng::from_rng(rand::thread_rng()).unwrap(),
+    }
+}
+
+//Uniform number in [0, 1) that only depends on the given values, for choices made where no
+//random generator is at hand, like whether a ray goes through a partly transparent surface
+pub fn hash_float(values: &[f32]) -> f32 {
+    let hash = values.iter().fold(0u64, |hash, value| {
+        //Mixing steps of splitmix64
+        let mut x = (hash ^ value.to_bits() as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^ (x >> 31)
+    });
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
 #[derive(Copy, Clone)]
 pub enum Axis {
     X = 0,
@@ -1,5 +1,7 @@
+pub mod color;
 pub mod draw_sample;
 pub mod input;
 pub mod math;
+pub mod noise;
 pub mod onb;
 pub mod vector3;
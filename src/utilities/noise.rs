@@ -0,0 +1,82 @@
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::utilities::vector3::Vector3;
+
+const POINT_COUNT: usize = 256;
+
+//Perlin gradient noise, the same seed always gives the same field
+#[derive(Clone)]
+pub struct Perlin {
+    gradients: Vec<Vector3<f32>>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                Vector3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                )
+                .norm()
+            })
+            .collect();
+        let mut permutation = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+        let perm = [permutation(), permutation(), permutation()];
+        Self { gradients, perm }
+    }
+
+    //Roughly in [-1, 1]
+    pub fn noise(&self, p: Vector3<f32>) -> f32 {
+        let floor = [p.x.floor(), p.y.floor(), p.z.floor()];
+        let frac = [p.x - floor[0], p.y - floor[1], p.z - floor[2]];
+        let cell = floor.map(|c| c as i64);
+
+        let smooth = frac.map(|f| f * f * (3.0 - 2.0 * f));
+        let mut acc = 0.0;
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let gradient = self.gradients[self.perm[0]
+                        [((cell[0] + i as i64) & 255) as usize]
+                        ^ self.perm[1][((cell[1] + j as i64) & 255) as usize]
+                        ^ self.perm[2][((cell[2] + k as i64) & 255) as usize]];
+                    let weight =
+                        Vector3::new(frac[0] - i as f32, frac[1] - j as f32, frac[2] - k as f32);
+                    let blend = |s: f32, corner: usize| {
+                        if corner == 1 {
+                            s
+                        } else {
+                            1.0 - s
+                        }
+                    };
+                    acc += blend(smooth[0], i)
+                        * blend(smooth[1], j)
+                        * blend(smooth[2], k)
+                        * Vector3::dot(gradient, weight);
+                }
+            }
+        }
+        acc
+    }
+
+    //Fractal sum of octaves, each at twice the frequency and half the amplitude
+    pub fn fbm(&self, p: Vector3<f32>, octaves: u32) -> f32 {
+        let mut acc = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            acc += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        acc
+    }
+}
@@ -1,5 +1,6 @@
 use crate::Vector3;
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
 pub struct ONB {
     pub u: Vector3<f32>,
     pub v: Vector3<f32>,
@@ -19,7 +20,46 @@ impl ONB {
         let v = Vector3::cross(w, u);
         Self { u, v, w }
     }
+    //Frame around n with u along the tangent, made orthogonal to n. Tangents along n or missing
+    //fall back to the frame of build_from
+    pub fn from_tangent(n: Vector3<f32>, tangent: Vector3<f32>) -> Self {
+        let w = n.norm();
+        let u = tangent - w * Vector3::dot(w, tangent);
+        if u.magnitude2() < 1e-8 {
+            return Self::build_from(n);
+        }
+        let u = u.norm();
+        let v = Vector3::cross(w, u);
+        Self { u, v, w }
+    }
+    //Rotation by Euler angles in degrees, applied around y, then x, then z. local() maps
+    //rotated directions back to world space and to_local() rotates world directions
+    pub fn from_euler(angles: Vector3<f32>) -> Self {
+        let rotate = |p: Vector3<f32>| {
+            [(1, angles.y), (0, angles.x), (2, angles.z)]
+                .iter()
+                .fold(p, |p, &(axis, angle)| {
+                    let radians = angle.to_radians();
+                    p.rotate(axis, radians.cos(), radians.sin())
+                })
+        };
+        let x = rotate(Vector3::new(1.0, 0.0, 0.0));
+        let y = rotate(Vector3::new(0.0, 1.0, 0.0));
+        let z = rotate(Vector3::new(0.0, 0.0, 1.0));
+        Self {
+            u: Vector3::new(x.x, y.x, z.x),
+            v: Vector3::new(x.y, y.y, z.y),
+            w: Vector3::new(x.z, y.z, z.z),
+        }
+    }
     pub fn local(&self, a: Vector3<f32>) -> Vector3<f32> {
         self.u * a.x + self.v * a.y + self.w * a.z
     }
+    pub fn to_local(self, a: Vector3<f32>) -> Vector3<f32> {
+        Vector3::new(
+            Vector3::dot(a, self.u),
+            Vector3::dot(a, self.v),
+            Vector3::dot(a, self.w),
+        )
+    }
 }
@@ -2,7 +2,7 @@
 
 use super::math::{fmax, fmin, Axis};
 use num::{Float, Num};
-use rand::{prelude::ThreadRng, Rng};
+use rand::Rng;
 use std::{
     borrow::Borrow,
     f32::consts::PI,
@@ -158,7 +158,7 @@ impl Vector3<f32> {
     }
 
     #[inline(always)]
-    pub fn random_vec(min: f32, max: f32, rng: &mut ThreadRng) -> Self {
+    pub fn random_vec(min: f32, max: f32, rng: &mut impl Rng) -> Self {
         Self::new(
             rng.gen_range(min..max),
             rng.gen_range(min..max),
@@ -170,7 +170,7 @@ impl Vector3<f32> {
         0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
     }
     #[inline(always)]
-    pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Self {
+    pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Self {
         loop {
             let p = Vector3::random_vec(-1.0, 1.0, rng);
             if p.magnitude2() >= 1.0 {
@@ -180,7 +180,7 @@ impl Vector3<f32> {
         }
     }
     #[inline(always)]
-    pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Self {
+    pub fn random_in_unit_disk(rng: &mut impl Rng) -> Self {
         loop {
             let p = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
             if p.magnitude2() >= 1.0 {
@@ -190,12 +190,12 @@ impl Vector3<f32> {
         }
     }
     #[inline(always)]
-    pub fn random_unit_vector(rng: &mut ThreadRng) -> Self {
+    pub fn random_unit_vector(rng: &mut impl Rng) -> Self {
         let mut v = Vector3::random_in_unit_sphere(rng);
         v.normalize()
     }
     #[inline(always)]
-    pub fn random_in_hemisphere(normal: Vector3<f32>, rng: &mut ThreadRng) -> Self {
+    pub fn random_in_hemisphere(normal: Vector3<f32>, rng: &mut impl Rng) -> Self {
         let v = Vector3::random_in_unit_sphere(rng);
         if Vector3::dot(v, normal) > 0.0 {
             v
@@ -204,7 +204,7 @@ impl Vector3<f32> {
         }
     }
     #[inline(always)]
-    pub fn random_cosine_direction(rng: &mut ThreadRng) -> Self {
+    pub fn random_cosine_direction(rng: &mut impl Rng) -> Self {
         let r1 = rng.gen::<f32>();
         let r2 = rng.gen::<f32>();
         let z = (1.0 - r2).sqrt();
@@ -216,7 +216,7 @@ impl Vector3<f32> {
         Vector3::new(x, y, z)
     }
     #[inline(always)]
-    pub fn random_cosine_direction_exponent(exponent: f32, rng: &mut ThreadRng) -> Self {
+    pub fn random_cosine_direction_exponent(exponent: f32, rng: &mut impl Rng) -> Self {
         let r1 = rng.gen::<f32>();
         let r2 = rng.gen::<f32>().powf(1.0 / (exponent + 1.0));
         let sin_theta = (1.0 - r2 * r2).sqrt();
@@ -229,7 +229,7 @@ impl Vector3<f32> {
     }
     #[inline(always)]
     //The Ashikhmin and Shirley BRDF Model
-    pub fn random_as(nu: f32, nv: f32, rng: &mut ThreadRng) -> Self {
+    pub fn random_as(nu: f32, nv: f32, rng: &mut impl Rng) -> Self {
         let r1 = rng.gen::<f32>();
 
         /*let (r1_corr,correction) = if r1<0.25{
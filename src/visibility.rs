@@ -0,0 +1,101 @@
+use crate::{
+    aabb::AABB,
+    object::{Hittable, Object},
+    ray::{HitRecord, Ray, RayType},
+};
+
+//Light groups are bit masks, an emitter lights a surface if their masks share a bit
+pub const ALL_LIGHT_GROUPS: u32 = u32::MAX;
+
+//Which rays see an object and which lights it takes part in. There are no separate shadow
+//rays, rays scattered by diffuse or glossy surfaces both cast shadows and carry indirect light
+#[derive(Clone, Copy)]
+pub struct RayVisibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub reflection: bool,
+    pub refraction: bool,
+    //Groups an emitter belongs to
+    pub light_group: u32,
+    //Groups allowed to illuminate this object
+    pub lit_by: u32,
+}
+
+impl RayVisibility {
+    pub fn hidden_from_camera() -> Self {
+        Self {
+            camera: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_light_group(self, light_group: u32) -> Self {
+        Self {
+            light_group,
+            ..self
+        }
+    }
+
+    pub fn with_lit_by(self, lit_by: u32) -> Self {
+        Self { lit_by, ..self }
+    }
+
+    #[inline(always)]
+    pub fn visible_to(&self, ray_type: RayType) -> bool {
+        match ray_type {
+            RayType::Camera => self.camera,
+            RayType::Reflection => self.reflection,
+            RayType::Refraction => self.refraction,
+            RayType::Diffuse => self.shadow,
+        }
+    }
+
+    #[inline(always)]
+    pub fn lights(&self, lit_by: u32) -> bool {
+        self.light_group & lit_by != 0
+    }
+}
+
+impl Default for RayVisibility {
+    fn default() -> Self {
+        Self {
+            camera: true,
+            shadow: true,
+            reflection: true,
+            refraction: true,
+            light_group: ALL_LIGHT_GROUPS,
+            lit_by: ALL_LIGHT_GROUPS,
+        }
+    }
+}
+
+//Attaches visibility flags to an object. It has to be the outermost wrapper,
+//SceneBVH only checks the flags of the objects it stores
+#[derive(Clone)]
+pub struct Visible {
+    pub object: Box<Object>,
+    pub visibility: RayVisibility,
+}
+
+impl Visible {
+    pub fn new(object: Object, visibility: RayVisibility) -> Self {
+        Self {
+            object: Box::new(object),
+            visibility,
+        }
+    }
+}
+
+impl Hittable for Visible {
+    #[inline(always)]
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.object.hit(r, t_min, t_max).map(|mut hit| {
+            hit.visibility = self.visibility;
+            hit
+        })
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.object.bounding_box()
+    }
+}